@@ -0,0 +1,253 @@
+use crate::token::Position;
+use std::fmt;
+
+/// The parse errors defined by the HTML standard's tokenizer, followed by the
+/// ones of the tree builder. They display as the standard's error codes, e.g.
+/// `eof-in-tag`.
+///
+/// The standard doesn't name tree construction errors, so those only say
+/// which kind of token was out of place.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    AbruptClosingOfEmptyComment,
+    AbruptDoctypePublicIdentifier,
+    AbruptDoctypeSystemIdentifier,
+    AbsenceOfDigitsInNumericCharacterReference,
+    CdataInHtmlContent,
+    CharacterReferenceOutsideUnicodeRange,
+    ControlCharacterInInputStream,
+    ControlCharacterReference,
+    DuplicateAttribute,
+    EndTagWithAttributes,
+    EndTagWithTrailingSolidus,
+    EofBeforeTagName,
+    EofInCdata,
+    EofInComment,
+    EofInDoctype,
+    EofInScriptHtmlCommentLikeText,
+    EofInTag,
+    IncorrectlyClosedComment,
+    IncorrectlyOpenedComment,
+    InvalidCharacterSequenceAfterDoctypeName,
+    InvalidFirstCharacterOfTagName,
+    MissingAttributeValue,
+    MissingDoctypeName,
+    MissingDoctypePublicIdentifier,
+    MissingDoctypeSystemIdentifier,
+    MissingEndTagName,
+    MissingQuoteBeforeDoctypePublicIdentifier,
+    MissingQuoteBeforeDoctypeSystemIdentifier,
+    MissingSemicolonAfterCharacterReference,
+    MissingWhitespaceAfterDoctypePublicKeyword,
+    MissingWhitespaceAfterDoctypeSystemKeyword,
+    MissingWhitespaceBeforeDoctypeName,
+    MissingWhitespaceBetweenAttributes,
+    MissingWhitespaceBetweenDoctypePublicAndSystemIdentifiers,
+    NestedComment,
+    NoncharacterCharacterReference,
+    NoncharacterInInputStream,
+    NonVoidHtmlElementStartTagWithTrailingSolidus,
+    NullCharacterReference,
+    SurrogateCharacterReference,
+    SurrogateInInputStream,
+    UnexpectedCharacterAfterDoctypeSystemIdentifier,
+    UnexpectedCharacterInAttributeName,
+    UnexpectedCharacterInUnquotedAttributeValue,
+    UnexpectedEqualsSignBeforeAttributeName,
+    UnexpectedNullCharacter,
+    UnexpectedQuestionMarkInsteadOfTagName,
+    UnexpectedSolidusInTag,
+    UnknownNamedCharacterReference,
+
+    MissingDoctype,
+    NonConformingDoctype,
+    UnexpectedDoctype,
+    UnexpectedStartTag,
+    UnexpectedEndTag,
+    UnexpectedCharacter,
+    /// An element was still open when something closed its parent or the
+    /// input ended.
+    MissingEndTag,
+    /// Formatting elements overlap instead of nesting, e.g. `<b><i></b></i>`.
+    MisnestedTag,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::AbruptClosingOfEmptyComment => "abrupt-closing-of-empty-comment",
+            ErrorCode::AbruptDoctypePublicIdentifier => "abrupt-doctype-public-identifier",
+            ErrorCode::AbruptDoctypeSystemIdentifier => "abrupt-doctype-system-identifier",
+            ErrorCode::AbsenceOfDigitsInNumericCharacterReference => {
+                "absence-of-digits-in-numeric-character-reference"
+            }
+            ErrorCode::CdataInHtmlContent => "cdata-in-html-content",
+            ErrorCode::CharacterReferenceOutsideUnicodeRange => {
+                "character-reference-outside-unicode-range"
+            }
+            ErrorCode::ControlCharacterInInputStream => "control-character-in-input-stream",
+            ErrorCode::ControlCharacterReference => "control-character-reference",
+            ErrorCode::DuplicateAttribute => "duplicate-attribute",
+            ErrorCode::EndTagWithAttributes => "end-tag-with-attributes",
+            ErrorCode::EndTagWithTrailingSolidus => "end-tag-with-trailing-solidus",
+            ErrorCode::EofBeforeTagName => "eof-before-tag-name",
+            ErrorCode::EofInCdata => "eof-in-cdata",
+            ErrorCode::EofInComment => "eof-in-comment",
+            ErrorCode::EofInDoctype => "eof-in-doctype",
+            ErrorCode::EofInScriptHtmlCommentLikeText => "eof-in-script-html-comment-like-text",
+            ErrorCode::EofInTag => "eof-in-tag",
+            ErrorCode::IncorrectlyClosedComment => "incorrectly-closed-comment",
+            ErrorCode::IncorrectlyOpenedComment => "incorrectly-opened-comment",
+            ErrorCode::InvalidCharacterSequenceAfterDoctypeName => {
+                "invalid-character-sequence-after-doctype-name"
+            }
+            ErrorCode::InvalidFirstCharacterOfTagName => "invalid-first-character-of-tag-name",
+            ErrorCode::MissingAttributeValue => "missing-attribute-value",
+            ErrorCode::MissingDoctypeName => "missing-doctype-name",
+            ErrorCode::MissingDoctypePublicIdentifier => "missing-doctype-public-identifier",
+            ErrorCode::MissingDoctypeSystemIdentifier => "missing-doctype-system-identifier",
+            ErrorCode::MissingEndTagName => "missing-end-tag-name",
+            ErrorCode::MissingQuoteBeforeDoctypePublicIdentifier => {
+                "missing-quote-before-doctype-public-identifier"
+            }
+            ErrorCode::MissingQuoteBeforeDoctypeSystemIdentifier => {
+                "missing-quote-before-doctype-system-identifier"
+            }
+            ErrorCode::MissingSemicolonAfterCharacterReference => {
+                "missing-semicolon-after-character-reference"
+            }
+            ErrorCode::MissingWhitespaceAfterDoctypePublicKeyword => {
+                "missing-whitespace-after-doctype-public-keyword"
+            }
+            ErrorCode::MissingWhitespaceAfterDoctypeSystemKeyword => {
+                "missing-whitespace-after-doctype-system-keyword"
+            }
+            ErrorCode::MissingWhitespaceBeforeDoctypeName => {
+                "missing-whitespace-before-doctype-name"
+            }
+            ErrorCode::MissingWhitespaceBetweenAttributes => {
+                "missing-whitespace-between-attributes"
+            }
+            ErrorCode::MissingWhitespaceBetweenDoctypePublicAndSystemIdentifiers => {
+                "missing-whitespace-between-doctype-public-and-system-identifiers"
+            }
+            ErrorCode::NestedComment => "nested-comment",
+            ErrorCode::NoncharacterCharacterReference => "noncharacter-character-reference",
+            ErrorCode::NoncharacterInInputStream => "noncharacter-in-input-stream",
+            ErrorCode::NonVoidHtmlElementStartTagWithTrailingSolidus => {
+                "non-void-html-element-start-tag-with-trailing-solidus"
+            }
+            ErrorCode::NullCharacterReference => "null-character-reference",
+            ErrorCode::SurrogateCharacterReference => "surrogate-character-reference",
+            ErrorCode::SurrogateInInputStream => "surrogate-in-input-stream",
+            ErrorCode::UnexpectedCharacterAfterDoctypeSystemIdentifier => {
+                "unexpected-character-after-doctype-system-identifier"
+            }
+            ErrorCode::UnexpectedCharacterInAttributeName => {
+                "unexpected-character-in-attribute-name"
+            }
+            ErrorCode::UnexpectedCharacterInUnquotedAttributeValue => {
+                "unexpected-character-in-unquoted-attribute-value"
+            }
+            ErrorCode::UnexpectedEqualsSignBeforeAttributeName => {
+                "unexpected-equals-sign-before-attribute-name"
+            }
+            ErrorCode::UnexpectedNullCharacter => "unexpected-null-character",
+            ErrorCode::UnexpectedQuestionMarkInsteadOfTagName => {
+                "unexpected-question-mark-instead-of-tag-name"
+            }
+            ErrorCode::UnexpectedSolidusInTag => "unexpected-solidus-in-tag",
+            ErrorCode::UnknownNamedCharacterReference => "unknown-named-character-reference",
+            ErrorCode::MissingDoctype => "missing-doctype",
+            ErrorCode::NonConformingDoctype => "non-conforming-doctype",
+            ErrorCode::UnexpectedDoctype => "unexpected-doctype",
+            ErrorCode::UnexpectedStartTag => "unexpected-start-tag",
+            ErrorCode::UnexpectedEndTag => "unexpected-end-tag",
+            ErrorCode::UnexpectedCharacter => "unexpected-character",
+            ErrorCode::MissingEndTag => "missing-end-tag",
+            ErrorCode::MisnestedTag => "misnested-tag",
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Something malformed in the input. Parsing always recovers from these, they
+/// are only reported so tools can point authors at the problem.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub code: ErrorCode,
+    /// Where the offending character is in the original input.
+    pub position: Position,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at {}:{}",
+            self.code, self.position.line, self.position.column
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Why the DOM refused to change a tree. Those from `ParentCannotHaveChildren`
+/// to `DoctypeAfterDocumentElement` are what the DOM standard reports as a
+/// `HierarchyRequestError`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DomError {
+    /// The reference node isn't a child of the parent.
+    NotFound,
+    /// Only documents, document fragments and elements have children.
+    ParentCannotHaveChildren,
+    /// The node is the parent or one of its ancestors.
+    WouldCreateCycle,
+    /// A document can't be inserted anywhere.
+    CannotInsertDocument,
+    /// Text can't be a child of a document.
+    TextInDocument,
+    /// A doctype can only be a child of a document.
+    DoctypeOutsideDocument,
+    MultipleDocumentElements,
+    MultipleDoctypes,
+    /// The doctype of a document has to come before its element.
+    DoctypeAfterDocumentElement,
+    /// An attribute name or class is empty or has a character it can't have,
+    /// such as whitespace.
+    InvalidCharacter,
+    /// A qualified name's prefix doesn't go with its namespace, e.g. `xml:`
+    /// outside the XML namespace.
+    NamespaceMismatch,
+}
+
+impl DomError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DomError::NotFound => "not-found",
+            DomError::ParentCannotHaveChildren => "parent-cannot-have-children",
+            DomError::WouldCreateCycle => "would-create-cycle",
+            DomError::CannotInsertDocument => "cannot-insert-document",
+            DomError::TextInDocument => "text-in-document",
+            DomError::DoctypeOutsideDocument => "doctype-outside-document",
+            DomError::MultipleDocumentElements => "multiple-document-elements",
+            DomError::MultipleDoctypes => "multiple-doctypes",
+            DomError::DoctypeAfterDocumentElement => "doctype-after-document-element",
+            DomError::InvalidCharacter => "invalid-character",
+            DomError::NamespaceMismatch => "namespace-mismatch",
+        }
+    }
+}
+
+impl fmt::Display for DomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::error::Error for DomError {}
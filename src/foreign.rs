@@ -0,0 +1,168 @@
+//! Tables used to adjust the names of SVG and MathML elements and attributes,
+//! which the tokenizer lowercases.
+
+use crate::node::{Attribute, Namespace};
+
+/// SVG element names that aren't all lowercase.
+static SVG_TAG_NAMES: &[&str] = &[
+    "altGlyph",
+    "altGlyphDef",
+    "altGlyphItem",
+    "animateColor",
+    "animateMotion",
+    "animateTransform",
+    "clipPath",
+    "feBlend",
+    "feColorMatrix",
+    "feComponentTransfer",
+    "feComposite",
+    "feConvolveMatrix",
+    "feDiffuseLighting",
+    "feDisplacementMap",
+    "feDistantLight",
+    "feDropShadow",
+    "feFlood",
+    "feFuncA",
+    "feFuncB",
+    "feFuncG",
+    "feFuncR",
+    "feGaussianBlur",
+    "feImage",
+    "feMerge",
+    "feMergeNode",
+    "feMorphology",
+    "feOffset",
+    "fePointLight",
+    "feSpecularLighting",
+    "feSpotLight",
+    "feTile",
+    "feTurbulence",
+    "foreignObject",
+    "glyphRef",
+    "linearGradient",
+    "radialGradient",
+    "textPath",
+];
+
+/// SVG attribute names that aren't all lowercase.
+static SVG_ATTRIBUTE_NAMES: &[&str] = &[
+    "attributeName",
+    "attributeType",
+    "baseFrequency",
+    "baseProfile",
+    "calcMode",
+    "clipPathUnits",
+    "diffuseConstant",
+    "edgeMode",
+    "filterUnits",
+    "glyphRef",
+    "gradientTransform",
+    "gradientUnits",
+    "kernelMatrix",
+    "kernelUnitLength",
+    "keyPoints",
+    "keySplines",
+    "keyTimes",
+    "lengthAdjust",
+    "limitingConeAngle",
+    "markerHeight",
+    "markerUnits",
+    "markerWidth",
+    "maskContentUnits",
+    "maskUnits",
+    "numOctaves",
+    "pathLength",
+    "patternContentUnits",
+    "patternTransform",
+    "patternUnits",
+    "pointsAtX",
+    "pointsAtY",
+    "pointsAtZ",
+    "preserveAlpha",
+    "preserveAspectRatio",
+    "primitiveUnits",
+    "refX",
+    "refY",
+    "repeatCount",
+    "repeatDur",
+    "requiredExtensions",
+    "requiredFeatures",
+    "specularConstant",
+    "specularExponent",
+    "spreadMethod",
+    "startOffset",
+    "stdDeviation",
+    "stitchTiles",
+    "surfaceScale",
+    "systemLanguage",
+    "tableValues",
+    "targetX",
+    "targetY",
+    "textLength",
+    "viewBox",
+    "viewTarget",
+    "xChannelSelector",
+    "yChannelSelector",
+    "zoomAndPan",
+];
+
+/// Attributes that get a namespace on foreign elements, as the name they are
+/// written with, their prefix, their local name and their namespace.
+static FOREIGN_ATTRIBUTES: &[(&str, Option<&str>, &str, Namespace)] = &[
+    ("xlink:actuate", Some("xlink"), "actuate", Namespace::XLink),
+    ("xlink:arcrole", Some("xlink"), "arcrole", Namespace::XLink),
+    ("xlink:href", Some("xlink"), "href", Namespace::XLink),
+    ("xlink:role", Some("xlink"), "role", Namespace::XLink),
+    ("xlink:show", Some("xlink"), "show", Namespace::XLink),
+    ("xlink:title", Some("xlink"), "title", Namespace::XLink),
+    ("xlink:type", Some("xlink"), "type", Namespace::XLink),
+    ("xml:lang", Some("xml"), "lang", Namespace::XML),
+    ("xml:space", Some("xml"), "space", Namespace::XML),
+    ("xmlns", None, "xmlns", Namespace::XMLNS),
+    ("xmlns:xlink", Some("xmlns"), "xlink", Namespace::XMLNS),
+];
+
+fn find_case_insensitive(table: &'static [&'static str], name: &str) -> Option<&'static str> {
+    table
+        .iter()
+        .find(|entry| entry.eq_ignore_ascii_case(name))
+        .copied()
+}
+
+/// Restores the case of an SVG element name, such as `foreignObject`.
+pub fn adjust_svg_tag_name(tag_name: &mut String) {
+    if let Some(adjusted) = find_case_insensitive(SVG_TAG_NAMES, tag_name) {
+        *tag_name = adjusted.to_string();
+    }
+}
+
+pub fn adjust_svg_attributes(attributes: &mut [Attribute]) {
+    for attribute in attributes {
+        if let Some(adjusted) = find_case_insensitive(SVG_ATTRIBUTE_NAMES, &attribute.local_name) {
+            attribute.local_name = adjusted.to_string();
+        }
+    }
+}
+
+pub fn adjust_mathml_attributes(attributes: &mut [Attribute]) {
+    for attribute in attributes {
+        if attribute.local_name == "definitionurl" {
+            attribute.local_name = "definitionURL".to_string();
+        }
+    }
+}
+
+/// Splits `xlink:`, `xml:` and `xmlns` attributes into a prefix and a local
+/// name in their namespace.
+pub fn adjust_foreign_attributes(attributes: &mut [Attribute]) {
+    for attribute in attributes {
+        let adjusted = FOREIGN_ATTRIBUTES
+            .iter()
+            .find(|&&(name, ..)| name == attribute.local_name);
+        if let Some(&(_, prefix, local_name, namespace)) = adjusted {
+            attribute.prefix = prefix.map(str::to_string);
+            attribute.local_name = local_name.to_string();
+            attribute.namespace = Some(namespace);
+        }
+    }
+}
@@ -0,0 +1,9 @@
+#![allow(clippy::upper_case_acronyms)]
+
+mod entities;
+pub mod error;
+mod foreign;
+pub mod node;
+pub mod parser;
+pub mod token;
+pub mod tokenizer;
@@ -1,13 +1,11 @@
-mod node;
-mod parser;
-mod token;
-mod tokenizer;
-
+use my_html_parser::parser;
 use std::fs;
-use tokenizer::Tokenizer;
 
 fn main() {
     let file = fs::read_to_string("./test.html").unwrap();
-    let mut t = Tokenizer::new(file);
-    t.run();
+    let parser = parser::parse_document(file);
+    print!("{}", parser.document());
+    for error in parser.errors() {
+        eprintln!("{}", error);
+    }
 }
@@ -1,27 +1,693 @@
+use crate::token;
+use index::Indexes;
+use std::fmt;
+use std::ops::Index;
+
+mod attributes;
+mod index;
+mod mutation;
+mod traversal;
+
+pub use attributes::{ClassList, Dataset};
+pub use traversal::{
+    Ancestors, Children, Descendants, FilterResult, FollowingSiblings, NodeIterator,
+    PrecedingSiblings, TreeWalker, WhatToShow,
+};
+
+/// Handle to a node of a [`Document`]. It is only meaningful for the
+/// document that created the node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NodeId(usize);
+
+/// Owns every node of a tree in a single arena, so a whole tree is dropped
+/// at once. Nodes refer to each other by [`NodeId`], and detached nodes stay
+/// allocated until the document is dropped.
+pub struct Document {
+    nodes: Vec<Node>,
+    indexes: Indexes,
+    /// Decided by the parser from the DOCTYPE, or its absence.
+    pub quirks_mode: QuirksMode,
+}
+
+impl Document {
+    /// The document node, or the fragment node of a fragment, which is always
+    /// the first node.
+    pub const ROOT: NodeId = NodeId(0);
+
+    pub fn new() -> Self {
+        Self::with_root(NodeData::Document)
+    }
+
+    /// A tree whose root is a document fragment rather than a document.
+    pub fn new_fragment() -> Self {
+        Self::with_root(NodeData::DocumentFragment)
+    }
+
+    fn with_root(data: NodeData) -> Self {
+        let mut root = Node::new(data);
+        root.connected = true;
+        Self {
+            nodes: vec![root],
+            indexes: Indexes::default(),
+            quirks_mode: QuirksMode::NoQuirks,
+        }
+    }
+
+    fn push(&mut self, node: Node) -> NodeId {
+        self.nodes.push(node);
+        NodeId(self.nodes.len() - 1)
+    }
+
+    /// Mutable access stays inside the crate, so that nothing can relink a
+    /// node or change an element behind the indexes' back.
+    pub(crate) fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        &mut self.nodes[id.0]
+    }
+
+    pub fn create_element(
+        &mut self,
+        local_name: String,
+        namespace: Namespace,
+        attributes: Vec<Attribute>,
+    ) -> NodeId {
+        self.push(Node::new(NodeData::Element(Element {
+            local_name,
+            namespace,
+            attributes,
+            template_contents: None,
+        })))
+    }
+
+    pub fn create_text(&mut self, data: String) -> NodeId {
+        self.push(Node::new(NodeData::Text(data)))
+    }
+
+    pub fn create_comment(&mut self, data: String) -> NodeId {
+        self.push(Node::new(NodeData::Comment(data)))
+    }
+
+    pub fn create_doctype(&mut self, name: String, public_id: String, system_id: String) -> NodeId {
+        self.push(Node::new(NodeData::Doctype(Doctype {
+            name,
+            public_id,
+            system_id,
+        })))
+    }
+
+    pub fn create_document_fragment(&mut self) -> NodeId {
+        self.push(Node::new(NodeData::DocumentFragment))
+    }
+
+    /// The text of a text or comment node, which can be changed freely.
+    pub fn character_data_mut(&mut self, node: NodeId) -> Option<&mut String> {
+        match &mut self.node_mut(node).data {
+            NodeData::Text(data) | NodeData::Comment(data) => Some(data),
+            _ => None,
+        }
+    }
+
+    pub fn parent(&self, node: NodeId) -> Option<NodeId> {
+        self[node].parent
+    }
+
+    pub fn first_child(&self, node: NodeId) -> Option<NodeId> {
+        self[node].first_child
+    }
+
+    pub fn last_child(&self, node: NodeId) -> Option<NodeId> {
+        self[node].last_child
+    }
+
+    pub fn previous_sibling(&self, node: NodeId) -> Option<NodeId> {
+        self[node].previous_sibling
+    }
+
+    pub fn next_sibling(&self, node: NodeId) -> Option<NodeId> {
+        self[node].next_sibling
+    }
+
+    /// Links `child` as the last child of `parent` without checking that the
+    /// result is a valid tree, which the parser already guarantees.
+    pub(crate) fn append(&mut self, parent: NodeId, child: NodeId) {
+        self.insert(parent, child, None);
+    }
+
+    /// Links `child` into `parent` before `reference`, or as its last child
+    /// if there is no reference node. Unchecked like [`Document::append`].
+    pub(crate) fn insert(&mut self, parent: NodeId, child: NodeId, reference: Option<NodeId>) {
+        let was_connected = self[child].connected;
+        self.unlink(child);
+        let previous = match reference {
+            Some(reference) => {
+                debug_assert_eq!(self[reference].parent, Some(parent));
+                self[reference].previous_sibling
+            }
+            None => self[parent].last_child,
+        };
+        self.node_mut(child).parent = Some(parent);
+        self.node_mut(child).previous_sibling = previous;
+        self.node_mut(child).next_sibling = reference;
+        match previous {
+            Some(previous) => self.node_mut(previous).next_sibling = Some(child),
+            None => self.node_mut(parent).first_child = Some(child),
+        }
+        match reference {
+            Some(reference) => self.node_mut(reference).previous_sibling = Some(child),
+            None => self.node_mut(parent).last_child = Some(child),
+        }
+        let connected = self[parent].connected;
+        if connected {
+            self.number_in_tree_order(child);
+        }
+        if connected != was_connected {
+            self.set_connected(child, connected);
+        }
+    }
+
+    /// Unlinks `child` from its parent, if it has one.
+    pub(crate) fn detach(&mut self, child: NodeId) {
+        if self.unlink(child) && self[child].connected {
+            self.set_connected(child, false);
+        }
+    }
+
+    /// Removes the links between `child` and its parent and siblings, and
+    /// returns whether it had a parent.
+    fn unlink(&mut self, child: NodeId) -> bool {
+        let parent = match self.node_mut(child).parent.take() {
+            Some(parent) => parent,
+            None => return false,
+        };
+        let previous = self.node_mut(child).previous_sibling.take();
+        let next = self.node_mut(child).next_sibling.take();
+        match previous {
+            Some(previous) => self.node_mut(previous).next_sibling = next,
+            None => self.node_mut(parent).first_child = next,
+        }
+        match next {
+            Some(next) => self.node_mut(next).previous_sibling = previous,
+            None => self.node_mut(parent).last_child = previous,
+        }
+        true
+    }
+
+    /// Moves all children of `from` to the end of `to`, unchecked.
+    pub(crate) fn move_children(&mut self, from: NodeId, to: NodeId) {
+        if from == to {
+            return;
+        }
+        while let Some(child) = self[from].first_child {
+            self.append(to, child);
+        }
+    }
+
+    fn fmt_node(&self, f: &mut fmt::Formatter<'_>, id: NodeId, depth: usize) -> fmt::Result {
+        let node = &self[id];
+        let indent = "  ".repeat(depth);
+        match &node.data {
+            NodeData::Element(element) => {
+                match element.namespace {
+                    Namespace::HTML => writeln!(f, "| {}<{}>", indent, element.local_name)?,
+                    namespace => writeln!(
+                        f,
+                        "| {}<{} {}>",
+                        indent,
+                        namespace.prefix(),
+                        element.local_name
+                    )?,
+                }
+                let mut attributes: Vec<_> = element
+                    .attributes
+                    .iter()
+                    .map(|attribute| match attribute.namespace {
+                        Some(namespace) => (
+                            format!("{} {}", namespace.prefix(), attribute.local_name),
+                            &attribute.value,
+                        ),
+                        None => (attribute.local_name.clone(), &attribute.value),
+                    })
+                    .collect();
+                attributes.sort();
+                for (name, value) in attributes {
+                    writeln!(f, "| {}  {}=\"{}\"", indent, name, value)?;
+                }
+                if let Some(contents) = element.template_contents {
+                    writeln!(f, "| {}  content", indent)?;
+                    for child in self.children(contents) {
+                        self.fmt_node(f, child, depth + 2)?;
+                    }
+                }
+            }
+            NodeData::Text(data) => writeln!(f, "| {}\"{}\"", indent, data)?,
+            NodeData::Comment(data) => writeln!(f, "| {}<!-- {} -->", indent, data)?,
+            NodeData::Doctype(doctype) => {
+                if doctype.public_id.is_empty() && doctype.system_id.is_empty() {
+                    writeln!(f, "| {}<!DOCTYPE {}>", indent, doctype.name)?;
+                } else {
+                    writeln!(
+                        f,
+                        "| {}<!DOCTYPE {} \"{}\" \"{}\">",
+                        indent, doctype.name, doctype.public_id, doctype.system_id
+                    )?;
+                }
+            }
+            NodeData::Document | NodeData::DocumentFragment => {}
+        }
+        for child in self.children(id) {
+            self.fmt_node(f, child, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+impl Index<NodeId> for Document {
+    type Output = Node;
+
+    fn index(&self, id: NodeId) -> &Node {
+        &self.nodes[id.0]
+    }
+}
+
+impl Default for Document {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Dumps the tree in the format of the html5lib tree construction tests, one
+/// node per line.
+impl fmt::Display for Document {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for child in self.children(Self::ROOT) {
+            self.fmt_node(f, child, 0)?;
+        }
+        Ok(())
+    }
+}
+
+/// A node and its links to the surrounding ones, which only the [`Document`]
+/// may change so they and its indexes stay consistent.
 pub struct Node {
-    ty: NodeType,
-    name: NodeName,
+    parent: Option<NodeId>,
+    first_child: Option<NodeId>,
+    last_child: Option<NodeId>,
+    previous_sibling: Option<NodeId>,
+    next_sibling: Option<NodeId>,
+    /// Whether the node is in the tree of the document's root, which is what
+    /// the indexes cover.
+    connected: bool,
+    pub(crate) data: NodeData,
+}
+
+impl Node {
+    fn new(data: NodeData) -> Self {
+        Self {
+            parent: None,
+            first_child: None,
+            last_child: None,
+            previous_sibling: None,
+            next_sibling: None,
+            connected: false,
+            data,
+        }
+    }
+
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn first_child(&self) -> Option<NodeId> {
+        self.first_child
+    }
+
+    pub fn last_child(&self) -> Option<NodeId> {
+        self.last_child
+    }
+
+    pub fn previous_sibling(&self) -> Option<NodeId> {
+        self.previous_sibling
+    }
+
+    pub fn next_sibling(&self) -> Option<NodeId> {
+        self.next_sibling
+    }
+
+    /// Whether the node is the root or one of its descendants.
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    pub fn node_type(&self) -> NodeType {
+        match self.data {
+            NodeData::Document => NodeType::DocumentNode,
+            NodeData::DocumentFragment => NodeType::DocumentFragmentNode,
+            NodeData::Doctype(_) => NodeType::DocumentTypeNode,
+            NodeData::Element(_) => NodeType::ElementNode,
+            NodeData::Text(_) => NodeType::TextNode,
+            NodeData::Comment(_) => NodeType::CommentNode,
+        }
+    }
+
+    pub fn as_element(&self) -> Option<&Element> {
+        match &self.data {
+            NodeData::Element(element) => Some(element),
+            _ => None,
+        }
+    }
+
+    pub fn data(&self) -> &NodeData {
+        &self.data
+    }
+
+    pub(crate) fn as_element_mut(&mut self) -> Option<&mut Element> {
+        match &mut self.data {
+            NodeData::Element(element) => Some(element),
+            _ => None,
+        }
+    }
+}
+
+/// What a node is, along with what only that kind of node has.
+pub enum NodeData {
+    Document,
+    DocumentFragment,
+    Doctype(Doctype),
+    Element(Element),
+    Text(String),
+    Comment(String),
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum NodeType {
     ElementNode,
     TextNode,
-    CDataSectionNode,
-    ProcessingInstructionNode,
     CommentNode,
     DocumentNode,
     DocumentTypeNode,
     DocumentFragmentNode,
 }
 
-pub enum NodeName {
-    Element,
-    Attr,
-    Text,
-    CDataSection,
-    ProcessingInstruction,
-    Comment,
-    Document,
-    DocumentType,
-    DocumentFragment,
+/// Missing identifiers are empty, as in the DOM.
+#[derive(Debug, Clone, Default)]
+pub struct Doctype {
+    pub name: String,
+    pub public_id: String,
+    pub system_id: String,
+}
+
+/// An element's name can't change, and its attributes only change through
+/// the [`Document`], which keeps its indexes up to date.
+#[derive(Debug, Clone)]
+pub struct Element {
+    pub(crate) local_name: String,
+    pub(crate) namespace: Namespace,
+    /// In source order.
+    pub(crate) attributes: Vec<Attribute>,
+    /// The fragment holding the children of a `<template>` element, which
+    /// aren't children of the element itself.
+    pub(crate) template_contents: Option<NodeId>,
+}
+
+impl Element {
+    pub fn local_name(&self) -> &str {
+        &self.local_name
+    }
+
+    pub fn namespace(&self) -> Namespace {
+        self.namespace
+    }
+
+    pub fn attributes(&self) -> &[Attribute] {
+        &self.attributes
+    }
+
+    pub fn template_contents(&self) -> Option<NodeId> {
+        self.template_contents
+    }
+
+    pub fn is(&self, namespace: Namespace, local_name: &str) -> bool {
+        self.namespace == namespace && self.local_name == local_name
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Attribute {
+    /// Only the `xlink:`, `xml:` and `xmlns` attributes of foreign elements
+    /// have a namespace, and most of them a prefix.
+    pub namespace: Option<Namespace>,
+    pub prefix: Option<String>,
+    pub local_name: String,
+    pub value: String,
+}
+
+impl From<token::Attribute> for Attribute {
+    fn from(attribute: token::Attribute) -> Self {
+        Self {
+            namespace: None,
+            prefix: None,
+            local_name: attribute.name,
+            value: attribute.value,
+        }
+    }
+}
+
+/// How closely a document follows the standards when rendered, which also
+/// changes how `<table>` is parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuirksMode {
+    NoQuirks,
+    Quirks,
+    LimitedQuirks,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Namespace {
+    HTML,
+    MathML,
+    SVG,
+    XLink,
+    XML,
+    XMLNS,
+}
+
+impl Namespace {
+    pub fn url(self) -> &'static str {
+        match self {
+            Namespace::HTML => "http://www.w3.org/1999/xhtml",
+            Namespace::MathML => "http://www.w3.org/1998/Math/MathML",
+            Namespace::SVG => "http://www.w3.org/2000/svg",
+            Namespace::XLink => "http://www.w3.org/1999/xlink",
+            Namespace::XML => "http://www.w3.org/XML/1998/namespace",
+            Namespace::XMLNS => "http://www.w3.org/2000/xmlns/",
+        }
+    }
+
+    /// The conventional prefix, which the tree dump puts in front of names.
+    pub fn prefix(self) -> &'static str {
+        match self {
+            Namespace::HTML => "html",
+            Namespace::MathML => "math",
+            Namespace::SVG => "svg",
+            Namespace::XLink => "xlink",
+            Namespace::XML => "xml",
+            Namespace::XMLNS => "xmlns",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn element(document: &mut Document, name: &str) -> NodeId {
+        document.create_element(name.to_string(), Namespace::HTML, Vec::new())
+    }
+
+    fn attribute(namespace: Option<Namespace>, local_name: &str, value: &str) -> Attribute {
+        Attribute {
+            namespace,
+            prefix: namespace.map(|namespace| namespace.prefix().to_string()),
+            local_name: local_name.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    #[test]
+    fn nodes_have_data_for_their_kind() {
+        let mut document = Document::new();
+        let div = element(&mut document, "div");
+        let text = document.create_text("a".to_string());
+        let comment = document.create_comment("b".to_string());
+        let doctype = document.create_doctype("html".to_string(), String::new(), String::new());
+        let fragment = document.create_document_fragment();
+
+        let node_types: Vec<NodeType> = [Document::ROOT, div, text, comment, doctype, fragment]
+            .iter()
+            .map(|&node| document[node].node_type())
+            .collect();
+        assert_eq!(
+            node_types,
+            [
+                NodeType::DocumentNode,
+                NodeType::ElementNode,
+                NodeType::TextNode,
+                NodeType::CommentNode,
+                NodeType::DocumentTypeNode,
+                NodeType::DocumentFragmentNode
+            ]
+        );
+        assert!(document[div]
+            .as_element()
+            .unwrap()
+            .is(Namespace::HTML, "div"));
+        assert!(!document[div]
+            .as_element()
+            .unwrap()
+            .is(Namespace::SVG, "div"));
+        assert!(document[text].as_element().is_none());
+        assert!(
+            matches!(document[doctype].data(), NodeData::Doctype(doctype) if doctype.name == "html")
+        );
+
+        document.character_data_mut(text).unwrap().push('c');
+        assert!(matches!(document[text].data(), NodeData::Text(data) if data == "ac"));
+        assert!(document.character_data_mut(comment).is_some());
+        assert!(document.character_data_mut(div).is_none());
+        assert!(matches!(
+            Document::new_fragment()[Document::ROOT].data(),
+            NodeData::DocumentFragment
+        ));
+    }
+
+    #[test]
+    fn dumps_trees_in_the_html5lib_format() {
+        let mut document = Document::new();
+        let doctype = document.create_doctype(
+            "html".to_string(),
+            "-//W3C//DTD HTML 4.01//EN".to_string(),
+            String::new(),
+        );
+        let html = element(&mut document, "html");
+        let body = document.create_element(
+            "body".to_string(),
+            Namespace::HTML,
+            vec![attribute(None, "id", "b"), attribute(None, "class", "c")],
+        );
+        let svg = document.create_element(
+            "svg".to_string(),
+            Namespace::SVG,
+            vec![attribute(Some(Namespace::XLink), "href", "#a")],
+        );
+        let template = element(&mut document, "template");
+        let contents = document.create_document_fragment();
+        let text = document.create_text("x\ny".to_string());
+        let comment = document.create_comment(" c ".to_string());
+        document
+            .node_mut(template)
+            .as_element_mut()
+            .unwrap()
+            .template_contents = Some(contents);
+        document.append(Document::ROOT, doctype);
+        document.append(Document::ROOT, html);
+        document.append(html, body);
+        document.append(body, svg);
+        document.append(body, template);
+        document.append(contents, text);
+        document.append(body, comment);
+        assert_eq!(
+            document.to_string(),
+            "| <!DOCTYPE html \"-//W3C//DTD HTML 4.01//EN\" \"\">\n\
+             | <html>\n\
+             |   <body>\n\
+             |     class=\"c\"\n\
+             |     id=\"b\"\n\
+             |     <svg svg>\n\
+             |       xlink href=\"#a\"\n\
+             |     <template>\n\
+             |       content\n\
+             |         \"x\n\
+             y\"\n\
+             |     <!--  c  -->\n"
+        );
+    }
+
+    /// The children of `parent`, checking that every link between them and
+    /// their parent has a matching link back.
+    fn linked_children(document: &Document, parent: NodeId) -> Vec<NodeId> {
+        let mut children = Vec::new();
+        let mut previous = None;
+        let mut next = document.first_child(parent);
+        while let Some(child) = next {
+            assert_eq!(document.parent(child), Some(parent));
+            assert_eq!(document.previous_sibling(child), previous);
+            children.push(child);
+            previous = Some(child);
+            next = document.next_sibling(child);
+        }
+        assert_eq!(document.last_child(parent), previous);
+        children
+    }
+
+    #[test]
+    fn links_children_and_siblings() {
+        let mut document = Document::new();
+        let div = element(&mut document, "div");
+        let [a, b, c, d] = ["a", "b", "c", "d"].map(|name| element(&mut document, name));
+        assert!(linked_children(&document, div).is_empty());
+
+        document.append(div, a);
+        document.append(div, c);
+        document.insert(div, b, Some(c));
+        document.insert(div, d, Some(a));
+        assert_eq!(linked_children(&document, div), [d, a, b, c]);
+
+        document.detach(d);
+        document.detach(b);
+        document.detach(c);
+        assert_eq!(linked_children(&document, div), [a]);
+        assert_eq!(document.parent(b), None);
+        assert_eq!(document.next_sibling(b), None);
+        assert_eq!(document.previous_sibling(b), None);
+        // Detached nodes stay allocated and can be linked again.
+        document.detach(b);
+        document.append(b, c);
+        assert_eq!(linked_children(&document, b), [c]);
+    }
+
+    #[test]
+    fn linking_a_node_unlinks_it_from_its_old_place() {
+        let mut document = Document::new();
+        let [first, second] = ["first", "second"].map(|name| element(&mut document, name));
+        let [a, b, c] = ["a", "b", "c"].map(|name| element(&mut document, name));
+        document.append(first, a);
+        document.append(first, b);
+        document.append(first, c);
+
+        document.append(second, b);
+        assert_eq!(linked_children(&document, first), [a, c]);
+        assert_eq!(linked_children(&document, second), [b]);
+        document.append(first, a);
+        assert_eq!(linked_children(&document, first), [c, a]);
+
+        document.move_children(first, second);
+        assert!(linked_children(&document, first).is_empty());
+        assert_eq!(linked_children(&document, second), [b, c, a]);
+        document.move_children(second, second);
+        assert_eq!(linked_children(&document, second), [b, c, a]);
+    }
+
+    #[test]
+    fn nodes_are_connected_while_under_the_root() {
+        let mut document = Document::new();
+        let html = element(&mut document, "html");
+        let body = element(&mut document, "body");
+        document.append(html, body);
+        assert!(document[Document::ROOT].is_connected());
+        assert!(!document[body].is_connected());
+        document.append(Document::ROOT, html);
+        assert!(document[html].is_connected());
+        assert!(document[body].is_connected());
+        document.detach(html);
+        assert!(!document[body].is_connected());
+    }
 }
@@ -0,0 +1,597 @@
+//! Reading and changing the attributes of elements, following the DOM
+//! standard's name rules: names are matched case-insensitively on HTML
+//! elements, and namespaced attributes are found by namespace and local name.
+//!
+//! Changes go through the [`Document`], like changes to the tree, so it can
+//! keep its indexes of ids and classes up to date.
+
+use super::{Attribute, Document, Element, Namespace, NodeId};
+use crate::error::DomError;
+use std::borrow::Cow;
+
+impl Element {
+    /// The value of the first attribute whose qualified name, such as `href`
+    /// or `xlink:href`, is `name`. The name is lowercased for HTML elements,
+    /// whose attribute names the tokenizer has lowercased too.
+    pub fn get_attribute(&self, name: &str) -> Option<&str> {
+        let index = self.find_attribute(name)?;
+        Some(&self.attributes[index].value)
+    }
+
+    /// The value of the attribute called `local_name` in `namespace`, which
+    /// is `None` for almost every attribute.
+    pub fn get_attribute_ns(&self, namespace: Option<Namespace>, local_name: &str) -> Option<&str> {
+        let index = self.find_attribute_ns(namespace, local_name)?;
+        Some(&self.attributes[index].value)
+    }
+
+    pub fn has_attribute(&self, name: &str) -> bool {
+        self.find_attribute(name).is_some()
+    }
+
+    pub fn has_attribute_ns(&self, namespace: Option<Namespace>, local_name: &str) -> bool {
+        self.find_attribute_ns(namespace, local_name).is_some()
+    }
+
+    /// The `id` attribute, or an empty string without one.
+    pub fn id(&self) -> &str {
+        self.get_attribute_ns(None, "id").unwrap_or("")
+    }
+
+    /// The classes in the `class` attribute, without repeats.
+    pub fn classes(&self) -> Vec<&str> {
+        let mut classes: Vec<&str> = Vec::new();
+        for class in self
+            .get_attribute_ns(None, "class")
+            .unwrap_or("")
+            .split_ascii_whitespace()
+        {
+            if !classes.contains(&class) {
+                classes.push(class);
+            }
+        }
+        classes
+    }
+
+    pub fn has_class(&self, class: &str) -> bool {
+        self.classes().contains(&class)
+    }
+
+    /// The `data-*` attributes as pairs of the camelCase names the DOM's
+    /// `dataset` uses, such as `fooBar` for `data-foo-bar`, and values.
+    pub fn dataset(&self) -> Vec<(String, &str)> {
+        self.attributes
+            .iter()
+            .filter(|attribute| attribute.namespace.is_none())
+            .filter_map(|attribute| {
+                let name = attribute.local_name.strip_prefix("data-")?;
+                if name.bytes().any(|b| b.is_ascii_uppercase()) {
+                    return None;
+                }
+                Some((data_property_name(name), attribute.value.as_str()))
+            })
+            .collect()
+    }
+
+    /// The value of the `data-*` attribute for the camelCase `name`.
+    pub fn data(&self, name: &str) -> Option<&str> {
+        let name = data_attribute_name(name).ok()?;
+        self.get_attribute_ns(None, &name)
+    }
+
+    fn adjust_name<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        if self.namespace == Namespace::HTML {
+            name.to_ascii_lowercase().into()
+        } else {
+            name.into()
+        }
+    }
+
+    fn find_attribute(&self, name: &str) -> Option<usize> {
+        let name = self.adjust_name(name);
+        self.attributes
+            .iter()
+            .position(|attribute| has_qualified_name(attribute, &name))
+    }
+
+    fn find_attribute_ns(&self, namespace: Option<Namespace>, local_name: &str) -> Option<usize> {
+        self.attributes.iter().position(|attribute| {
+            attribute.namespace == namespace && attribute.local_name == local_name
+        })
+    }
+
+    fn set_attribute(&mut self, name: &str, value: String) -> Result<(), DomError> {
+        validate_name(name)?;
+        match self.find_attribute(name) {
+            Some(index) => self.attributes[index].value = value,
+            None => {
+                let local_name = self.adjust_name(name).into_owned();
+                self.attributes.push(Attribute {
+                    namespace: None,
+                    prefix: None,
+                    local_name,
+                    value,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn set_attribute_ns(
+        &mut self,
+        namespace: Option<Namespace>,
+        qualified_name: &str,
+        value: String,
+    ) -> Result<(), DomError> {
+        let (prefix, local_name) = match qualified_name.split_once(':') {
+            Some((prefix, local_name)) => (Some(prefix), local_name),
+            None => (None, qualified_name),
+        };
+        if let Some(prefix) = prefix {
+            validate_name(prefix)?;
+        }
+        validate_name(local_name)?;
+        let is_xmlns = prefix == Some("xmlns") || qualified_name == "xmlns";
+        if (prefix.is_some() && namespace.is_none())
+            || (prefix == Some("xml") && namespace != Some(Namespace::XML))
+            || is_xmlns != (namespace == Some(Namespace::XMLNS))
+        {
+            return Err(DomError::NamespaceMismatch);
+        }
+        match self.find_attribute_ns(namespace, local_name) {
+            Some(index) => self.attributes[index].value = value,
+            None => self.attributes.push(Attribute {
+                namespace,
+                prefix: prefix.map(str::to_string),
+                local_name: local_name.to_string(),
+                value,
+            }),
+        }
+        Ok(())
+    }
+
+    fn remove_attribute(&mut self, name: &str) -> Option<Attribute> {
+        let index = self.find_attribute(name)?;
+        Some(self.attributes.remove(index))
+    }
+
+    fn remove_attribute_ns(
+        &mut self,
+        namespace: Option<Namespace>,
+        local_name: &str,
+    ) -> Option<Attribute> {
+        let index = self.find_attribute_ns(namespace, local_name)?;
+        Some(self.attributes.remove(index))
+    }
+}
+
+impl Document {
+    /// Sets the attribute with the qualified name `name`, adding it in no
+    /// namespace if there isn't one. Panics if `node` isn't an element.
+    pub fn set_attribute(
+        &mut self,
+        node: NodeId,
+        name: &str,
+        value: String,
+    ) -> Result<(), DomError> {
+        self.change_attributes(node, |element| element.set_attribute(name, value))
+    }
+
+    /// Sets the attribute called `local_name` in `namespace`, adding it with
+    /// the prefix of `qualified_name` if there isn't one. Panics if `node`
+    /// isn't an element.
+    pub fn set_attribute_ns(
+        &mut self,
+        node: NodeId,
+        namespace: Option<Namespace>,
+        qualified_name: &str,
+        value: String,
+    ) -> Result<(), DomError> {
+        self.change_attributes(node, |element| {
+            element.set_attribute_ns(namespace, qualified_name, value)
+        })
+    }
+
+    /// Removes the attribute with the qualified name `name` and returns it,
+    /// if there is one. Panics if `node` isn't an element.
+    pub fn remove_attribute(&mut self, node: NodeId, name: &str) -> Option<Attribute> {
+        self.change_attributes(node, |element| element.remove_attribute(name))
+    }
+
+    /// Removes the attribute called `local_name` in `namespace` and returns
+    /// it, if there is one. Panics if `node` isn't an element.
+    pub fn remove_attribute_ns(
+        &mut self,
+        node: NodeId,
+        namespace: Option<Namespace>,
+        local_name: &str,
+    ) -> Option<Attribute> {
+        self.change_attributes(node, |element| {
+            element.remove_attribute_ns(namespace, local_name)
+        })
+    }
+
+    /// Edits the classes of an element. Panics if `node` isn't an element.
+    pub fn class_list(&mut self, node: NodeId) -> ClassList<'_> {
+        self.expect_element(node);
+        ClassList {
+            document: self,
+            element: node,
+        }
+    }
+
+    /// Edits the `data-*` attributes of an element. Panics if `node` isn't
+    /// an element.
+    pub fn dataset(&mut self, node: NodeId) -> Dataset<'_> {
+        self.expect_element(node);
+        Dataset {
+            document: self,
+            element: node,
+        }
+    }
+
+    fn expect_element(&self, node: NodeId) {
+        assert!(
+            self[node].as_element().is_some(),
+            "only elements have attributes"
+        );
+    }
+}
+
+/// The classes of an element as a set, like the DOM's `classList`. Changes
+/// rewrite the `class` attribute with single spaces between classes.
+pub struct ClassList<'a> {
+    document: &'a mut Document,
+    element: NodeId,
+}
+
+impl ClassList<'_> {
+    fn element(&self) -> &Element {
+        self.document[self.element]
+            .as_element()
+            .expect("class lists belong to elements")
+    }
+
+    pub fn contains(&self, class: &str) -> bool {
+        self.element().has_class(class)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.element().classes().into_iter()
+    }
+
+    pub fn add(&mut self, class: &str) -> Result<(), DomError> {
+        validate_class(class)?;
+        let mut classes = self.element().classes();
+        if !classes.contains(&class) {
+            classes.push(class);
+            let value = classes.join(" ");
+            self.set_classes(value);
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, class: &str) -> Result<(), DomError> {
+        validate_class(class)?;
+        let mut classes = self.element().classes();
+        if classes.contains(&class) {
+            classes.retain(|&c| c != class);
+            let value = classes.join(" ");
+            self.set_classes(value);
+        }
+        Ok(())
+    }
+
+    /// Removes `class` if the element has it and adds it otherwise. Returns
+    /// whether the element has it now.
+    pub fn toggle(&mut self, class: &str) -> Result<bool, DomError> {
+        if self.contains(class) {
+            self.remove(class)?;
+            Ok(false)
+        } else {
+            self.add(class)?;
+            Ok(true)
+        }
+    }
+
+    fn set_classes(&mut self, value: String) {
+        self.document
+            .set_attribute(self.element, "class", value)
+            .expect("class is a valid attribute name");
+    }
+}
+
+/// The `data-*` attributes of an element by their camelCase names, like the
+/// DOM's `dataset`.
+pub struct Dataset<'a> {
+    document: &'a mut Document,
+    element: NodeId,
+}
+
+impl Dataset<'_> {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.document[self.element]
+            .as_element()
+            .expect("datasets belong to elements")
+            .data(name)
+    }
+
+    /// Sets `data-foo-bar` for `fooBar`. Fails for names with a dash before
+    /// a lowercase letter, which no attribute name maps to.
+    pub fn set(&mut self, name: &str, value: String) -> Result<(), DomError> {
+        let name = data_attribute_name(name)?;
+        self.document.set_attribute(self.element, &name, value)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Attribute> {
+        let name = data_attribute_name(name).ok()?;
+        self.document.remove_attribute_ns(self.element, None, &name)
+    }
+}
+
+fn has_qualified_name(attribute: &Attribute, name: &str) -> bool {
+    match &attribute.prefix {
+        Some(prefix) => {
+            name.split_once(':') == Some((prefix.as_str(), attribute.local_name.as_str()))
+        }
+        None => name == attribute.local_name,
+    }
+}
+
+/// Attribute names can't be empty, and can't have whitespace or the
+/// characters that end a name in markup.
+fn validate_name(name: &str) -> Result<(), DomError> {
+    let invalid = |c: char| c.is_ascii_whitespace() || matches!(c, '\0' | '/' | '>' | '=');
+    if name.is_empty() || name.contains(invalid) {
+        return Err(DomError::InvalidCharacter);
+    }
+    Ok(())
+}
+
+fn validate_class(class: &str) -> Result<(), DomError> {
+    if class.is_empty() || class.contains(|c: char| c.is_ascii_whitespace()) {
+        return Err(DomError::InvalidCharacter);
+    }
+    Ok(())
+}
+
+/// `foo-bar` to `fooBar`.
+fn data_property_name(name: &str) -> String {
+    let mut property = String::with_capacity(name.len());
+    let mut chars = name.chars().peekable();
+    while let Some(c) = chars.next() {
+        match chars.peek() {
+            Some(next) if c == '-' && next.is_ascii_lowercase() => {
+                property.push(next.to_ascii_uppercase());
+                chars.next();
+            }
+            _ => property.push(c),
+        }
+    }
+    property
+}
+
+/// `fooBar` to `data-foo-bar`.
+fn data_attribute_name(property: &str) -> Result<String, DomError> {
+    let mut chars = property.chars().peekable();
+    let mut name = String::from("data-");
+    while let Some(c) = chars.next() {
+        if c == '-' && chars.peek().is_some_and(char::is_ascii_lowercase) {
+            return Err(DomError::InvalidCharacter);
+        }
+        if c.is_ascii_uppercase() {
+            name.push('-');
+            name.push(c.to_ascii_lowercase());
+        } else {
+            name.push(c);
+        }
+    }
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    /// A `<div>` and an SVG `<a>` parsed from markup, with the div first.
+    fn document() -> (Document, NodeId, NodeId) {
+        let document = parser::parse_document(
+            "<div ID=a Class='x  y x' data-foo-bar=1 data-baz=2 data-=3></div>\
+             <svg viewBox='0 0 1 1'><a xlink:href=#b></a></svg>"
+                .to_string(),
+        )
+        .into_document();
+        let div = document.get_elements_by_tag_name("div")[0];
+        let a = document.get_elements_by_tag_name("a")[0];
+        (document, div, a)
+    }
+
+    fn element_of(document: &Document, node: NodeId) -> &Element {
+        document[node].as_element().unwrap()
+    }
+
+    #[test]
+    fn reads_attributes() {
+        let (document, div, a) = document();
+        let div = element_of(&document, div);
+        assert_eq!(div.get_attribute("ID"), Some("a"));
+        assert_eq!(div.id(), "a");
+        assert_eq!(div.classes(), ["x", "y"]);
+        assert!(div.has_class("y"));
+        assert!(!div.has_class("X"));
+
+        let a = element_of(&document, a);
+        assert_eq!(a.get_attribute("xlink:href"), Some("#b"));
+        assert_eq!(a.get_attribute("href"), None);
+        assert_eq!(
+            a.get_attribute_ns(Some(Namespace::XLink), "href"),
+            Some("#b")
+        );
+        assert!(a.has_attribute_ns(Some(Namespace::XLink), "href"));
+        assert!(!a.has_attribute_ns(None, "href"));
+        assert_eq!(a.id(), "");
+        assert!(a.classes().is_empty());
+
+        // Names are only lowercased on HTML elements.
+        let svg = element_of(&document, document.get_elements_by_tag_name("svg")[0]);
+        assert!(svg.has_attribute("viewBox"));
+        assert!(!svg.has_attribute("viewbox"));
+    }
+
+    #[test]
+    fn sets_and_removes_attributes() {
+        let (mut document, div, _) = document();
+        document
+            .set_attribute(div, "TITLE", "t".to_string())
+            .unwrap();
+        document.set_attribute(div, "id", "b".to_string()).unwrap();
+        let element = element_of(&document, div);
+        assert_eq!(element.get_attribute("title"), Some("t"));
+        assert_eq!(element.attributes()[0].value, "b");
+        assert_eq!(element.attributes().last().unwrap().local_name, "title");
+
+        for name in ["", "a b", "a=b", "a/", "a>"] {
+            assert_eq!(
+                document.set_attribute(div, name, String::new()),
+                Err(DomError::InvalidCharacter),
+                "{:?}",
+                name
+            );
+        }
+
+        let removed = document.remove_attribute(div, "Title").unwrap();
+        assert_eq!(
+            (removed.local_name.as_str(), removed.value.as_str()),
+            ("title", "t")
+        );
+        assert!(document.remove_attribute(div, "title").is_none());
+    }
+
+    #[test]
+    fn sets_namespaced_attributes() {
+        let (mut document, _, a) = document();
+        document
+            .set_attribute_ns(a, Some(Namespace::XLink), "xl:href", "#c".to_string())
+            .unwrap();
+        document
+            .set_attribute_ns(a, Some(Namespace::XML), "xml:lang", "en".to_string())
+            .unwrap();
+        document
+            .set_attribute_ns(a, Some(Namespace::XMLNS), "xmlns", "x".to_string())
+            .unwrap();
+        let element = element_of(&document, a);
+        // The existing attribute keeps its prefix.
+        assert_eq!(element.get_attribute("xlink:href"), Some("#c"));
+        assert_eq!(element.get_attribute("xml:lang"), Some("en"));
+        assert_eq!(
+            element.get_attribute_ns(Some(Namespace::XMLNS), "xmlns"),
+            Some("x")
+        );
+
+        let mismatches: &[(Option<Namespace>, &str)] = &[
+            (None, "xlink:href"),
+            (Some(Namespace::XLink), "xml:lang"),
+            (Some(Namespace::XLink), "xmlns"),
+            (Some(Namespace::XLink), "xmlns:a"),
+            (Some(Namespace::XMLNS), "a"),
+        ];
+        for &(namespace, name) in mismatches {
+            assert_eq!(
+                document.set_attribute_ns(a, namespace, name, String::new()),
+                Err(DomError::NamespaceMismatch),
+                "{}",
+                name
+            );
+        }
+        assert_eq!(
+            document.set_attribute_ns(a, Some(Namespace::XLink), "xlink:", String::new()),
+            Err(DomError::InvalidCharacter)
+        );
+
+        let removed = document.remove_attribute_ns(a, Some(Namespace::XLink), "href");
+        assert_eq!(removed.unwrap().prefix.as_deref(), Some("xlink"));
+        assert!(!element_of(&document, a).has_attribute("xlink:href"));
+    }
+
+    #[test]
+    fn edits_class_lists() {
+        let (mut document, div, _) = document();
+        let mut classes = document.class_list(div);
+        assert!(classes.contains("x"));
+        assert_eq!(classes.iter().collect::<Vec<_>>(), ["x", "y"]);
+        classes.add("z").unwrap();
+        classes.add("x").unwrap();
+        classes.remove("y").unwrap();
+        classes.remove("w").unwrap();
+        assert_eq!(classes.toggle("x"), Ok(false));
+        assert_eq!(classes.toggle("w"), Ok(true));
+        assert_eq!(classes.add(""), Err(DomError::InvalidCharacter));
+        assert_eq!(classes.remove("a b"), Err(DomError::InvalidCharacter));
+        assert_eq!(classes.toggle("a\tb"), Err(DomError::InvalidCharacter));
+        assert_eq!(
+            element_of(&document, div).get_attribute("class"),
+            Some("z w")
+        );
+    }
+
+    #[test]
+    fn reads_and_edits_datasets() {
+        let (mut document, div, _) = document();
+        document
+            .set_attribute_ns(div, None, "data-Upper", "4".to_string())
+            .unwrap();
+        let element = element_of(&document, div);
+        // Names with uppercase letters have no property name.
+        assert_eq!(
+            element.dataset(),
+            [
+                ("fooBar".to_string(), "1"),
+                ("baz".to_string(), "2"),
+                (String::new(), "3")
+            ]
+        );
+        assert_eq!(element.data("fooBar"), Some("1"));
+        assert_eq!(element.data("foo-bar"), None);
+
+        let mut dataset = document.dataset(div);
+        dataset.set("newValue", "5".to_string()).unwrap();
+        dataset.set("baz", "6".to_string()).unwrap();
+        assert_eq!(
+            dataset.set("a-b", String::new()),
+            Err(DomError::InvalidCharacter)
+        );
+        assert_eq!(dataset.get("newValue"), Some("5"));
+        assert_eq!(dataset.remove("fooBar").unwrap().local_name, "data-foo-bar");
+        assert!(dataset.remove("fooBar").is_none());
+        let element = element_of(&document, div);
+        assert_eq!(element.get_attribute("data-new-value"), Some("5"));
+        assert_eq!(element.get_attribute("data-baz"), Some("6"));
+        assert!(!element.has_attribute("data-foo-bar"));
+    }
+
+    #[test]
+    fn converts_between_data_names() {
+        for (property, attribute) in [
+            ("fooBar", "data-foo-bar"),
+            ("a1B", "data-a1-b"),
+            ("foo-1", "data-foo-1"),
+            ("-", "data--"),
+            ("", "data-"),
+        ] {
+            assert_eq!(data_attribute_name(property).as_deref(), Ok(attribute));
+            assert_eq!(
+                data_property_name(attribute.strip_prefix("data-").unwrap()),
+                property
+            );
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "only elements have attributes")]
+    fn only_elements_have_class_lists() {
+        let mut document = Document::new();
+        let text = document.create_text("x".to_string());
+        document.class_list(text);
+    }
+}
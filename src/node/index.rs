@@ -0,0 +1,453 @@
+//! Lookups of elements by id, tag name and class that don't scan the tree.
+//! The indexes only hold elements connected to the root, and are updated
+//! whenever an element is connected, disconnected or has its `id` or `class`
+//! changed. Results are put in tree order by numbers that every connected
+//! node carries, which are kept up to date as nodes are inserted.
+
+use super::{Document, Element, Namespace, Node, NodeData, NodeId, QuirksMode};
+use std::collections::{HashMap, HashSet};
+
+/// The gap left between the numbers of consecutive nodes when the tree is
+/// numbered from scratch, so nodes inserted later can be numbered in between.
+const TREE_ORDER_SPACING: u64 = 1 << 32;
+
+pub(super) struct Indexes {
+    ids: HashMap<String, HashSet<NodeId>>,
+    tag_names: HashMap<String, HashSet<NodeId>>,
+    /// Keyed by lowercased class, so quirks mode can match classes
+    /// case-insensitively.
+    classes: HashMap<String, HashSet<NodeId>>,
+    /// Numbers that increase in tree order, by node index. Only the numbers
+    /// of connected nodes mean anything; the root's is always 0.
+    tree_order: Vec<u64>,
+}
+
+impl Default for Indexes {
+    fn default() -> Self {
+        Self {
+            ids: HashMap::new(),
+            tag_names: HashMap::new(),
+            classes: HashMap::new(),
+            tree_order: vec![0],
+        }
+    }
+}
+
+fn add(index: &mut HashMap<String, HashSet<NodeId>>, key: &str, node: NodeId) {
+    match index.get_mut(key) {
+        Some(nodes) => {
+            nodes.insert(node);
+        }
+        None => {
+            index.insert(key.to_string(), std::iter::once(node).collect());
+        }
+    }
+}
+
+fn remove(index: &mut HashMap<String, HashSet<NodeId>>, key: &str, node: NodeId) {
+    if let Some(nodes) = index.get_mut(key) {
+        nodes.remove(&node);
+        if nodes.is_empty() {
+            index.remove(key);
+        }
+    }
+}
+
+impl Document {
+    /// The first element in tree order whose `id` is `id`. Takes time in
+    /// the number of elements with that id, not the size of the tree.
+    pub fn get_element_by_id(&self, id: &str) -> Option<NodeId> {
+        if id.is_empty() {
+            return None;
+        }
+        let mut elements = self.matching(self.indexes.ids.get(id), |element| element.id() == id);
+        self.sort_in_tree_order(&mut elements);
+        elements.first().copied()
+    }
+
+    /// The elements called `qualified_name` in tree order, or all of them
+    /// for `*`. HTML elements match the name in any case. Takes time in the
+    /// number of elements with the name, plus sorting them, except for `*`,
+    /// which walks the whole tree.
+    pub fn get_elements_by_tag_name(&self, qualified_name: &str) -> Vec<NodeId> {
+        if qualified_name == "*" {
+            // Every element matches, so walking the tree is the cheapest way
+            // to have them in order.
+            return self
+                .descendants(Document::ROOT)
+                .filter(|&node| self[node].as_element().is_some())
+                .collect();
+        }
+        let lowercase = qualified_name.to_ascii_lowercase();
+        let mut elements: Vec<NodeId> = if lowercase == qualified_name {
+            // Every element in the bucket matches, whatever its namespace.
+            let bucket = self.indexes.tag_names.get(qualified_name);
+            bucket.into_iter().flatten().copied().collect()
+        } else {
+            let html = self.matching(self.indexes.tag_names.get(&lowercase), |element| {
+                element.namespace == Namespace::HTML
+            });
+            let foreign = self.matching(self.indexes.tag_names.get(qualified_name), |element| {
+                element.namespace != Namespace::HTML
+            });
+            html.into_iter().chain(foreign).collect()
+        };
+        self.sort_in_tree_order(&mut elements);
+        elements
+    }
+
+    /// The elements that have all the space-separated `class_names`, in tree
+    /// order. Classes are matched case-insensitively in quirks mode. Takes
+    /// time in the number of elements with the rarest of the classes, plus
+    /// sorting the matches.
+    pub fn get_elements_by_class_name(&self, class_names: &str) -> Vec<NodeId> {
+        let classes: Vec<&str> = class_names.split_ascii_whitespace().collect();
+        let smallest = classes
+            .iter()
+            .map(|class| self.indexes.classes.get(&class.to_ascii_lowercase()))
+            .min_by_key(|nodes| nodes.map_or(0, HashSet::len));
+        let smallest = match smallest {
+            Some(smallest) => smallest,
+            None => return Vec::new(),
+        };
+        let quirks = self.quirks_mode == QuirksMode::Quirks;
+        let mut elements = self.matching(smallest, |element| {
+            let own = element.classes();
+            classes.iter().all(|class| {
+                own.iter().any(|own| {
+                    if quirks {
+                        own.eq_ignore_ascii_case(class)
+                    } else {
+                        own == class
+                    }
+                })
+            })
+        });
+        self.sort_in_tree_order(&mut elements);
+        elements
+    }
+
+    /// The elements among `candidates` that `matches` accepts.
+    fn matching(
+        &self,
+        candidates: Option<&HashSet<NodeId>>,
+        matches: impl Fn(&Element) -> bool,
+    ) -> Vec<NodeId> {
+        candidates
+            .into_iter()
+            .flatten()
+            .copied()
+            .filter(|&node| self[node].as_element().is_some_and(&matches))
+            .collect()
+    }
+
+    /// Sorts connected nodes into tree order.
+    fn sort_in_tree_order(&self, nodes: &mut [NodeId]) {
+        nodes.sort_unstable_by_key(|node| self.indexes.tree_order[node.0]);
+    }
+
+    /// Numbers the just inserted `node` and its descendants between the
+    /// nodes before and after them in tree order. If the gap between those
+    /// is too small, numbers the whole tree again, which happens about once
+    /// every 32 insertions at the same place.
+    pub(super) fn number_in_tree_order(&mut self, node: NodeId) {
+        self.indexes.tree_order.resize(self.nodes.len(), 0);
+        let before = self
+            .preceding(node, Document::ROOT)
+            .expect("an inserted node has a parent");
+        let lowest = self.indexes.tree_order[before.0];
+        let after = self.following(self.last_descendant(node), Document::ROOT);
+        let count = self.descendants(node).count() as u64 + 1;
+        let step = match after {
+            Some(after) => (self.indexes.tree_order[after.0] - lowest) / (count + 1),
+            None => TREE_ORDER_SPACING,
+        };
+        if step == 0
+            || step
+                .checked_mul(count + 1)
+                .and_then(|gap| gap.checked_add(lowest))
+                .is_none()
+        {
+            self.number_tree();
+            return;
+        }
+        self.number_subtree(node, lowest + step, step);
+    }
+
+    fn number_tree(&mut self) {
+        self.number_subtree(Document::ROOT, 0, TREE_ORDER_SPACING);
+    }
+
+    /// Numbers `node` and its descendants in tree order, from `first` up in
+    /// steps of `step`.
+    fn number_subtree(&mut self, node: NodeId, first: u64, step: u64) {
+        let mut number = first;
+        let mut next = Some(node);
+        while let Some(current) = next {
+            self.indexes.tree_order[current.0] = number;
+            number += step;
+            next = self.following(current, node);
+        }
+    }
+
+    /// Marks `node` and its descendants as connected or not, adding their
+    /// elements to the indexes or removing them.
+    pub(super) fn set_connected(&mut self, node: NodeId, connected: bool) {
+        let mut next = Some(node);
+        while let Some(current) = next {
+            let Node {
+                connected: node_connected,
+                data,
+                ..
+            } = &mut self.nodes[current.0];
+            *node_connected = connected;
+            if let NodeData::Element(element) = data {
+                let indexes = &mut self.indexes;
+                if connected {
+                    add(&mut indexes.tag_names, &element.local_name, current);
+                    index_attributes(indexes, element, current);
+                } else {
+                    remove(&mut indexes.tag_names, &element.local_name, current);
+                    unindex_attributes(indexes, element, current);
+                }
+            }
+            next = self.following(current, node);
+        }
+    }
+
+    /// Runs `change` on the attributes of the element `node`, updating the
+    /// indexes around it. Panics if `node` isn't an element.
+    pub(crate) fn change_attributes<R>(
+        &mut self,
+        node: NodeId,
+        change: impl FnOnce(&mut Element) -> R,
+    ) -> R {
+        let Node {
+            connected, data, ..
+        } = &mut self.nodes[node.0];
+        let element = match data {
+            NodeData::Element(element) => element,
+            _ => panic!("only elements have attributes"),
+        };
+        if !*connected {
+            return change(element);
+        }
+        unindex_attributes(&mut self.indexes, element, node);
+        let result = change(element);
+        index_attributes(&mut self.indexes, element, node);
+        result
+    }
+}
+
+fn index_attributes(indexes: &mut Indexes, element: &Element, node: NodeId) {
+    let id = element.id();
+    if !id.is_empty() {
+        add(&mut indexes.ids, id, node);
+    }
+    for class in element.classes() {
+        add(&mut indexes.classes, &class.to_ascii_lowercase(), node);
+    }
+}
+
+fn unindex_attributes(indexes: &mut Indexes, element: &Element, node: NodeId) {
+    let id = element.id();
+    if !id.is_empty() {
+        remove(&mut indexes.ids, id, node);
+    }
+    for class in element.classes() {
+        remove(&mut indexes.classes, &class.to_ascii_lowercase(), node);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_document;
+
+    fn parse(input: &str) -> Document {
+        parse_document(input.to_string()).into_document()
+    }
+
+    fn local_names(document: &Document, nodes: &[NodeId]) -> Vec<String> {
+        nodes
+            .iter()
+            .map(|&node| {
+                let element = document[node].as_element().unwrap();
+                format!("{}#{}", element.local_name(), element.id())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finds_elements_of_a_parsed_document() {
+        let document = parse(
+            "<!DOCTYPE html><div id=a class='x Y'><p id=b class=y></p>\
+             <svg><clipPath id=c class=x></clipPath></svg>\
+             <template><i id=t class=x></i></template></div><p id=a class='x y'>",
+        );
+        let div = document.get_element_by_id("a").unwrap();
+        assert_eq!(local_names(&document, &[div]), ["div#a"]);
+        assert_eq!(document.get_element_by_id("t"), None);
+        assert_eq!(document.get_element_by_id(""), None);
+        assert_eq!(
+            local_names(&document, &document.get_elements_by_tag_name("P")),
+            ["p#b", "p#a"]
+        );
+        assert_eq!(
+            local_names(&document, &document.get_elements_by_tag_name("clipPath")),
+            ["clipPath#c"]
+        );
+        assert!(document.get_elements_by_tag_name("clippath").is_empty());
+        assert_eq!(document.get_elements_by_tag_name("*").len(), 9);
+        assert_eq!(
+            local_names(&document, &document.get_elements_by_class_name("x")),
+            ["div#a", "clipPath#c", "p#a"]
+        );
+        assert_eq!(
+            local_names(&document, &document.get_elements_by_class_name(" y  x ")),
+            ["p#a"]
+        );
+        assert!(document.get_elements_by_class_name(" ").is_empty());
+    }
+
+    #[test]
+    fn matches_classes_case_insensitively_in_quirks_mode() {
+        let document = parse("<div class='x Y'></div><p class='x y'>");
+        assert_eq!(document.quirks_mode, QuirksMode::Quirks);
+        assert_eq!(document.get_elements_by_class_name("X y").len(), 2);
+        let document = parse("<!DOCTYPE html><div class='x Y'></div><p class='x y'>");
+        assert_eq!(document.get_elements_by_class_name("X y").len(), 0);
+        assert_eq!(document.get_elements_by_class_name("x y").len(), 1);
+    }
+
+    #[test]
+    fn follows_insertions_and_removals() {
+        let mut document = parse("<!DOCTYPE html><div id=a><p id=b class=x></p></div>");
+        let div = document.get_element_by_id("a").unwrap();
+        let p = document.get_element_by_id("b").unwrap();
+        let body = document.parent(div).unwrap();
+
+        document.remove_child(body, div).unwrap();
+        assert_eq!(document.get_element_by_id("a"), None);
+        assert_eq!(document.get_element_by_id("b"), None);
+        assert!(document.get_elements_by_class_name("x").is_empty());
+        assert!(document.get_elements_by_tag_name("p").is_empty());
+
+        let span = document.create_element("span".to_string(), Namespace::HTML, Vec::new());
+        document.set_attribute(span, "id", "s".to_string()).unwrap();
+        assert_eq!(document.get_element_by_id("s"), None);
+        document.append_child(p, span).unwrap();
+        assert_eq!(document.get_element_by_id("s"), None);
+        document.append_child(body, div).unwrap();
+        assert_eq!(document.get_element_by_id("a"), Some(div));
+        assert_eq!(document.get_element_by_id("s"), Some(span));
+
+        let fragment = document.create_document_fragment();
+        let other = document.create_element("p".to_string(), Namespace::HTML, Vec::new());
+        document.append_child(fragment, other).unwrap();
+        document.replace_child(body, fragment, div).unwrap();
+        assert_eq!(document.get_element_by_id("a"), None);
+        assert_eq!(document.get_elements_by_tag_name("p"), [other]);
+    }
+
+    #[test]
+    fn follows_attribute_changes() {
+        let mut document = parse("<!DOCTYPE html><div id=a class='x y'>");
+        let div = document.get_element_by_id("a").unwrap();
+
+        document.set_attribute(div, "ID", "b".to_string()).unwrap();
+        assert_eq!(document.get_element_by_id("a"), None);
+        assert_eq!(document.get_element_by_id("b"), Some(div));
+        document.remove_attribute(div, "id");
+        assert_eq!(document.get_element_by_id("b"), None);
+
+        document.class_list(div).remove("x").unwrap();
+        document.class_list(div).add("z").unwrap();
+        assert!(document.get_elements_by_class_name("x").is_empty());
+        assert_eq!(document.get_elements_by_class_name("y z"), [div]);
+        document.set_attribute(div, "class", String::new()).unwrap();
+        assert!(document.get_elements_by_class_name("y").is_empty());
+    }
+
+    #[test]
+    fn keeps_results_in_tree_order_after_moves() {
+        let mut document = parse("<!DOCTYPE html><p id=1></p><p id=2></p><p id=3></p>");
+        let ids = |document: &Document| -> Vec<String> {
+            let paragraphs = document.get_elements_by_tag_name("p");
+            local_names(document, &paragraphs)
+        };
+        assert_eq!(ids(&document), ["p#1", "p#2", "p#3"]);
+        let first = document.get_element_by_id("1").unwrap();
+        let body = document.parent(first).unwrap();
+        document.append_child(body, first).unwrap();
+        assert_eq!(ids(&document), ["p#2", "p#3", "p#1"]);
+
+        let third = document.get_element_by_id("3").unwrap();
+        document
+            .set_attribute(third, "id", "1".to_string())
+            .unwrap();
+        assert_eq!(document.get_element_by_id("1"), Some(third));
+    }
+
+    #[test]
+    fn lookups_match_a_scan() {
+        let mut input = String::from("<!DOCTYPE html><table>");
+        for row in 0..500 {
+            input.push_str("<tr>");
+            for cell in 0..4 {
+                let class = if (row * 4 + cell) % 10 == 0 {
+                    " class=hit"
+                } else {
+                    ""
+                };
+                input.push_str(&format!("<td{}><b>{}</b> text</td>", class, cell));
+            }
+            input.push_str("</tr>");
+        }
+        let document = parse(&input);
+        let scan = |matches: &dyn Fn(&Element) -> bool| -> Vec<NodeId> {
+            document
+                .descendants(Document::ROOT)
+                .filter(|&node| document[node].as_element().is_some_and(matches))
+                .collect()
+        };
+
+        let by_class = document.get_elements_by_class_name("hit");
+        assert_eq!(by_class, scan(&|element| element.has_class("hit")));
+        assert_eq!(by_class.len(), 200);
+        let by_tag = document.get_elements_by_tag_name("td");
+        assert_eq!(by_tag, scan(&|element| element.local_name() == "td"));
+    }
+
+    #[test]
+    fn keeps_results_in_tree_order_after_many_insertions_in_one_place() {
+        let mut document = parse("<!DOCTYPE html><p id=first></p><p id=last></p>");
+        let last = document.get_element_by_id("last").unwrap();
+        let body = document.parent(last).unwrap();
+        // Each paragraph goes right before the last one, halving the gap
+        // between the numbers around it until the tree has to be renumbered.
+        for i in 0..100 {
+            let p = document.create_element("p".to_string(), Namespace::HTML, Vec::new());
+            document.set_attribute(p, "id", i.to_string()).unwrap();
+            let span = document.create_element("span".to_string(), Namespace::HTML, Vec::new());
+            document.append_child(p, span).unwrap();
+            document.insert_before(body, p, Some(last)).unwrap();
+        }
+        let scanned: Vec<NodeId> = document
+            .descendants(Document::ROOT)
+            .filter(|&node| {
+                document[node]
+                    .as_element()
+                    .is_some_and(|element| element.local_name() == "p")
+            })
+            .collect();
+        let paragraphs = document.get_elements_by_tag_name("p");
+        assert_eq!(paragraphs, scanned);
+        assert_eq!(
+            local_names(&document, &paragraphs[..3]),
+            ["p#first", "p#0", "p#1"]
+        );
+        assert_eq!(local_names(&document, &paragraphs[101..]), ["p#last"]);
+        assert_eq!(document.get_elements_by_tag_name("span").len(), 100);
+    }
+}
@@ -0,0 +1,442 @@
+//! The DOM standard's checked tree mutations. Unlike the parser's unchecked
+//! linking, these refuse to build a tree the DOM couldn't represent, such as
+//! a node inside itself or a document with two document elements.
+
+use super::{Document, NodeData, NodeId, NodeType};
+use crate::error::DomError;
+
+impl Document {
+    /// Appends `node` to the children of `parent`, moving it from wherever it
+    /// was. A document fragment is emptied into `parent` instead.
+    pub fn append_child(&mut self, parent: NodeId, node: NodeId) -> Result<NodeId, DomError> {
+        self.insert_before(parent, node, None)
+    }
+
+    /// Inserts `node` into `parent` before `child`, or as its last child if
+    /// there is no `child`. Returns `node`.
+    pub fn insert_before(
+        &mut self,
+        parent: NodeId,
+        node: NodeId,
+        child: Option<NodeId>,
+    ) -> Result<NodeId, DomError> {
+        self.ensure_validity(parent, node, child, None)?;
+        let reference = if child == Some(node) {
+            self[node].next_sibling
+        } else {
+            child
+        };
+        self.insert_node(parent, node, reference);
+        Ok(node)
+    }
+
+    /// Removes `child` from `parent` and returns it. The node stays in the
+    /// document and can be inserted again.
+    pub fn remove_child(&mut self, parent: NodeId, child: NodeId) -> Result<NodeId, DomError> {
+        if self[child].parent != Some(parent) {
+            return Err(DomError::NotFound);
+        }
+        self.detach(child);
+        Ok(child)
+    }
+
+    /// Puts `node` where `child` is in `parent` and returns `child`.
+    pub fn replace_child(
+        &mut self,
+        parent: NodeId,
+        node: NodeId,
+        child: NodeId,
+    ) -> Result<NodeId, DomError> {
+        self.ensure_validity(parent, node, Some(child), Some(child))?;
+        let mut reference = self[child].next_sibling;
+        if reference == Some(node) {
+            reference = self[node].next_sibling;
+        }
+        self.detach(child);
+        self.insert_node(parent, node, reference);
+        Ok(child)
+    }
+
+    /// Removes `node` from its parent, if it has one. This is always valid.
+    pub fn remove_from_parent(&mut self, node: NodeId) {
+        self.detach(node);
+    }
+
+    /// Moves all children of `from` to the end of `to`, as if they were in a
+    /// document fragment appended to `to`. Moving them to where they are
+    /// already does nothing.
+    pub fn reparent_children(&mut self, from: NodeId, to: NodeId) -> Result<(), DomError> {
+        if from == to {
+            return Ok(());
+        }
+        self.ensure_validity_of(to, self.children(from).collect(), None, None)?;
+        self.move_children(from, to);
+        Ok(())
+    }
+
+    /// Merges runs of adjacent text nodes among the descendants of `node`
+    /// into their first node, and removes empty text nodes.
+    pub fn normalize(&mut self, node: NodeId) {
+        let mut stack = vec![node];
+        while let Some(parent) = stack.pop() {
+            let mut next = self[parent].first_child;
+            while let Some(child) = next {
+                next = self[child].next_sibling;
+                if !matches!(self[child].data, NodeData::Text(_)) {
+                    stack.push(child);
+                    continue;
+                }
+                let mut merged = String::new();
+                while let Some(sibling) = next {
+                    match &self[sibling].data {
+                        NodeData::Text(data) => merged.push_str(data),
+                        _ => break,
+                    }
+                    next = self[sibling].next_sibling;
+                    self.detach(sibling);
+                }
+                if let NodeData::Text(data) = &mut self.node_mut(child).data {
+                    data.push_str(&merged);
+                    if data.is_empty() {
+                        self.detach(child);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Whether `node` is `other` or one of its ancestors.
+    pub fn is_inclusive_ancestor(&self, node: NodeId, other: NodeId) -> bool {
+        let mut current = Some(other);
+        while let Some(ancestor) = current {
+            if ancestor == node {
+                return true;
+            }
+            current = self[ancestor].parent;
+        }
+        false
+    }
+
+    /// Inserts `node` before `reference`, or its children if it is a
+    /// document fragment.
+    fn insert_node(&mut self, parent: NodeId, node: NodeId, reference: Option<NodeId>) {
+        if let NodeData::DocumentFragment = self[node].data {
+            while let Some(child) = self[node].first_child {
+                self.insert(parent, child, reference);
+            }
+        } else {
+            self.insert(parent, node, reference);
+        }
+    }
+
+    /// Checks that `node` can go before `child` in `parent`, with `replaced`
+    /// being the child that `node` takes the place of, if any.
+    fn ensure_validity(
+        &self,
+        parent: NodeId,
+        node: NodeId,
+        child: Option<NodeId>,
+        replaced: Option<NodeId>,
+    ) -> Result<(), DomError> {
+        if self.is_inclusive_ancestor(node, parent) {
+            return Err(DomError::WouldCreateCycle);
+        }
+        let inserted = match self[node].data {
+            NodeData::DocumentFragment => self.children(node).collect(),
+            _ => vec![node],
+        };
+        self.ensure_validity_of(parent, inserted, child, replaced)
+    }
+
+    /// Checks that the `inserted` nodes can go before `child` in `parent`
+    /// together, in that order.
+    fn ensure_validity_of(
+        &self,
+        parent: NodeId,
+        inserted: Vec<NodeId>,
+        child: Option<NodeId>,
+        replaced: Option<NodeId>,
+    ) -> Result<(), DomError> {
+        let parent_type = self[parent].node_type();
+        match parent_type {
+            NodeType::DocumentNode | NodeType::DocumentFragmentNode | NodeType::ElementNode => {}
+            _ => return Err(DomError::ParentCannotHaveChildren),
+        }
+        if inserted
+            .iter()
+            .any(|&node| self.is_inclusive_ancestor(node, parent))
+        {
+            return Err(DomError::WouldCreateCycle);
+        }
+        if let Some(child) = child {
+            if self[child].parent != Some(parent) {
+                return Err(DomError::NotFound);
+            }
+        }
+
+        let in_document = parent_type == NodeType::DocumentNode;
+        let mut elements = 0;
+        let mut doctypes = 0;
+        for &node in &inserted {
+            match self[node].node_type() {
+                NodeType::DocumentNode => return Err(DomError::CannotInsertDocument),
+                NodeType::TextNode if in_document => return Err(DomError::TextInDocument),
+                NodeType::DocumentTypeNode if !in_document => {
+                    return Err(DomError::DoctypeOutsideDocument)
+                }
+                NodeType::ElementNode => elements += 1,
+                NodeType::DocumentTypeNode if elements > 0 => {
+                    return Err(DomError::DoctypeAfterDocumentElement)
+                }
+                NodeType::DocumentTypeNode => doctypes += 1,
+                _ => {}
+            }
+        }
+        if !in_document {
+            return Ok(());
+        }
+        if elements > 1 {
+            return Err(DomError::MultipleDocumentElements);
+        }
+        if doctypes > 1 {
+            return Err(DomError::MultipleDoctypes);
+        }
+
+        let mut before_child = true;
+        for existing in self.children(parent) {
+            if Some(existing) == child {
+                before_child = false;
+            }
+            if Some(existing) == replaced {
+                continue;
+            }
+            match self[existing].node_type() {
+                NodeType::ElementNode if elements > 0 => {
+                    return Err(DomError::MultipleDocumentElements)
+                }
+                NodeType::ElementNode if doctypes > 0 && before_child => {
+                    return Err(DomError::DoctypeAfterDocumentElement)
+                }
+                NodeType::DocumentTypeNode if doctypes > 0 => {
+                    return Err(DomError::MultipleDoctypes)
+                }
+                NodeType::DocumentTypeNode if elements > 0 && !before_child => {
+                    return Err(DomError::DoctypeAfterDocumentElement)
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::Namespace;
+
+    fn element(document: &mut Document, name: &str) -> NodeId {
+        document.create_element(name.to_string(), Namespace::HTML, Vec::new())
+    }
+
+    /// A document with a doctype and an `<html>` element holding `<body>`.
+    fn document() -> (Document, NodeId, NodeId) {
+        let mut document = Document::new();
+        let doctype = document.create_doctype("html".to_string(), String::new(), String::new());
+        let html = element(&mut document, "html");
+        let body = element(&mut document, "body");
+        document.append_child(Document::ROOT, doctype).unwrap();
+        document.append_child(Document::ROOT, html).unwrap();
+        document.append_child(html, body).unwrap();
+        (document, html, body)
+    }
+
+    #[test]
+    fn moves_nodes_between_parents() {
+        let (mut document, html, body) = document();
+        let div = element(&mut document, "div");
+        let p = element(&mut document, "p");
+        document.append_child(body, div).unwrap();
+        document.append_child(body, p).unwrap();
+        document.insert_before(body, p, Some(div)).unwrap();
+        document.append_child(p, div).unwrap();
+        assert_eq!(
+            document.to_string(),
+            "| <!DOCTYPE html>\n| <html>\n|   <body>\n|     <p>\n|       <div>\n"
+        );
+        assert_eq!(document.remove_child(body, p), Ok(p));
+        assert_eq!(document.remove_child(body, p), Err(DomError::NotFound));
+        assert_eq!(document.parent(p), None);
+        assert_eq!(document.parent(div), Some(p));
+        assert_eq!(document.last_child(html), Some(body));
+    }
+
+    #[test]
+    fn inserting_before_itself_keeps_the_node_in_place() {
+        let (mut document, _, body) = document();
+        let a = element(&mut document, "a");
+        let b = element(&mut document, "b");
+        document.append_child(body, a).unwrap();
+        document.append_child(body, b).unwrap();
+        document.insert_before(body, a, Some(a)).unwrap();
+        assert_eq!(document.children(body).collect::<Vec<_>>(), [a, b]);
+    }
+
+    #[test]
+    fn inserting_a_fragment_moves_its_children() {
+        let (mut document, _, body) = document();
+        let fragment = document.create_document_fragment();
+        let a = element(&mut document, "a");
+        let text = document.create_text("x".to_string());
+        document.append_child(fragment, a).unwrap();
+        document.append_child(fragment, text).unwrap();
+        assert_eq!(document.append_child(body, fragment), Ok(fragment));
+        assert_eq!(document.children(body).collect::<Vec<_>>(), [a, text]);
+        assert_eq!(document.first_child(fragment), None);
+    }
+
+    #[test]
+    fn replaces_children() {
+        let (mut document, _, body) = document();
+        let a = element(&mut document, "a");
+        let b = element(&mut document, "b");
+        let c = element(&mut document, "c");
+        for child in [a, b] {
+            document.append_child(body, child).unwrap();
+        }
+        assert_eq!(document.replace_child(body, c, a), Ok(a));
+        assert_eq!(document.children(body).collect::<Vec<_>>(), [c, b]);
+        assert_eq!(document.replace_child(body, b, c), Ok(c));
+        assert_eq!(document.children(body).collect::<Vec<_>>(), [b]);
+        assert_eq!(document.replace_child(body, a, c), Err(DomError::NotFound));
+    }
+
+    #[test]
+    fn the_document_element_can_be_replaced() {
+        let (mut document, html, _) = document();
+        let other = element(&mut document, "html");
+        assert_eq!(
+            document.replace_child(Document::ROOT, other, html),
+            Ok(html)
+        );
+        assert_eq!(document.last_child(Document::ROOT), Some(other));
+    }
+
+    #[test]
+    fn reports_invalid_insertions() {
+        let (mut document, html, body) = document();
+        let doctype = document.first_child(Document::ROOT).unwrap();
+        let div = element(&mut document, "div");
+        let text = document.create_text("x".to_string());
+        let second_doctype =
+            document.create_doctype("html".to_string(), String::new(), String::new());
+        let before = document.to_string();
+
+        let errors = [
+            (
+                document.append_child(body, html),
+                DomError::WouldCreateCycle,
+            ),
+            (
+                document.append_child(body, body),
+                DomError::WouldCreateCycle,
+            ),
+            (
+                document.append_child(text, div),
+                DomError::ParentCannotHaveChildren,
+            ),
+            (
+                document.append_child(div, Document::ROOT),
+                DomError::CannotInsertDocument,
+            ),
+            (
+                document.append_child(body, Document::ROOT),
+                DomError::WouldCreateCycle,
+            ),
+            (
+                document.insert_before(body, div, Some(html)),
+                DomError::NotFound,
+            ),
+            (
+                document.append_child(Document::ROOT, div),
+                DomError::MultipleDocumentElements,
+            ),
+            (
+                document.append_child(Document::ROOT, text),
+                DomError::TextInDocument,
+            ),
+            (
+                document.append_child(body, second_doctype),
+                DomError::DoctypeOutsideDocument,
+            ),
+            (
+                document.append_child(Document::ROOT, second_doctype),
+                DomError::MultipleDoctypes,
+            ),
+        ];
+        for (result, error) in errors.iter() {
+            assert_eq!(*result, Err(*error));
+        }
+
+        document.remove_child(Document::ROOT, doctype).unwrap();
+        assert_eq!(
+            document.append_child(Document::ROOT, doctype),
+            Err(DomError::DoctypeAfterDocumentElement)
+        );
+        assert_eq!(
+            document.insert_before(Document::ROOT, doctype, Some(html)),
+            Ok(doctype)
+        );
+        assert_eq!(document.to_string(), before);
+    }
+
+    #[test]
+    fn reparenting_children_into_their_parent_does_nothing() {
+        let (mut document, _, body) = document();
+        let a = element(&mut document, "a");
+        let b = element(&mut document, "b");
+        document.append_child(body, a).unwrap();
+        document.append_child(body, b).unwrap();
+        assert_eq!(document.reparent_children(body, body), Ok(()));
+        assert_eq!(document.children(body).collect::<Vec<_>>(), [a, b]);
+        assert_eq!(
+            document.reparent_children(Document::ROOT, Document::ROOT),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn reparents_children() {
+        let (mut document, html, body) = document();
+        let div = element(&mut document, "div");
+        let a = element(&mut document, "a");
+        document.append_child(body, a).unwrap();
+        document.append_child(body, div).unwrap();
+        assert_eq!(
+            document.reparent_children(html, div),
+            Err(DomError::WouldCreateCycle)
+        );
+        let other = element(&mut document, "section");
+        assert_eq!(document.reparent_children(body, other), Ok(()));
+        assert_eq!(document.children(other).collect::<Vec<_>>(), [a, div]);
+        assert_eq!(document.first_child(body), None);
+    }
+
+    #[test]
+    fn normalize_merges_adjacent_text() {
+        let (mut document, _, body) = document();
+        let p = element(&mut document, "p");
+        document.append_child(body, p).unwrap();
+        for data in ["a", "", "b"] {
+            let text = document.create_text(data.to_string());
+            document.append_child(p, text).unwrap();
+        }
+        let empty = document.create_text(String::new());
+        document.append_child(body, empty).unwrap();
+        document.normalize(Document::ROOT);
+        assert_eq!(
+            document.to_string(),
+            "| <!DOCTYPE html>\n| <html>\n|   <body>\n|     <p>\n|       \"ab\"\n"
+        );
+    }
+}
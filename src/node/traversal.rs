@@ -0,0 +1,841 @@
+//! Iterators over the nodes around a node, and the DOM standard's
+//! `TreeWalker` and `NodeIterator` for walks that skip some of them.
+
+use super::{Document, NodeId, NodeType};
+use std::ops::BitOr;
+
+impl Document {
+    pub fn children(&self, node: NodeId) -> Children<'_> {
+        Children {
+            document: self,
+            front: self[node].first_child,
+            back: self[node].last_child,
+        }
+    }
+
+    /// The descendants of `node` in tree order, i.e. the order their start
+    /// tags appear in the source. Reverse it for reverse document order.
+    pub fn descendants(&self, node: NodeId) -> Descendants<'_> {
+        Descendants {
+            document: self,
+            root: node,
+            front: self[node].first_child,
+            back: self[node].last_child.map(|last| self.last_descendant(last)),
+        }
+    }
+
+    /// The parent of `node`, then its parent, and so on up to the root.
+    pub fn ancestors(&self, node: NodeId) -> Ancestors<'_> {
+        Ancestors {
+            document: self,
+            next: self[node].parent,
+        }
+    }
+
+    /// The siblings after `node`, nearest first.
+    pub fn following_siblings(&self, node: NodeId) -> FollowingSiblings<'_> {
+        FollowingSiblings {
+            document: self,
+            next: self[node].next_sibling,
+        }
+    }
+
+    /// The siblings before `node`, nearest first.
+    pub fn preceding_siblings(&self, node: NodeId) -> PrecedingSiblings<'_> {
+        PrecedingSiblings {
+            document: self,
+            next: self[node].previous_sibling,
+        }
+    }
+
+    /// Walks the descendants of `root`, only stopping at nodes whose type is
+    /// in `what_to_show` and that `filter` accepts.
+    pub fn tree_walker<F>(
+        &self,
+        root: NodeId,
+        what_to_show: WhatToShow,
+        filter: F,
+    ) -> TreeWalker<'_, F>
+    where
+        F: FnMut(&Document, NodeId) -> FilterResult,
+    {
+        TreeWalker {
+            document: self,
+            root,
+            what_to_show,
+            filter,
+            current_node: root,
+        }
+    }
+
+    /// Iterates over `root` and its descendants in tree order, only
+    /// returning nodes whose type is in `what_to_show` and that `filter`
+    /// accepts.
+    pub fn node_iterator<F>(
+        &self,
+        root: NodeId,
+        what_to_show: WhatToShow,
+        filter: F,
+    ) -> NodeIterator<'_, F>
+    where
+        F: FnMut(&Document, NodeId) -> FilterResult,
+    {
+        NodeIterator {
+            document: self,
+            root,
+            what_to_show,
+            filter,
+            reference_node: root,
+            pointer_before_reference_node: true,
+        }
+    }
+
+    /// `node` if it has no children, or else the last node inside it in tree
+    /// order.
+    pub(super) fn last_descendant(&self, mut node: NodeId) -> NodeId {
+        while let Some(last) = self[node].last_child {
+            node = last;
+        }
+        node
+    }
+
+    /// The node after `node` in tree order, without leaving `root`.
+    pub(super) fn following(&self, node: NodeId, root: NodeId) -> Option<NodeId> {
+        if let Some(first) = self[node].first_child {
+            return Some(first);
+        }
+        let mut current = node;
+        while current != root {
+            if let Some(next) = self[current].next_sibling {
+                return Some(next);
+            }
+            current = self[current].parent?;
+        }
+        None
+    }
+
+    /// The node before `node` in tree order, without leaving `root`.
+    pub(super) fn preceding(&self, node: NodeId, root: NodeId) -> Option<NodeId> {
+        if node == root {
+            return None;
+        }
+        match self[node].previous_sibling {
+            Some(previous) => Some(self.last_descendant(previous)),
+            None => self[node].parent,
+        }
+    }
+}
+
+/// Iterator over the children of a node, in order.
+pub struct Children<'a> {
+    document: &'a Document,
+    front: Option<NodeId>,
+    back: Option<NodeId>,
+}
+
+impl Iterator for Children<'_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let node = self.front?;
+        if self.front == self.back {
+            self.front = None;
+            self.back = None;
+        } else {
+            self.front = self.document[node].next_sibling;
+        }
+        Some(node)
+    }
+}
+
+impl DoubleEndedIterator for Children<'_> {
+    fn next_back(&mut self) -> Option<NodeId> {
+        let node = self.back?;
+        if self.front == self.back {
+            self.front = None;
+            self.back = None;
+        } else {
+            self.back = self.document[node].previous_sibling;
+        }
+        Some(node)
+    }
+}
+
+/// Iterator over the descendants of a node, in tree order from the front and
+/// reverse tree order from the back.
+pub struct Descendants<'a> {
+    document: &'a Document,
+    root: NodeId,
+    front: Option<NodeId>,
+    back: Option<NodeId>,
+}
+
+impl Iterator for Descendants<'_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let node = self.front?;
+        if self.front == self.back {
+            self.front = None;
+            self.back = None;
+        } else {
+            self.front = self.document.following(node, self.root);
+        }
+        Some(node)
+    }
+}
+
+impl DoubleEndedIterator for Descendants<'_> {
+    fn next_back(&mut self) -> Option<NodeId> {
+        let node = self.back?;
+        if self.front == self.back {
+            self.front = None;
+            self.back = None;
+        } else {
+            // Stops before the root, which isn't one of its descendants.
+            self.back = self
+                .document
+                .preceding(node, self.root)
+                .filter(|&previous| previous != self.root);
+        }
+        Some(node)
+    }
+}
+
+/// Iterator over the ancestors of a node, nearest first.
+pub struct Ancestors<'a> {
+    document: &'a Document,
+    next: Option<NodeId>,
+}
+
+impl Iterator for Ancestors<'_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let node = self.next?;
+        self.next = self.document[node].parent;
+        Some(node)
+    }
+}
+
+pub struct FollowingSiblings<'a> {
+    document: &'a Document,
+    next: Option<NodeId>,
+}
+
+impl Iterator for FollowingSiblings<'_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let node = self.next?;
+        self.next = self.document[node].next_sibling;
+        Some(node)
+    }
+}
+
+pub struct PrecedingSiblings<'a> {
+    document: &'a Document,
+    next: Option<NodeId>,
+}
+
+impl Iterator for PrecedingSiblings<'_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let node = self.next?;
+        self.next = self.document[node].previous_sibling;
+        Some(node)
+    }
+}
+
+/// A set of node types, with the DOM's `NodeFilter.SHOW_*` values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WhatToShow(u32);
+
+impl WhatToShow {
+    pub const ALL: WhatToShow = WhatToShow(0xFFFF_FFFF);
+    pub const ELEMENT: WhatToShow = WhatToShow(0x1);
+    pub const TEXT: WhatToShow = WhatToShow(0x4);
+    pub const COMMENT: WhatToShow = WhatToShow(0x80);
+    pub const DOCUMENT: WhatToShow = WhatToShow(0x100);
+    pub const DOCUMENT_TYPE: WhatToShow = WhatToShow(0x200);
+    pub const DOCUMENT_FRAGMENT: WhatToShow = WhatToShow(0x400);
+
+    pub fn contains(self, node_type: NodeType) -> bool {
+        let bit = match node_type {
+            NodeType::ElementNode => WhatToShow::ELEMENT,
+            NodeType::TextNode => WhatToShow::TEXT,
+            NodeType::CommentNode => WhatToShow::COMMENT,
+            NodeType::DocumentNode => WhatToShow::DOCUMENT,
+            NodeType::DocumentTypeNode => WhatToShow::DOCUMENT_TYPE,
+            NodeType::DocumentFragmentNode => WhatToShow::DOCUMENT_FRAGMENT,
+        };
+        self.0 & bit.0 != 0
+    }
+}
+
+impl BitOr for WhatToShow {
+    type Output = WhatToShow;
+
+    fn bitor(self, other: WhatToShow) -> WhatToShow {
+        WhatToShow(self.0 | other.0)
+    }
+}
+
+/// What a filter decides about a node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterResult {
+    Accept,
+    /// Leaves out the node and, for a [`TreeWalker`], its descendants.
+    Reject,
+    /// Leaves out the node but not its descendants.
+    Skip,
+}
+
+fn filter_node<F>(
+    document: &Document,
+    what_to_show: WhatToShow,
+    filter: &mut F,
+    node: NodeId,
+) -> FilterResult
+where
+    F: FnMut(&Document, NodeId) -> FilterResult,
+{
+    if !what_to_show.contains(document[node].node_type()) {
+        return FilterResult::Skip;
+    }
+    filter(document, node)
+}
+
+/// Moves a current node around the tree below a root, seeing only the nodes
+/// that pass its filter. Every move returns the new current node, or `None`
+/// and stays put if there is nothing to move to. As an iterator it goes
+/// forward in tree order.
+pub struct TreeWalker<'a, F> {
+    document: &'a Document,
+    root: NodeId,
+    what_to_show: WhatToShow,
+    filter: F,
+    current_node: NodeId,
+}
+
+impl<F> TreeWalker<'_, F>
+where
+    F: FnMut(&Document, NodeId) -> FilterResult,
+{
+    pub fn root(&self) -> NodeId {
+        self.root
+    }
+
+    pub fn current_node(&self) -> NodeId {
+        self.current_node
+    }
+
+    /// Moves to `node`, which doesn't have to pass the filter or even be
+    /// inside the root.
+    pub fn set_current_node(&mut self, node: NodeId) {
+        self.current_node = node;
+    }
+
+    fn filter(&mut self, node: NodeId) -> FilterResult {
+        filter_node(self.document, self.what_to_show, &mut self.filter, node)
+    }
+
+    pub fn parent_node(&mut self) -> Option<NodeId> {
+        let mut node = self.current_node;
+        while node != self.root {
+            node = self.document[node].parent?;
+            if self.filter(node) == FilterResult::Accept {
+                self.current_node = node;
+                return Some(node);
+            }
+        }
+        None
+    }
+
+    pub fn first_child(&mut self) -> Option<NodeId> {
+        self.traverse_children(true)
+    }
+
+    pub fn last_child(&mut self) -> Option<NodeId> {
+        self.traverse_children(false)
+    }
+
+    fn traverse_children(&mut self, first: bool) -> Option<NodeId> {
+        let document = self.document;
+        let first_child = |node: NodeId| {
+            if first {
+                document[node].first_child
+            } else {
+                document[node].last_child
+            }
+        };
+        let next_sibling = |node: NodeId| {
+            if first {
+                document[node].next_sibling
+            } else {
+                document[node].previous_sibling
+            }
+        };
+
+        let mut node = first_child(self.current_node)?;
+        loop {
+            match self.filter(node) {
+                FilterResult::Accept => {
+                    self.current_node = node;
+                    return Some(node);
+                }
+                FilterResult::Skip => {
+                    if let Some(child) = first_child(node) {
+                        node = child;
+                        continue;
+                    }
+                }
+                FilterResult::Reject => {}
+            }
+            loop {
+                if let Some(sibling) = next_sibling(node) {
+                    node = sibling;
+                    break;
+                }
+                let parent = document[node].parent?;
+                if parent == self.root || parent == self.current_node {
+                    return None;
+                }
+                node = parent;
+            }
+        }
+    }
+
+    pub fn previous_sibling(&mut self) -> Option<NodeId> {
+        self.traverse_siblings(false)
+    }
+
+    pub fn next_sibling(&mut self) -> Option<NodeId> {
+        self.traverse_siblings(true)
+    }
+
+    fn traverse_siblings(&mut self, next: bool) -> Option<NodeId> {
+        let document = self.document;
+        let sibling_of = |node: NodeId| {
+            if next {
+                document[node].next_sibling
+            } else {
+                document[node].previous_sibling
+            }
+        };
+        let first_child = |node: NodeId| {
+            if next {
+                document[node].first_child
+            } else {
+                document[node].last_child
+            }
+        };
+
+        let mut node = self.current_node;
+        if node == self.root {
+            return None;
+        }
+        loop {
+            let mut sibling = sibling_of(node);
+            while let Some(candidate) = sibling {
+                node = candidate;
+                let result = self.filter(node);
+                if result == FilterResult::Accept {
+                    self.current_node = node;
+                    return Some(node);
+                }
+                sibling = first_child(node);
+                if result == FilterResult::Reject || sibling.is_none() {
+                    sibling = sibling_of(node);
+                }
+            }
+            node = document[node].parent?;
+            if node == self.root || self.filter(node) == FilterResult::Accept {
+                return None;
+            }
+        }
+    }
+
+    /// Moves to the previous visible node in tree order.
+    pub fn previous_node(&mut self) -> Option<NodeId> {
+        let document = self.document;
+        let mut node = self.current_node;
+        while node != self.root {
+            while let Some(sibling) = document[node].previous_sibling {
+                node = sibling;
+                let mut result = self.filter(node);
+                while result != FilterResult::Reject {
+                    match document[node].last_child {
+                        Some(last) => {
+                            node = last;
+                            result = self.filter(node);
+                        }
+                        None => break,
+                    }
+                }
+                if result == FilterResult::Accept {
+                    self.current_node = node;
+                    return Some(node);
+                }
+            }
+            node = document[node].parent?;
+            if self.filter(node) == FilterResult::Accept {
+                self.current_node = node;
+                return Some(node);
+            }
+        }
+        None
+    }
+
+    /// Moves to the next visible node in tree order.
+    pub fn next_node(&mut self) -> Option<NodeId> {
+        let document = self.document;
+        let mut node = self.current_node;
+        let mut result = FilterResult::Accept;
+        loop {
+            while result != FilterResult::Reject {
+                match document[node].first_child {
+                    Some(first) => {
+                        node = first;
+                        result = self.filter(node);
+                        if result == FilterResult::Accept {
+                            self.current_node = node;
+                            return Some(node);
+                        }
+                    }
+                    None => break,
+                }
+            }
+            let mut current = node;
+            loop {
+                if current == self.root {
+                    return None;
+                }
+                if let Some(sibling) = document[current].next_sibling {
+                    node = sibling;
+                    break;
+                }
+                current = document[current].parent?;
+            }
+            result = self.filter(node);
+            if result == FilterResult::Accept {
+                self.current_node = node;
+                return Some(node);
+            }
+        }
+    }
+}
+
+impl<F> Iterator for TreeWalker<'_, F>
+where
+    F: FnMut(&Document, NodeId) -> FilterResult,
+{
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        self.next_node()
+    }
+}
+
+/// Goes back and forth over a root and its descendants in tree order,
+/// returning the nodes that pass its filter. Unlike for a [`TreeWalker`],
+/// rejecting a node doesn't hide its descendants. As an iterator it goes
+/// forward.
+pub struct NodeIterator<'a, F> {
+    document: &'a Document,
+    root: NodeId,
+    what_to_show: WhatToShow,
+    filter: F,
+    reference_node: NodeId,
+    /// Whether the iterator is before or after the reference node.
+    pointer_before_reference_node: bool,
+}
+
+impl<F> NodeIterator<'_, F>
+where
+    F: FnMut(&Document, NodeId) -> FilterResult,
+{
+    pub fn root(&self) -> NodeId {
+        self.root
+    }
+
+    /// The last node returned, or the root before the first call.
+    pub fn reference_node(&self) -> NodeId {
+        self.reference_node
+    }
+
+    pub fn next_node(&mut self) -> Option<NodeId> {
+        self.traverse(true)
+    }
+
+    pub fn previous_node(&mut self) -> Option<NodeId> {
+        self.traverse(false)
+    }
+
+    fn traverse(&mut self, next: bool) -> Option<NodeId> {
+        let mut node = self.reference_node;
+        let mut before_node = self.pointer_before_reference_node;
+        loop {
+            if next {
+                if before_node {
+                    before_node = false;
+                } else {
+                    node = self.document.following(node, self.root)?;
+                }
+            } else if !before_node {
+                before_node = true;
+            } else {
+                node = self.document.preceding(node, self.root)?;
+            }
+            let result = filter_node(self.document, self.what_to_show, &mut self.filter, node);
+            if result == FilterResult::Accept {
+                break;
+            }
+        }
+        self.reference_node = node;
+        self.pointer_before_reference_node = before_node;
+        Some(node)
+    }
+}
+
+impl<F> Iterator for NodeIterator<'_, F>
+where
+    F: FnMut(&Document, NodeId) -> FilterResult,
+{
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        self.next_node()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::NodeData;
+    use crate::parser;
+
+    /// `<body>` holding `<div id=a><p>1</p><!--c--><ul><li>2<li>3</ul></div>`
+    /// and `<p>4</p>`.
+    fn document() -> (Document, NodeId) {
+        let document = parser::parse_document(
+            "<!DOCTYPE html><div id=a><p>1</p><!--c--><ul><li>2<li>3</ul></div><p>4</p>"
+                .to_string(),
+        )
+        .into_document();
+        let body = document.get_elements_by_tag_name("body")[0];
+        (document, body)
+    }
+
+    fn describe(document: &Document, node: NodeId) -> String {
+        match document[node].data() {
+            NodeData::Document => "#document".to_string(),
+            NodeData::DocumentFragment => "#fragment".to_string(),
+            NodeData::Doctype(_) => "!doctype".to_string(),
+            NodeData::Element(element) => element.local_name().to_string(),
+            NodeData::Text(data) => format!("{:?}", data),
+            NodeData::Comment(data) => format!("<!--{}-->", data),
+        }
+    }
+
+    fn describe_all(document: &Document, nodes: impl Iterator<Item = NodeId>) -> Vec<String> {
+        nodes.map(|node| describe(document, node)).collect()
+    }
+
+    fn accept_all(_: &Document, _: NodeId) -> FilterResult {
+        FilterResult::Accept
+    }
+
+    #[test]
+    fn iterates_over_children_from_both_ends() {
+        let (document, body) = document();
+        let div = document.first_child(body).unwrap();
+        assert_eq!(
+            describe_all(&document, document.children(div)),
+            ["p", "<!--c-->", "ul"]
+        );
+        assert_eq!(
+            describe_all(&document, document.children(div).rev()),
+            ["ul", "<!--c-->", "p"]
+        );
+        let mut children = document.children(div);
+        assert_eq!(children.next(), document.first_child(div));
+        assert_eq!(children.next_back(), document.last_child(div));
+        assert_eq!(describe_all(&document, children.by_ref()), ["<!--c-->"]);
+        assert_eq!(children.next_back(), None);
+    }
+
+    #[test]
+    fn iterates_over_descendants_in_tree_order() {
+        let (document, body) = document();
+        let order = [
+            "div", "p", "\"1\"", "<!--c-->", "ul", "li", "\"2\"", "li", "\"3\"", "p", "\"4\"",
+        ];
+        assert_eq!(describe_all(&document, document.descendants(body)), order);
+        let mut reversed = order.to_vec();
+        reversed.reverse();
+        assert_eq!(
+            describe_all(&document, document.descendants(body).rev()),
+            reversed
+        );
+
+        // The two ends meet in the middle without repeating a node.
+        let mut descendants = document.descendants(body);
+        let mut met = Vec::new();
+        while let Some(front) = descendants.next() {
+            met.push(front);
+            met.extend(descendants.next_back());
+        }
+        assert_eq!(met.len(), order.len());
+
+        let p = document.get_elements_by_tag_name("p")[1];
+        let text = document.first_child(p).unwrap();
+        assert_eq!(document.descendants(text).next(), None);
+    }
+
+    #[test]
+    fn iterates_over_ancestors_and_siblings() {
+        let (document, body) = document();
+        let ul = document.get_elements_by_tag_name("ul")[0];
+        assert_eq!(
+            describe_all(&document, document.ancestors(ul)),
+            ["div", "body", "html", "#document"]
+        );
+        let comment = document.previous_sibling(ul).unwrap();
+        assert_eq!(
+            describe_all(&document, document.preceding_siblings(ul)),
+            ["<!--c-->", "p"]
+        );
+        assert_eq!(
+            describe_all(&document, document.following_siblings(comment)),
+            ["ul"]
+        );
+        assert_eq!(document.following_siblings(ul).next(), None);
+        assert_eq!(document.ancestors(Document::ROOT).next(), None);
+        assert_eq!(
+            document.parent(document.first_child(body).unwrap()),
+            Some(body)
+        );
+    }
+
+    #[test]
+    fn what_to_show_selects_node_types() {
+        let (document, _) = document();
+        let shown = |what_to_show| {
+            describe_all(
+                &document,
+                document.node_iterator(Document::ROOT, what_to_show, accept_all),
+            )
+        };
+        assert_eq!(
+            shown(WhatToShow::TEXT | WhatToShow::COMMENT),
+            ["\"1\"", "<!--c-->", "\"2\"", "\"3\"", "\"4\""]
+        );
+        assert_eq!(
+            shown(WhatToShow::DOCUMENT | WhatToShow::DOCUMENT_TYPE),
+            ["#document", "!doctype"]
+        );
+        assert_eq!(shown(WhatToShow::ALL).len(), 16);
+        assert!(!WhatToShow::ELEMENT.contains(NodeType::TextNode));
+        assert!(WhatToShow::ALL.contains(NodeType::DocumentFragmentNode));
+    }
+
+    /// Skips `<ul>` but not its children, or rejects it and them.
+    fn filter_ul(result: FilterResult) -> impl FnMut(&Document, NodeId) -> FilterResult {
+        move |document, node| {
+            let is_ul = document[node]
+                .as_element()
+                .is_some_and(|element| element.local_name() == "ul");
+            if is_ul {
+                result
+            } else {
+                FilterResult::Accept
+            }
+        }
+    }
+
+    #[test]
+    fn tree_walkers_skip_or_reject_filtered_nodes() {
+        let (document, body) = document();
+        let skip = document.tree_walker(body, WhatToShow::ELEMENT, filter_ul(FilterResult::Skip));
+        assert_eq!(describe_all(&document, skip), ["div", "p", "li", "li", "p"]);
+        let reject =
+            document.tree_walker(body, WhatToShow::ELEMENT, filter_ul(FilterResult::Reject));
+        assert_eq!(describe_all(&document, reject), ["div", "p", "p"]);
+    }
+
+    #[test]
+    fn tree_walkers_move_around_the_tree() {
+        let (document, body) = document();
+        let div = document.first_child(body).unwrap();
+        let mut walker =
+            document.tree_walker(div, WhatToShow::ELEMENT, filter_ul(FilterResult::Skip));
+        assert_eq!(walker.root(), div);
+        assert_eq!(walker.parent_node(), None);
+        assert_eq!(walker.previous_node(), None);
+
+        // The skipped `<ul>` is looked through, so its items are children
+        // and siblings of the `<p>`.
+        let p = walker.first_child().unwrap();
+        assert_eq!(describe(&document, p), "p");
+        let li = walker.next_sibling().unwrap();
+        assert_eq!(describe(&document, li), "li");
+        assert_eq!(walker.parent_node(), Some(div));
+        let last = walker.last_child().unwrap();
+        assert_eq!(walker.previous_sibling(), Some(li));
+        assert_eq!(walker.previous_sibling(), Some(p));
+        assert_eq!(walker.previous_sibling(), None);
+        assert_eq!(walker.current_node(), p);
+        assert_eq!(walker.first_child(), None);
+
+        // Moves in tree order stay inside the root.
+        walker.set_current_node(last);
+        assert_eq!(walker.next_node(), None);
+        assert_eq!(walker.current_node(), last);
+        assert_eq!(walker.previous_node(), Some(li));
+        assert_eq!(walker.previous_node(), Some(p));
+        assert_eq!(walker.previous_node(), Some(div));
+        assert_eq!(walker.previous_node(), None);
+    }
+
+    #[test]
+    fn tree_walkers_return_to_the_root_from_outside_it() {
+        let (document, body) = document();
+        let div = document.first_child(body).unwrap();
+        let mut walker = document.tree_walker(div, WhatToShow::ALL, accept_all);
+        walker.set_current_node(body);
+        assert_eq!(walker.current_node(), body);
+        assert_eq!(
+            walker.first_child().map(|node| describe(&document, node)),
+            Some("div".to_string())
+        );
+    }
+
+    #[test]
+    fn node_iterators_go_back_and_forth() {
+        let (document, body) = document();
+        let ul = document.get_elements_by_tag_name("ul")[0];
+        let mut iterator = document.node_iterator(ul, WhatToShow::ALL, accept_all);
+        assert_eq!(iterator.previous_node(), None);
+        assert_eq!(iterator.next_node(), Some(ul));
+        let li = iterator.next_node().unwrap();
+        assert_eq!(iterator.reference_node(), li);
+        // Turning around returns the reference node again.
+        assert_eq!(iterator.previous_node(), Some(li));
+        assert_eq!(iterator.previous_node(), Some(ul));
+        assert_eq!(iterator.previous_node(), None);
+        assert_eq!(
+            describe_all(&document, iterator),
+            ["ul", "li", "\"2\"", "li", "\"3\""]
+        );
+
+        // Rejecting a node doesn't hide its descendants.
+        let rejected =
+            document.node_iterator(body, WhatToShow::ELEMENT, filter_ul(FilterResult::Reject));
+        assert_eq!(
+            describe_all(&document, rejected),
+            ["body", "div", "p", "li", "li", "p"]
+        );
+    }
+}
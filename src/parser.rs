@@ -1,6 +1,11 @@
-use crate::node::Node;
+use crate::error::{ErrorCode, ParseError};
+use crate::foreign;
+use crate::node::{Attribute, Document, Element, Namespace, NodeData, NodeId, QuirksMode};
+use crate::token::{self, Span, Tag, Token, DOCTYPE};
+use crate::tokenizer::{text_state_for, TokenSink, TokenSinkResult, Tokenizer};
 
-enum InsertionMode {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InsertionMode {
     Initial,
     BeforeHTML,
     BeforeHead,
@@ -26,7 +31,2943 @@ enum InsertionMode {
     AfterAfterFrameset,
 }
 
-struct Parser {
-    insetrion_mode: InsertionMode,
-    stack_of_open_elements: Vec<Node>,
+/// The tree construction stage. It receives tokens from a [`Tokenizer`] and
+/// builds a [`Document`] out of them.
+pub struct Parser {
+    document: Document,
+    insertion_mode: InsertionMode,
+    /// The mode to go back to after the text insertion mode.
+    original_insertion_mode: InsertionMode,
+    stack_of_open_elements: Vec<NodeId>,
+    /// The element a fragment is parsed in. It is a detached copy, as the
+    /// original belongs to another document.
+    context_element: Option<NodeId>,
+    head_element_pointer: Option<NodeId>,
+    form_element_pointer: Option<NodeId>,
+    active_formatting_elements: Vec<FormattingEntry>,
+    /// Set while content misplaced in a table is inserted in front of it.
+    foster_parenting: bool,
+    pending_table_character_tokens: Vec<(char, Span)>,
+    /// The modes `<template>` contents are parsed in, innermost last.
+    stack_of_template_insertion_modes: Vec<InsertionMode>,
+    frameset_ok: bool,
+    /// Whether `<noscript>` content is treated as raw text, as it is by
+    /// browsers that run scripts.
+    scripting: bool,
+    /// State the tokenizer should switch to after the current token.
+    tokenizer_state: TokenSinkResult,
+    /// Span of the token being processed, for reporting errors.
+    current_span: Span,
+    self_closing_flag_acknowledged: bool,
+    /// Set after `<pre>`, `<listing>` and `<textarea>`, whose first newline
+    /// is dropped.
+    ignore_next_line_feed: bool,
+    errors: Vec<ParseError>,
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Self {
+            document: Document::new(),
+            insertion_mode: InsertionMode::Initial,
+            original_insertion_mode: InsertionMode::Initial,
+            stack_of_open_elements: Vec::new(),
+            context_element: None,
+            head_element_pointer: None,
+            form_element_pointer: None,
+            active_formatting_elements: Vec::new(),
+            foster_parenting: false,
+            pending_table_character_tokens: Vec::new(),
+            stack_of_template_insertion_modes: Vec::new(),
+            frameset_ok: true,
+            scripting: false,
+            tokenizer_state: TokenSinkResult::Continue,
+            current_span: Span::default(),
+            self_closing_flag_acknowledged: false,
+            ignore_next_line_feed: false,
+            errors: Vec::new(),
+        }
+    }
+
+    /// Whether `<noscript>` content is parsed as raw text, as it is by
+    /// browsers that run scripts, rather than as markup. Off by default.
+    pub fn set_scripting(&mut self, scripting: bool) {
+        self.scripting = scripting;
+    }
+
+    /// Parses `input` as a whole document with a parser that hasn't parsed
+    /// anything yet. See [`parse_document`].
+    pub fn parse_document(self, input: String) -> Self {
+        let mut tokenizer = Tokenizer::new(input, self);
+        tokenizer.run();
+        tokenizer.into_sink()
+    }
+
+    /// Parses `input` as the contents of `context_element` with a parser
+    /// that hasn't parsed anything yet. See [`parse_fragment`].
+    pub fn parse_fragment(
+        mut self,
+        document: &Document,
+        context_element: NodeId,
+        input: String,
+    ) -> Self {
+        self.start_fragment(document, context_element);
+        let state = self.fragment_tokenizer_state();
+        let mut tokenizer = Tokenizer::new(input, self);
+        tokenizer.switch_to_text_state(state);
+        tokenizer.run();
+        let mut parser = tokenizer.into_sink();
+        parser.finish_fragment();
+        parser
+    }
+
+    /// Sets the parser up for the contents of `context_element`, which
+    /// belongs to `context_document`. The result is a document fragment.
+    fn start_fragment(&mut self, context_document: &Document, context_element: NodeId) {
+        self.document = Document::new_fragment();
+        self.document.quirks_mode = context_document.quirks_mode;
+
+        let copy_element = |parser: &mut Self, node: NodeId| {
+            let element = context_document[node]
+                .as_element()
+                .expect("the context of a fragment is an element");
+            parser.document.create_element(
+                element.local_name.clone(),
+                element.namespace,
+                element.attributes.clone(),
+            )
+        };
+        let context = copy_element(self, context_element);
+        self.context_element = Some(context);
+
+        let html = self
+            .document
+            .create_element("html".to_string(), Namespace::HTML, Vec::new());
+        self.document.append(Document::ROOT, html);
+        self.stack_of_open_elements.push(html);
+
+        if self.tag_name(context) == "template" {
+            self.stack_of_template_insertion_modes
+                .push(InsertionMode::InTemplate);
+        }
+        self.reset_the_insertion_mode_appropriately();
+
+        let mut ancestor = Some(context_element);
+        while let Some(node) = ancestor {
+            let is_form = context_document[node]
+                .as_element()
+                .is_some_and(|element| element.is(Namespace::HTML, "form"));
+            if is_form {
+                self.form_element_pointer = Some(copy_element(self, node));
+                break;
+            }
+            ancestor = context_document.parent(node);
+        }
+    }
+
+    /// The state the tokenizer starts in for the contents of the context
+    /// element.
+    fn fragment_tokenizer_state(&self) -> TokenSinkResult {
+        match self.context_element {
+            Some(context) => match self.tag_name(context) {
+                "noscript" if self.scripting => TokenSinkResult::RAWTEXT,
+                tag_name => text_state_for(tag_name),
+            },
+            None => TokenSinkResult::Continue,
+        }
+    }
+
+    /// Replaces the contents of the fragment with the children of the root
+    /// element, leaving out anything inserted next to it.
+    fn finish_fragment(&mut self) {
+        // The root element is created before anything else is inserted.
+        let html = self
+            .document
+            .first_child(Document::ROOT)
+            .expect("a fragment has a root element");
+        while let Some(child) = self.document.first_child(Document::ROOT) {
+            self.document.detach(child);
+        }
+        self.document.move_children(html, Document::ROOT);
+    }
+
+    /// Parse errors reported so far, in the order they were encountered.
+    pub fn errors(&self) -> &[ParseError] {
+        &self.errors
+    }
+
+    pub fn document(&self) -> &Document {
+        &self.document
+    }
+
+    pub fn into_document(self) -> Document {
+        self.document
+    }
+
+    fn parse_error(&mut self, code: ErrorCode) {
+        self.errors.push(ParseError {
+            code,
+            position: self.current_span.start,
+        });
+    }
+
+    fn current_node(&self) -> NodeId {
+        *self
+            .stack_of_open_elements
+            .last()
+            .expect("the stack of open elements is only empty once parsing stopped")
+    }
+
+    /// The tag name of an HTML element. Foreign elements have none here, so
+    /// they never match the names the insertion modes look for.
+    fn tag_name(&self, node: NodeId) -> &str {
+        let element = self.element(node);
+        match element.namespace {
+            Namespace::HTML => &element.local_name,
+            _ => "",
+        }
+    }
+
+    /// The parser only keeps track of elements, so every node it asks about
+    /// is one.
+    fn element(&self, node: NodeId) -> &Element {
+        self.document[node]
+            .as_element()
+            .expect("the parser only keeps track of elements")
+    }
+
+    /// The current node, or the context element when parsing a fragment and
+    /// only the root element is open.
+    fn adjusted_current_node(&self) -> Option<NodeId> {
+        match (self.context_element, self.stack_of_open_elements.as_slice()) {
+            (Some(context), [_]) => Some(context),
+            (_, stack) => stack.last().copied(),
+        }
+    }
+
+    fn is_mathml_text_integration_point(&self, node: NodeId) -> bool {
+        let node = self.element(node);
+        node.namespace == Namespace::MathML
+            && matches!(
+                node.local_name.as_str(),
+                "mi" | "mo" | "mn" | "ms" | "mtext"
+            )
+    }
+
+    /// Foreign elements whose content is parsed as HTML.
+    fn is_html_integration_point(&self, node: NodeId) -> bool {
+        let node = self.element(node);
+        match node.namespace {
+            Namespace::MathML => {
+                node.local_name == "annotation-xml"
+                    && node.attributes.iter().any(|attribute| {
+                        attribute.local_name == "encoding"
+                            && (attribute.value.eq_ignore_ascii_case("text/html")
+                                || attribute
+                                    .value
+                                    .eq_ignore_ascii_case("application/xhtml+xml"))
+                    })
+            }
+            Namespace::SVG => {
+                matches!(node.local_name.as_str(), "foreignObject" | "desc" | "title")
+            }
+            _ => false,
+        }
+    }
+
+    fn acknowledge_self_closing_flag(&mut self) {
+        self.self_closing_flag_acknowledged = true;
+    }
+
+    /// The appropriate place for inserting a node, as the parent and the child
+    /// to insert before. The target defaults to the current node.
+    fn appropriate_place_for_inserting_a_node(
+        &self,
+        override_target: Option<NodeId>,
+    ) -> (NodeId, Option<NodeId>) {
+        let target = override_target.unwrap_or_else(|| self.current_node());
+        let (parent, before) = if self.foster_parenting
+            && matches!(
+                self.tag_name(target),
+                "table" | "tbody" | "tfoot" | "thead" | "tr"
+            ) {
+            self.foster_parenting_location()
+        } else {
+            (target, None)
+        };
+        let template_contents = self.document[parent]
+            .as_element()
+            .and_then(|element| element.template_contents);
+        match template_contents {
+            Some(contents) => (contents, None),
+            None => (parent, before),
+        }
+    }
+
+    fn foster_parenting_location(&self) -> (NodeId, Option<NodeId>) {
+        let last_position = |tag_name: &str| {
+            self.stack_of_open_elements
+                .iter()
+                .rposition(|&node| self.tag_name(node) == tag_name)
+        };
+        let last_table = last_position("table");
+        match (last_position("template"), last_table) {
+            (Some(template), Some(table)) if template > table => {
+                (self.stack_of_open_elements[template], None)
+            }
+            (Some(template), None) => (self.stack_of_open_elements[template], None),
+            (_, None) => (self.stack_of_open_elements[0], None),
+            (_, Some(table)) => {
+                let table_element = self.stack_of_open_elements[table];
+                match self.document.parent(table_element) {
+                    Some(parent) => (parent, Some(table_element)),
+                    None => (self.stack_of_open_elements[table - 1], None),
+                }
+            }
+        }
+    }
+
+    fn insert_character(&mut self, c: char) {
+        let (parent, before) = self.appropriate_place_for_inserting_a_node(None);
+        if parent == Document::ROOT {
+            return;
+        }
+        let previous = match before {
+            Some(before) => self.document.previous_sibling(before),
+            None => self.document.last_child(parent),
+        };
+        match previous.map(|node| &mut self.document.node_mut(node).data) {
+            Some(NodeData::Text(data)) => data.push(c),
+            _ => {
+                let text = self.document.create_text(c.to_string());
+                self.document.insert(parent, text, before);
+            }
+        }
+    }
+
+    fn insert_comment(&mut self, data: String) {
+        let (parent, before) = self.appropriate_place_for_inserting_a_node(None);
+        let comment = self.document.create_comment(data);
+        self.document.insert(parent, comment, before);
+    }
+
+    fn insert_comment_into(&mut self, data: String, parent: NodeId) {
+        let comment = self.document.create_comment(data);
+        self.document.append(parent, comment);
+    }
+
+    /// Creates an element for a start tag without inserting it, which is what
+    /// recreated formatting elements start as.
+    fn create_html_element(&mut self, tag: &Tag) -> NodeId {
+        self.document.create_element(
+            tag.tag_name.clone(),
+            Namespace::HTML,
+            attributes_of(tag.attributes.clone()),
+        )
+    }
+
+    fn insert_html_element(&mut self, tag: Tag) -> NodeId {
+        self.insert_foreign_element(tag.tag_name, Namespace::HTML, attributes_of(tag.attributes))
+    }
+
+    fn insert_foreign_element(
+        &mut self,
+        local_name: String,
+        namespace: Namespace,
+        attributes: Vec<Attribute>,
+    ) -> NodeId {
+        let (parent, before) = self.appropriate_place_for_inserting_a_node(None);
+        let is_template = namespace == Namespace::HTML && local_name == "template";
+        let element = self
+            .document
+            .create_element(local_name, namespace, attributes);
+        if is_template {
+            let contents = self.document.create_document_fragment();
+            if let Some(template) = self.document.node_mut(element).as_element_mut() {
+                template.template_contents = Some(contents);
+            }
+        }
+        self.document.insert(parent, element, before);
+        self.stack_of_open_elements.push(element);
+        element
+    }
+
+    /// Inserts a MathML or SVG element, restoring the case of the names the
+    /// tokenizer lowercased. Foreign elements may be self-closing.
+    fn insert_foreign_start_tag(&mut self, mut tag: Tag, namespace: Namespace) {
+        let mut attributes = attributes_of(tag.attributes);
+        match namespace {
+            Namespace::MathML => foreign::adjust_mathml_attributes(&mut attributes),
+            Namespace::SVG => {
+                foreign::adjust_svg_tag_name(&mut tag.tag_name);
+                foreign::adjust_svg_attributes(&mut attributes);
+            }
+            _ => {}
+        }
+        foreign::adjust_foreign_attributes(&mut attributes);
+        let self_closing = tag.self_closing;
+        self.insert_foreign_element(tag.tag_name, namespace, attributes);
+        if self_closing {
+            // A self-closing SVG `<script>` would run here, but scripts
+            // aren't run.
+            self.stack_of_open_elements.pop();
+            self.acknowledge_self_closing_flag();
+        }
+    }
+
+    /// Inserts an element without attributes for a tag the input left out.
+    fn insert_implied_element(&mut self, tag_name: &str) -> NodeId {
+        self.insert_html_element(Tag {
+            tag_name: tag_name.to_string(),
+            ..Default::default()
+        })
+    }
+
+    /// The generic raw text and RCDATA element parsing algorithms.
+    fn parse_text_element(&mut self, tag: Tag, tokenizer_state: TokenSinkResult) {
+        self.insert_html_element(tag);
+        self.tokenizer_state = tokenizer_state;
+        self.original_insertion_mode = self.insertion_mode;
+        self.insertion_mode = InsertionMode::Text;
+    }
+
+    fn stop_parsing(&mut self) {
+        self.stack_of_open_elements.clear();
+    }
+
+    fn stack_contains(&self, tag_name: &str) -> bool {
+        self.stack_of_open_elements
+            .iter()
+            .any(|&node| self.tag_name(node) == tag_name)
+    }
+
+    /// Whether an element that must be closed explicitly is still open, which
+    /// is an error at the end of the body.
+    fn has_unclosed_elements(&self) -> bool {
+        self.stack_of_open_elements.iter().any(|&node| {
+            !matches!(
+                self.tag_name(node),
+                "dd" | "dt"
+                    | "li"
+                    | "optgroup"
+                    | "option"
+                    | "p"
+                    | "rb"
+                    | "rp"
+                    | "rt"
+                    | "rtc"
+                    | "tbody"
+                    | "td"
+                    | "tfoot"
+                    | "th"
+                    | "thead"
+                    | "tr"
+                    | "body"
+                    | "html"
+            )
+        })
+    }
+
+    /// Copies the attributes of a repeated `<html>` or `<body>` start tag to
+    /// the existing element, unless it already has them.
+    fn add_missing_attributes(&mut self, element: NodeId, tag: Tag) {
+        self.document.change_attributes(element, |element| {
+            for attribute in attributes_of(tag.attributes) {
+                if !element
+                    .attributes
+                    .iter()
+                    .any(|a| a.local_name == attribute.local_name)
+                {
+                    element.attributes.push(attribute);
+                }
+            }
+        });
+    }
+
+    fn has_an_element_in_specific_scope(
+        &self,
+        is_target: impl Fn(&Self, NodeId) -> bool,
+        is_boundary: impl Fn(&Self, NodeId) -> bool,
+    ) -> bool {
+        for &node in self.stack_of_open_elements.iter().rev() {
+            if is_target(self, node) {
+                return true;
+            }
+            if is_boundary(self, node) {
+                return false;
+            }
+        }
+        false
+    }
+
+    fn is_scope_boundary(&self, node: NodeId) -> bool {
+        matches!(
+            self.tag_name(node),
+            "applet"
+                | "caption"
+                | "html"
+                | "table"
+                | "td"
+                | "th"
+                | "marquee"
+                | "object"
+                | "template"
+        ) || self.is_foreign_scope_boundary(node)
+    }
+
+    /// The MathML and SVG elements that are both scope boundaries and special.
+    fn is_foreign_scope_boundary(&self, node: NodeId) -> bool {
+        let node = self.element(node);
+        match node.namespace {
+            Namespace::MathML => matches!(
+                node.local_name.as_str(),
+                "mi" | "mo" | "mn" | "ms" | "mtext" | "annotation-xml"
+            ),
+            Namespace::SVG => {
+                matches!(node.local_name.as_str(), "foreignObject" | "desc" | "title")
+            }
+            _ => false,
+        }
+    }
+
+    fn has_an_element_in_scope(&self, tag_name: &str) -> bool {
+        self.has_an_element_in_specific_scope(
+            |parser, node| parser.tag_name(node) == tag_name,
+            Self::is_scope_boundary,
+        )
+    }
+
+    fn has_node_in_scope(&self, target: NodeId) -> bool {
+        self.has_an_element_in_specific_scope(|_, node| node == target, Self::is_scope_boundary)
+    }
+
+    fn has_an_element_in_list_item_scope(&self, tag_name: &str) -> bool {
+        self.has_an_element_in_specific_scope(
+            |parser, node| parser.tag_name(node) == tag_name,
+            |parser, node| {
+                parser.is_scope_boundary(node) || matches!(parser.tag_name(node), "ol" | "ul")
+            },
+        )
+    }
+
+    fn has_an_element_in_button_scope(&self, tag_name: &str) -> bool {
+        self.has_an_element_in_specific_scope(
+            |parser, node| parser.tag_name(node) == tag_name,
+            |parser, node| parser.is_scope_boundary(node) || parser.tag_name(node) == "button",
+        )
+    }
+
+    fn has_an_element_in_table_scope(&self, tag_name: &str) -> bool {
+        self.has_an_element_in_specific_scope(
+            |parser, node| parser.tag_name(node) == tag_name,
+            |parser, node| matches!(parser.tag_name(node), "html" | "table" | "template"),
+        )
+    }
+
+    /// Unlike the other scopes, everything but `<optgroup>` and `<option>`
+    /// is a boundary.
+    fn has_an_element_in_select_scope(&self, tag_name: &str) -> bool {
+        self.has_an_element_in_specific_scope(
+            |parser, node| parser.tag_name(node) == tag_name,
+            |parser, node| !matches!(parser.tag_name(node), "optgroup" | "option"),
+        )
+    }
+
+    fn is_special(&self, node: NodeId) -> bool {
+        matches!(
+            self.tag_name(node),
+            "address"
+                | "applet"
+                | "area"
+                | "article"
+                | "aside"
+                | "base"
+                | "basefont"
+                | "bgsound"
+                | "blockquote"
+                | "body"
+                | "br"
+                | "button"
+                | "caption"
+                | "center"
+                | "col"
+                | "colgroup"
+                | "dd"
+                | "details"
+                | "dir"
+                | "div"
+                | "dl"
+                | "dt"
+                | "embed"
+                | "fieldset"
+                | "figcaption"
+                | "figure"
+                | "footer"
+                | "form"
+                | "frame"
+                | "frameset"
+                | "h1"
+                | "h2"
+                | "h3"
+                | "h4"
+                | "h5"
+                | "h6"
+                | "head"
+                | "header"
+                | "hgroup"
+                | "hr"
+                | "html"
+                | "iframe"
+                | "img"
+                | "input"
+                | "keygen"
+                | "li"
+                | "link"
+                | "listing"
+                | "main"
+                | "marquee"
+                | "menu"
+                | "meta"
+                | "nav"
+                | "noembed"
+                | "noframes"
+                | "noscript"
+                | "object"
+                | "ol"
+                | "p"
+                | "param"
+                | "plaintext"
+                | "pre"
+                | "script"
+                | "search"
+                | "section"
+                | "select"
+                | "source"
+                | "style"
+                | "summary"
+                | "table"
+                | "tbody"
+                | "td"
+                | "template"
+                | "textarea"
+                | "tfoot"
+                | "th"
+                | "thead"
+                | "title"
+                | "tr"
+                | "track"
+                | "ul"
+                | "wbr"
+                | "xmp"
+        ) || self.is_foreign_scope_boundary(node)
+    }
+
+    fn generate_implied_end_tags(&mut self) {
+        self.generate_implied_end_tags_except("");
+    }
+
+    fn generate_implied_end_tags_except(&mut self, excluded: &str) {
+        while let Some(&node) = self.stack_of_open_elements.last() {
+            let name = self.tag_name(node);
+            if name == excluded
+                || !matches!(
+                    name,
+                    "dd" | "dt" | "li" | "optgroup" | "option" | "p" | "rb" | "rp" | "rt" | "rtc"
+                )
+            {
+                break;
+            }
+            self.stack_of_open_elements.pop();
+        }
+    }
+
+    fn generate_all_implied_end_tags_thoroughly(&mut self) {
+        while let Some(&node) = self.stack_of_open_elements.last() {
+            if !matches!(
+                self.tag_name(node),
+                "caption"
+                    | "colgroup"
+                    | "dd"
+                    | "dt"
+                    | "li"
+                    | "optgroup"
+                    | "option"
+                    | "p"
+                    | "rb"
+                    | "rp"
+                    | "rt"
+                    | "rtc"
+                    | "tbody"
+                    | "td"
+                    | "tfoot"
+                    | "th"
+                    | "thead"
+                    | "tr"
+            ) {
+                break;
+            }
+            self.stack_of_open_elements.pop();
+        }
+    }
+
+    /// Pops elements until one named `tag_name` has been popped.
+    fn pop_until(&mut self, tag_name: &str) {
+        while let Some(node) = self.stack_of_open_elements.pop() {
+            if self.tag_name(node) == tag_name {
+                break;
+            }
+        }
+    }
+
+    fn pop_until_node(&mut self, target: NodeId) {
+        while let Some(node) = self.stack_of_open_elements.pop() {
+            if node == target {
+                break;
+            }
+        }
+    }
+
+    fn clear_the_stack_back_to_a_table_context(&mut self) {
+        while !matches!(
+            self.tag_name(self.current_node()),
+            "table" | "template" | "html"
+        ) {
+            self.stack_of_open_elements.pop();
+        }
+    }
+
+    fn clear_the_stack_back_to_a_table_body_context(&mut self) {
+        while !matches!(
+            self.tag_name(self.current_node()),
+            "tbody" | "tfoot" | "thead" | "template" | "html"
+        ) {
+            self.stack_of_open_elements.pop();
+        }
+    }
+
+    fn clear_the_stack_back_to_a_table_row_context(&mut self) {
+        while !matches!(
+            self.tag_name(self.current_node()),
+            "tr" | "template" | "html"
+        ) {
+            self.stack_of_open_elements.pop();
+        }
+    }
+
+    fn reset_the_insertion_mode_appropriately(&mut self) {
+        for (index, &node) in self.stack_of_open_elements.iter().enumerate().rev() {
+            let last = index == 0;
+            let node = match self.context_element {
+                Some(context) if last => context,
+                _ => node,
+            };
+            self.insertion_mode = match self.tag_name(node) {
+                "select" => self.select_insertion_mode(index),
+                "td" | "th" if !last => InsertionMode::InCell,
+                "tr" => InsertionMode::InRow,
+                "tbody" | "thead" | "tfoot" => InsertionMode::InTableBody,
+                "caption" => InsertionMode::InCaption,
+                "colgroup" => InsertionMode::InColumnGroup,
+                "table" => InsertionMode::InTable,
+                "template" => *self
+                    .stack_of_template_insertion_modes
+                    .last()
+                    .expect("an open template has a template insertion mode"),
+                "head" if !last => InsertionMode::InHead,
+                "body" => InsertionMode::InBody,
+                "frameset" => InsertionMode::InFrameset,
+                "html" if self.head_element_pointer.is_none() => InsertionMode::BeforeHead,
+                "html" => InsertionMode::AfterHead,
+                _ if last => InsertionMode::InBody,
+                _ => continue,
+            };
+            return;
+        }
+    }
+
+    /// A `<select>` inside a table is parsed differently, unless there is a
+    /// template between the two.
+    fn select_insertion_mode(&self, index: usize) -> InsertionMode {
+        for &ancestor in self.stack_of_open_elements[..index].iter().rev() {
+            match self.tag_name(ancestor) {
+                "template" => break,
+                "table" => return InsertionMode::InSelectTable,
+                _ => {}
+            }
+        }
+        InsertionMode::InSelect
+    }
+
+    /// The end of a `<template>`, whether through its end tag or the end of
+    /// the input.
+    fn close_the_template(&mut self) {
+        self.pop_until("template");
+        self.clear_the_list_of_active_formatting_elements_up_to_the_last_marker();
+        self.stack_of_template_insertion_modes.pop();
+        self.reset_the_insertion_mode_appropriately();
+    }
+
+    /// Switches the current template insertion mode to `mode` and
+    /// reprocesses `token` in it.
+    fn switch_template_insertion_mode(&mut self, mode: InsertionMode, token: Token) {
+        self.stack_of_template_insertion_modes.pop();
+        self.stack_of_template_insertion_modes.push(mode);
+        self.insertion_mode = mode;
+        self.process(token);
+    }
+
+    fn close_p_element(&mut self) {
+        self.generate_implied_end_tags_except("p");
+        if self.tag_name(self.current_node()) != "p" {
+            self.parse_error(ErrorCode::MissingEndTag);
+        }
+        self.pop_until("p");
+    }
+
+    fn close_p_element_in_button_scope(&mut self) {
+        if self.has_an_element_in_button_scope("p") {
+            self.close_p_element();
+        }
+    }
+
+    /// What most end tags of block elements do: close the element if it is
+    /// in scope, closing anything left open inside it as well.
+    fn close_element_in_scope(&mut self, tag_name: &str) {
+        if !self.has_an_element_in_scope(tag_name) {
+            self.parse_error(ErrorCode::UnexpectedEndTag);
+            return;
+        }
+        self.generate_implied_end_tags();
+        if self.tag_name(self.current_node()) != tag_name {
+            self.parse_error(ErrorCode::MissingEndTag);
+        }
+        self.pop_until(tag_name);
+    }
+
+    /// Sends tokens inside SVG and MathML to the rules for foreign content,
+    /// except where those elements contain HTML.
+    fn tree_construction_dispatcher(&mut self, token: Token) {
+        let in_html_content = match self.adjusted_current_node() {
+            None => true,
+            Some(node) => {
+                self.element(node).namespace == Namespace::HTML
+                    || (self.is_mathml_text_integration_point(node)
+                        && match &token {
+                            Token::StartTag(tag) => {
+                                !matches!(tag.tag_name.as_str(), "mglyph" | "malignmark")
+                            }
+                            Token::Character(..) => true,
+                            _ => false,
+                        })
+                    || (self.element(node).is(Namespace::MathML, "annotation-xml")
+                        && matches!(&token, Token::StartTag(tag) if tag.tag_name == "svg"))
+                    || (self.is_html_integration_point(node)
+                        && matches!(token, Token::StartTag(_) | Token::Character(..)))
+                    || matches!(token, Token::EndOfFile(_))
+            }
+        };
+        if in_html_content {
+            self.process(token);
+        } else {
+            self.in_foreign_content(token);
+        }
+    }
+
+    /// Handles `token` according to the current insertion mode.
+    fn process(&mut self, token: Token) {
+        self.process_using(self.insertion_mode, token);
+    }
+
+    fn process_using(&mut self, mode: InsertionMode, token: Token) {
+        match mode {
+            InsertionMode::Initial => self.initial(token),
+            InsertionMode::BeforeHTML => self.before_html(token),
+            InsertionMode::BeforeHead => self.before_head(token),
+            InsertionMode::InHead => self.in_head(token),
+            InsertionMode::InHeadNoScript => self.in_head_noscript(token),
+            InsertionMode::AfterHead => self.after_head(token),
+            InsertionMode::Text => self.text(token),
+            InsertionMode::InTable => self.in_table(token),
+            InsertionMode::InTableText => self.in_table_text(token),
+            InsertionMode::InCaption => self.in_caption(token),
+            InsertionMode::InColumnGroup => self.in_column_group(token),
+            InsertionMode::InTableBody => self.in_table_body(token),
+            InsertionMode::InRow => self.in_row(token),
+            InsertionMode::InCell => self.in_cell(token),
+            InsertionMode::InSelect => self.in_select(token),
+            InsertionMode::InSelectTable => self.in_select_table(token),
+            InsertionMode::InTemplate => self.in_template(token),
+            InsertionMode::AfterBody => self.after_body(token),
+            InsertionMode::InFrameset => self.in_frameset(token),
+            InsertionMode::AfterFrameset => self.after_frameset(token),
+            InsertionMode::AfterAfterBody => self.after_after_body(token),
+            InsertionMode::AfterAfterFrameset => self.after_after_frameset(token),
+            InsertionMode::InBody => self.in_body(token),
+        }
+    }
+
+    fn initial(&mut self, token: Token) {
+        match token {
+            Token::Character(c, _) if is_whitespace(c) => {}
+            Token::Comment(data, _) => self.insert_comment_into(data, Document::ROOT),
+            Token::DOCTYPE(doctype) => {
+                if doctype.name != "html"
+                    || doctype.public_identifier.is_some()
+                    || doctype
+                        .system_identifier
+                        .as_ref()
+                        .is_some_and(|id| id != "about:legacy-compat")
+                {
+                    self.parse_error(ErrorCode::NonConformingDoctype);
+                }
+                self.document.quirks_mode = quirks_mode(&doctype);
+                let node = self.document.create_doctype(
+                    doctype.name,
+                    doctype.public_identifier.unwrap_or_default(),
+                    doctype.system_identifier.unwrap_or_default(),
+                );
+                self.document.append(Document::ROOT, node);
+                self.insertion_mode = InsertionMode::BeforeHTML;
+            }
+            token => {
+                self.parse_error(ErrorCode::MissingDoctype);
+                self.document.quirks_mode = QuirksMode::Quirks;
+                self.insertion_mode = InsertionMode::BeforeHTML;
+                self.process(token);
+            }
+        }
+    }
+
+    fn before_html(&mut self, token: Token) {
+        match token {
+            Token::DOCTYPE(_) => self.parse_error(ErrorCode::UnexpectedDoctype),
+            Token::Comment(data, _) => self.insert_comment_into(data, Document::ROOT),
+            Token::Character(c, _) if is_whitespace(c) => {}
+            Token::StartTag(tag) if tag.tag_name == "html" => {
+                let html = self.create_html_element(&tag);
+                self.document.append(Document::ROOT, html);
+                self.stack_of_open_elements.push(html);
+                self.insertion_mode = InsertionMode::BeforeHead;
+            }
+            Token::EndTag(ref tag)
+                if !matches!(tag.tag_name.as_str(), "head" | "body" | "html" | "br") =>
+            {
+                self.parse_error(ErrorCode::UnexpectedEndTag);
+            }
+            token => {
+                let html =
+                    self.document
+                        .create_element("html".to_string(), Namespace::HTML, Vec::new());
+                self.document.append(Document::ROOT, html);
+                self.stack_of_open_elements.push(html);
+                self.insertion_mode = InsertionMode::BeforeHead;
+                self.process(token);
+            }
+        }
+    }
+
+    fn before_head(&mut self, token: Token) {
+        match token {
+            Token::Character(c, _) if is_whitespace(c) => {}
+            Token::Comment(data, _) => self.insert_comment(data),
+            Token::DOCTYPE(_) => self.parse_error(ErrorCode::UnexpectedDoctype),
+            Token::StartTag(ref tag) if tag.tag_name == "html" => {
+                self.process_using(InsertionMode::InBody, token);
+            }
+            Token::StartTag(tag) if tag.tag_name == "head" => {
+                let head = self.insert_html_element(tag);
+                self.head_element_pointer = Some(head);
+                self.insertion_mode = InsertionMode::InHead;
+            }
+            Token::EndTag(ref tag)
+                if !matches!(tag.tag_name.as_str(), "head" | "body" | "html" | "br") =>
+            {
+                self.parse_error(ErrorCode::UnexpectedEndTag);
+            }
+            token => {
+                let head = self.insert_implied_element("head");
+                self.head_element_pointer = Some(head);
+                self.insertion_mode = InsertionMode::InHead;
+                self.process(token);
+            }
+        }
+    }
+
+    fn in_head(&mut self, token: Token) {
+        match token {
+            Token::Character(c, _) if is_whitespace(c) => self.insert_character(c),
+            Token::Comment(data, _) => self.insert_comment(data),
+            Token::DOCTYPE(_) => self.parse_error(ErrorCode::UnexpectedDoctype),
+            Token::StartTag(ref tag) if tag.tag_name == "html" => {
+                self.process_using(InsertionMode::InBody, token);
+            }
+            Token::StartTag(tag)
+                if matches!(
+                    tag.tag_name.as_str(),
+                    "base" | "basefont" | "bgsound" | "link" | "meta"
+                ) =>
+            {
+                self.insert_html_element(tag);
+                self.stack_of_open_elements.pop();
+                self.acknowledge_self_closing_flag();
+            }
+            Token::StartTag(tag) if tag.tag_name == "title" => {
+                self.parse_text_element(tag, TokenSinkResult::RCDATA);
+            }
+            Token::StartTag(tag)
+                if (tag.tag_name == "noscript" && self.scripting)
+                    || matches!(tag.tag_name.as_str(), "noframes" | "style") =>
+            {
+                self.parse_text_element(tag, TokenSinkResult::RAWTEXT);
+            }
+            Token::StartTag(tag) if tag.tag_name == "noscript" => {
+                self.insert_html_element(tag);
+                self.insertion_mode = InsertionMode::InHeadNoScript;
+            }
+            Token::StartTag(tag) if tag.tag_name == "script" => {
+                // Scripts aren't run, so there is nothing to prepare.
+                self.parse_text_element(tag, TokenSinkResult::ScriptData);
+            }
+            Token::EndTag(ref tag) if tag.tag_name == "head" => {
+                self.stack_of_open_elements.pop();
+                self.insertion_mode = InsertionMode::AfterHead;
+            }
+            Token::StartTag(tag) if tag.tag_name == "template" => {
+                self.insert_html_element(tag);
+                self.active_formatting_elements
+                    .push(FormattingEntry::Marker);
+                self.frameset_ok = false;
+                self.insertion_mode = InsertionMode::InTemplate;
+                self.stack_of_template_insertion_modes
+                    .push(InsertionMode::InTemplate);
+            }
+            Token::EndTag(ref tag) if tag.tag_name == "template" => {
+                if !self.stack_contains("template") {
+                    self.parse_error(ErrorCode::UnexpectedEndTag);
+                    return;
+                }
+                self.generate_all_implied_end_tags_thoroughly();
+                if self.tag_name(self.current_node()) != "template" {
+                    self.parse_error(ErrorCode::MissingEndTag);
+                }
+                self.close_the_template();
+            }
+            Token::StartTag(ref tag) if tag.tag_name == "head" => {
+                self.parse_error(ErrorCode::UnexpectedStartTag);
+            }
+            Token::EndTag(ref tag) if !matches!(tag.tag_name.as_str(), "body" | "html" | "br") => {
+                self.parse_error(ErrorCode::UnexpectedEndTag);
+            }
+            token => {
+                self.stack_of_open_elements.pop();
+                self.insertion_mode = InsertionMode::AfterHead;
+                self.process(token);
+            }
+        }
+    }
+
+    fn in_head_noscript(&mut self, token: Token) {
+        match token {
+            Token::DOCTYPE(_) => self.parse_error(ErrorCode::UnexpectedDoctype),
+            Token::StartTag(ref tag) if tag.tag_name == "html" => {
+                self.process_using(InsertionMode::InBody, token);
+            }
+            Token::EndTag(ref tag) if tag.tag_name == "noscript" => {
+                self.stack_of_open_elements.pop();
+                self.insertion_mode = InsertionMode::InHead;
+            }
+            Token::Character(c, _) if is_whitespace(c) => {
+                self.process_using(InsertionMode::InHead, token);
+            }
+            Token::Comment(..) => self.process_using(InsertionMode::InHead, token),
+            Token::StartTag(ref tag)
+                if matches!(
+                    tag.tag_name.as_str(),
+                    "basefont" | "bgsound" | "link" | "meta" | "noframes" | "style"
+                ) =>
+            {
+                self.process_using(InsertionMode::InHead, token);
+            }
+            Token::StartTag(ref tag) if matches!(tag.tag_name.as_str(), "head" | "noscript") => {
+                self.parse_error(ErrorCode::UnexpectedStartTag);
+            }
+            Token::EndTag(ref tag) if tag.tag_name != "br" => {
+                self.parse_error(ErrorCode::UnexpectedEndTag);
+            }
+            token => {
+                self.parse_error(unexpected(&token));
+                self.stack_of_open_elements.pop();
+                self.insertion_mode = InsertionMode::InHead;
+                self.process(token);
+            }
+        }
+    }
+
+    fn after_head(&mut self, token: Token) {
+        match token {
+            Token::Character(c, _) if is_whitespace(c) => self.insert_character(c),
+            Token::Comment(data, _) => self.insert_comment(data),
+            Token::DOCTYPE(_) => self.parse_error(ErrorCode::UnexpectedDoctype),
+            Token::StartTag(ref tag) if tag.tag_name == "html" => {
+                self.process_using(InsertionMode::InBody, token);
+            }
+            Token::StartTag(tag) if tag.tag_name == "body" => {
+                self.insert_html_element(tag);
+                self.frameset_ok = false;
+                self.insertion_mode = InsertionMode::InBody;
+            }
+            Token::StartTag(tag) if tag.tag_name == "frameset" => {
+                self.insert_html_element(tag);
+                self.insertion_mode = InsertionMode::InFrameset;
+            }
+            Token::StartTag(ref tag)
+                if matches!(
+                    tag.tag_name.as_str(),
+                    "base"
+                        | "basefont"
+                        | "bgsound"
+                        | "link"
+                        | "meta"
+                        | "noframes"
+                        | "script"
+                        | "style"
+                        | "template"
+                        | "title"
+                ) =>
+            {
+                self.parse_error(ErrorCode::UnexpectedStartTag);
+                let head = self
+                    .head_element_pointer
+                    .expect("the head element exists after the head");
+                self.stack_of_open_elements.push(head);
+                self.process_using(InsertionMode::InHead, token);
+                self.stack_of_open_elements.retain(|&node| node != head);
+            }
+            Token::EndTag(ref tag) if tag.tag_name == "template" => {
+                self.process_using(InsertionMode::InHead, token);
+            }
+            Token::StartTag(ref tag) if tag.tag_name == "head" => {
+                self.parse_error(ErrorCode::UnexpectedStartTag);
+            }
+            Token::EndTag(ref tag) if !matches!(tag.tag_name.as_str(), "body" | "html" | "br") => {
+                self.parse_error(ErrorCode::UnexpectedEndTag);
+            }
+            token => {
+                self.insert_implied_element("body");
+                self.insertion_mode = InsertionMode::InBody;
+                self.process(token);
+            }
+        }
+    }
+
+    fn in_body(&mut self, token: Token) {
+        match token {
+            Token::Character('\0', _) => self.parse_error(ErrorCode::UnexpectedNullCharacter),
+            Token::Character(c, _) => {
+                self.reconstruct_the_active_formatting_elements();
+                if !is_whitespace(c) {
+                    self.frameset_ok = false;
+                }
+                self.insert_character(c);
+            }
+            Token::Comment(data, _) => self.insert_comment(data),
+            Token::DOCTYPE(_) => self.parse_error(ErrorCode::UnexpectedDoctype),
+            Token::StartTag(tag) => self.in_body_start_tag(tag),
+            Token::EndTag(tag) => self.in_body_end_tag(tag),
+            Token::EndOfFile(_) if !self.stack_of_template_insertion_modes.is_empty() => {
+                self.process_using(InsertionMode::InTemplate, token);
+            }
+            Token::EndOfFile(_) => {
+                if self.has_unclosed_elements() {
+                    self.parse_error(ErrorCode::MissingEndTag);
+                }
+                self.stop_parsing();
+            }
+        }
+    }
+
+    fn in_body_start_tag(&mut self, mut tag: Tag) {
+        match tag.tag_name.as_str() {
+            "html" => {
+                self.parse_error(ErrorCode::UnexpectedStartTag);
+                if !self.stack_contains("template") {
+                    let html = self.stack_of_open_elements[0];
+                    self.add_missing_attributes(html, tag);
+                }
+            }
+            "base" | "basefont" | "bgsound" | "link" | "meta" | "noframes" | "script" | "style"
+            | "template" | "title" => {
+                self.process_using(InsertionMode::InHead, Token::StartTag(tag));
+            }
+            "body" => {
+                self.parse_error(ErrorCode::UnexpectedStartTag);
+                match self.stack_of_open_elements.get(1) {
+                    Some(&body)
+                        if self.tag_name(body) == "body" && !self.stack_contains("template") =>
+                    {
+                        self.frameset_ok = false;
+                        self.add_missing_attributes(body, tag);
+                    }
+                    _ => {}
+                }
+            }
+            "frameset" => {
+                self.parse_error(ErrorCode::UnexpectedStartTag);
+                match self.stack_of_open_elements.get(1) {
+                    Some(&body) if self.tag_name(body) == "body" && self.frameset_ok => {
+                        self.document.detach(body);
+                        self.stack_of_open_elements.truncate(1);
+                        self.insert_html_element(tag);
+                        self.insertion_mode = InsertionMode::InFrameset;
+                    }
+                    _ => {}
+                }
+            }
+            "address" | "article" | "aside" | "blockquote" | "center" | "details" | "dialog"
+            | "dir" | "div" | "dl" | "fieldset" | "figcaption" | "figure" | "footer" | "header"
+            | "hgroup" | "main" | "menu" | "nav" | "ol" | "p" | "search" | "section"
+            | "summary" | "ul" => {
+                self.close_p_element_in_button_scope();
+                self.insert_html_element(tag);
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                self.close_p_element_in_button_scope();
+                if is_heading(self.tag_name(self.current_node())) {
+                    self.parse_error(ErrorCode::UnexpectedStartTag);
+                    self.stack_of_open_elements.pop();
+                }
+                self.insert_html_element(tag);
+            }
+            "pre" | "listing" => {
+                self.close_p_element_in_button_scope();
+                self.insert_html_element(tag);
+                self.ignore_next_line_feed = true;
+                self.frameset_ok = false;
+            }
+            "form" => {
+                let has_template = self.stack_contains("template");
+                if self.form_element_pointer.is_some() && !has_template {
+                    self.parse_error(ErrorCode::UnexpectedStartTag);
+                } else {
+                    self.close_p_element_in_button_scope();
+                    let form = self.insert_html_element(tag);
+                    if !has_template {
+                        self.form_element_pointer = Some(form);
+                    }
+                }
+            }
+            "li" | "dd" | "dt" => {
+                self.frameset_ok = false;
+                let closes = |name: &str| match tag.tag_name.as_str() {
+                    "li" => name == "li",
+                    _ => matches!(name, "dd" | "dt"),
+                };
+                for index in (0..self.stack_of_open_elements.len()).rev() {
+                    let node = self.stack_of_open_elements[index];
+                    let name = self.tag_name(node);
+                    if closes(name) {
+                        let name = name.to_string();
+                        self.generate_implied_end_tags_except(&name);
+                        if self.current_node() != node {
+                            self.parse_error(ErrorCode::MissingEndTag);
+                        }
+                        self.pop_until_node(node);
+                        break;
+                    }
+                    if self.is_special(node) && !matches!(name, "address" | "div" | "p") {
+                        break;
+                    }
+                }
+                self.close_p_element_in_button_scope();
+                self.insert_html_element(tag);
+            }
+            "plaintext" => {
+                self.close_p_element_in_button_scope();
+                self.insert_html_element(tag);
+                self.tokenizer_state = TokenSinkResult::PLAINTEXT;
+            }
+            "button" => {
+                if self.has_an_element_in_scope("button") {
+                    self.parse_error(ErrorCode::UnexpectedStartTag);
+                    self.generate_implied_end_tags();
+                    self.pop_until("button");
+                }
+                self.reconstruct_the_active_formatting_elements();
+                self.insert_html_element(tag);
+                self.frameset_ok = false;
+            }
+            "a" => {
+                let open_a = self
+                    .formatting_elements_after_last_marker()
+                    .find(|&(node, _)| self.tag_name(node) == "a");
+                if let Some((a, _)) = open_a {
+                    self.parse_error(ErrorCode::UnexpectedStartTag);
+                    self.adoption_agency("a");
+                    self.remove_from_active_formatting_elements(a);
+                    self.stack_of_open_elements.retain(|&node| node != a);
+                }
+                self.reconstruct_the_active_formatting_elements();
+                self.insert_formatting_element(tag);
+            }
+            "b" | "big" | "code" | "em" | "font" | "i" | "s" | "small" | "strike" | "strong"
+            | "tt" | "u" => {
+                self.reconstruct_the_active_formatting_elements();
+                self.insert_formatting_element(tag);
+            }
+            "nobr" => {
+                self.reconstruct_the_active_formatting_elements();
+                if self.has_an_element_in_scope("nobr") {
+                    self.parse_error(ErrorCode::UnexpectedStartTag);
+                    self.adoption_agency("nobr");
+                    self.reconstruct_the_active_formatting_elements();
+                }
+                self.insert_formatting_element(tag);
+            }
+            "applet" | "marquee" | "object" => {
+                self.reconstruct_the_active_formatting_elements();
+                self.insert_html_element(tag);
+                self.active_formatting_elements
+                    .push(FormattingEntry::Marker);
+                self.frameset_ok = false;
+            }
+            "area" | "br" | "embed" | "img" | "keygen" | "wbr" => {
+                self.reconstruct_the_active_formatting_elements();
+                self.insert_html_element(tag);
+                self.stack_of_open_elements.pop();
+                self.acknowledge_self_closing_flag();
+                self.frameset_ok = false;
+            }
+            "input" => {
+                let hidden = tag.attributes.iter().any(|attribute| {
+                    attribute.name == "type" && attribute.value.eq_ignore_ascii_case("hidden")
+                });
+                self.reconstruct_the_active_formatting_elements();
+                self.insert_html_element(tag);
+                self.stack_of_open_elements.pop();
+                self.acknowledge_self_closing_flag();
+                if !hidden {
+                    self.frameset_ok = false;
+                }
+            }
+            "param" | "source" | "track" => {
+                self.insert_html_element(tag);
+                self.stack_of_open_elements.pop();
+                self.acknowledge_self_closing_flag();
+            }
+            "hr" => {
+                self.close_p_element_in_button_scope();
+                self.insert_html_element(tag);
+                self.stack_of_open_elements.pop();
+                self.acknowledge_self_closing_flag();
+                self.frameset_ok = false;
+            }
+            "image" => {
+                self.parse_error(ErrorCode::UnexpectedStartTag);
+                tag.tag_name = "img".to_string();
+                self.process(Token::StartTag(tag));
+            }
+            "textarea" => {
+                self.insert_html_element(tag);
+                self.ignore_next_line_feed = true;
+                self.tokenizer_state = TokenSinkResult::RCDATA;
+                self.original_insertion_mode = self.insertion_mode;
+                self.frameset_ok = false;
+                self.insertion_mode = InsertionMode::Text;
+            }
+            "xmp" => {
+                self.close_p_element_in_button_scope();
+                self.reconstruct_the_active_formatting_elements();
+                self.frameset_ok = false;
+                self.parse_text_element(tag, TokenSinkResult::RAWTEXT);
+            }
+            "iframe" => {
+                self.frameset_ok = false;
+                self.parse_text_element(tag, TokenSinkResult::RAWTEXT);
+            }
+            "noembed" => self.parse_text_element(tag, TokenSinkResult::RAWTEXT),
+            "noscript" if self.scripting => {
+                self.parse_text_element(tag, TokenSinkResult::RAWTEXT);
+            }
+            "select" => {
+                self.reconstruct_the_active_formatting_elements();
+                self.insert_html_element(tag);
+                self.frameset_ok = false;
+                self.insertion_mode = match self.insertion_mode {
+                    InsertionMode::InTable
+                    | InsertionMode::InCaption
+                    | InsertionMode::InTableBody
+                    | InsertionMode::InRow
+                    | InsertionMode::InCell => InsertionMode::InSelectTable,
+                    _ => InsertionMode::InSelect,
+                };
+            }
+            "math" => {
+                self.reconstruct_the_active_formatting_elements();
+                self.insert_foreign_start_tag(tag, Namespace::MathML);
+            }
+            "svg" => {
+                self.reconstruct_the_active_formatting_elements();
+                self.insert_foreign_start_tag(tag, Namespace::SVG);
+            }
+            "optgroup" | "option" => {
+                if self.tag_name(self.current_node()) == "option" {
+                    self.stack_of_open_elements.pop();
+                }
+                self.reconstruct_the_active_formatting_elements();
+                self.insert_html_element(tag);
+            }
+            "rb" | "rtc" => {
+                if self.has_an_element_in_scope("ruby") {
+                    self.generate_implied_end_tags();
+                    if self.tag_name(self.current_node()) != "ruby" {
+                        self.parse_error(ErrorCode::UnexpectedStartTag);
+                    }
+                }
+                self.insert_html_element(tag);
+            }
+            "rp" | "rt" => {
+                if self.has_an_element_in_scope("ruby") {
+                    self.generate_implied_end_tags_except("rtc");
+                    if !matches!(self.tag_name(self.current_node()), "rtc" | "ruby") {
+                        self.parse_error(ErrorCode::UnexpectedStartTag);
+                    }
+                }
+                self.insert_html_element(tag);
+            }
+            "table" => {
+                if self.document.quirks_mode != QuirksMode::Quirks {
+                    self.close_p_element_in_button_scope();
+                }
+                self.insert_html_element(tag);
+                self.frameset_ok = false;
+                self.insertion_mode = InsertionMode::InTable;
+            }
+            "caption" | "col" | "colgroup" | "frame" | "head" | "tbody" | "td" | "tfoot" | "th"
+            | "thead" | "tr" => {
+                self.parse_error(ErrorCode::UnexpectedStartTag);
+            }
+            _ => {
+                self.reconstruct_the_active_formatting_elements();
+                self.insert_html_element(tag);
+            }
+        }
+    }
+
+    fn in_body_end_tag(&mut self, tag: Tag) {
+        match tag.tag_name.as_str() {
+            "template" => self.process_using(InsertionMode::InHead, Token::EndTag(tag)),
+            "body" | "html" => {
+                if !self.has_an_element_in_scope("body") {
+                    self.parse_error(ErrorCode::UnexpectedEndTag);
+                    return;
+                }
+                if self.has_unclosed_elements() {
+                    self.parse_error(ErrorCode::MissingEndTag);
+                }
+                self.insertion_mode = InsertionMode::AfterBody;
+                if tag.tag_name == "html" {
+                    self.process(Token::EndTag(tag));
+                }
+            }
+            "address" | "article" | "aside" | "blockquote" | "button" | "center" | "details"
+            | "dialog" | "dir" | "div" | "dl" | "fieldset" | "figcaption" | "figure" | "footer"
+            | "header" | "hgroup" | "listing" | "main" | "menu" | "nav" | "ol" | "pre"
+            | "search" | "section" | "summary" | "ul" => {
+                self.close_element_in_scope(&tag.tag_name);
+            }
+            "form" => {
+                if self.stack_contains("template") {
+                    self.close_element_in_scope("form");
+                    return;
+                }
+                let node = self.form_element_pointer.take();
+                match node {
+                    Some(node) if self.has_node_in_scope(node) => {
+                        self.generate_implied_end_tags();
+                        if self.current_node() != node {
+                            self.parse_error(ErrorCode::MissingEndTag);
+                        }
+                        self.stack_of_open_elements.retain(|&open| open != node);
+                    }
+                    _ => self.parse_error(ErrorCode::UnexpectedEndTag),
+                }
+            }
+            "p" => {
+                if !self.has_an_element_in_button_scope("p") {
+                    self.parse_error(ErrorCode::UnexpectedEndTag);
+                    self.insert_implied_element("p");
+                }
+                self.close_p_element();
+            }
+            "li" => {
+                if !self.has_an_element_in_list_item_scope("li") {
+                    self.parse_error(ErrorCode::UnexpectedEndTag);
+                    return;
+                }
+                self.generate_implied_end_tags_except("li");
+                if self.tag_name(self.current_node()) != "li" {
+                    self.parse_error(ErrorCode::MissingEndTag);
+                }
+                self.pop_until("li");
+            }
+            "dd" | "dt" => {
+                if !self.has_an_element_in_scope(&tag.tag_name) {
+                    self.parse_error(ErrorCode::UnexpectedEndTag);
+                    return;
+                }
+                self.generate_implied_end_tags_except(&tag.tag_name);
+                if self.tag_name(self.current_node()) != tag.tag_name {
+                    self.parse_error(ErrorCode::MissingEndTag);
+                }
+                self.pop_until(&tag.tag_name);
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                if !self.has_an_element_in_specific_scope(
+                    |parser, node| is_heading(parser.tag_name(node)),
+                    Self::is_scope_boundary,
+                ) {
+                    self.parse_error(ErrorCode::UnexpectedEndTag);
+                    return;
+                }
+                self.generate_implied_end_tags();
+                if self.tag_name(self.current_node()) != tag.tag_name {
+                    self.parse_error(ErrorCode::MissingEndTag);
+                }
+                while let Some(node) = self.stack_of_open_elements.pop() {
+                    if is_heading(self.tag_name(node)) {
+                        break;
+                    }
+                }
+            }
+            "a" | "b" | "big" | "code" | "em" | "font" | "i" | "nobr" | "s" | "small"
+            | "strike" | "strong" | "tt" | "u" => {
+                self.adoption_agency(&tag.tag_name);
+            }
+            "applet" | "marquee" | "object" => {
+                if !self.has_an_element_in_scope(&tag.tag_name) {
+                    self.parse_error(ErrorCode::UnexpectedEndTag);
+                    return;
+                }
+                self.generate_implied_end_tags();
+                if self.tag_name(self.current_node()) != tag.tag_name {
+                    self.parse_error(ErrorCode::MissingEndTag);
+                }
+                self.pop_until(&tag.tag_name);
+                self.clear_the_list_of_active_formatting_elements_up_to_the_last_marker();
+            }
+            "br" => {
+                self.parse_error(ErrorCode::UnexpectedEndTag);
+                self.in_body_start_tag(Tag {
+                    tag_name: tag.tag_name,
+                    ..Default::default()
+                });
+            }
+            _ => self.any_other_end_tag(&tag.tag_name),
+        }
+    }
+
+    /// Pushes onto the list of active formatting elements, keeping at most
+    /// three identical elements after the last marker (the Noah's Ark clause).
+    fn insert_formatting_element(&mut self, tag: Tag) {
+        let element = self.insert_html_element(tag.clone());
+        let identical: Vec<NodeId> = self
+            .formatting_elements_after_last_marker()
+            .filter(|(_, other)| other.tag_name == tag.tag_name && same_attributes(other, &tag))
+            .map(|(node, _)| node)
+            .collect();
+        if identical.len() >= 3 {
+            // The iterator runs backwards, so the earliest one comes last.
+            self.remove_from_active_formatting_elements(*identical.last().unwrap());
+        }
+        self.active_formatting_elements
+            .push(FormattingEntry::Element(element, tag));
+    }
+
+    /// The elements after the last marker, starting with the most recently
+    /// added one.
+    fn formatting_elements_after_last_marker(&self) -> impl Iterator<Item = (NodeId, &Tag)> {
+        self.active_formatting_elements
+            .iter()
+            .rev()
+            .map_while(|entry| match entry {
+                FormattingEntry::Marker => None,
+                FormattingEntry::Element(node, tag) => Some((*node, tag)),
+            })
+    }
+
+    fn formatting_entry_position(&self, node: NodeId) -> Option<usize> {
+        self.active_formatting_elements
+            .iter()
+            .position(|entry| matches!(entry, FormattingEntry::Element(n, _) if *n == node))
+    }
+
+    fn remove_from_active_formatting_elements(&mut self, node: NodeId) {
+        if let Some(position) = self.formatting_entry_position(node) {
+            self.active_formatting_elements.remove(position);
+        }
+    }
+
+    fn clear_the_list_of_active_formatting_elements_up_to_the_last_marker(&mut self) {
+        while let Some(entry) = self.active_formatting_elements.pop() {
+            if let FormattingEntry::Marker = entry {
+                break;
+            }
+        }
+    }
+
+    fn is_open(&self, node: NodeId) -> bool {
+        self.stack_of_open_elements.contains(&node)
+    }
+
+    /// Reopens formatting elements that were implicitly closed, e.g. the `<b>`
+    /// in `<p><b>x<p>y`, so the text that follows is formatted as well.
+    fn reconstruct_the_active_formatting_elements(&mut self) {
+        let is_open_or_marker =
+            |parser: &Self, index: usize| match &parser.active_formatting_elements[index] {
+                FormattingEntry::Marker => true,
+                FormattingEntry::Element(node, _) => parser.is_open(*node),
+            };
+        let len = self.active_formatting_elements.len();
+        if len == 0 || is_open_or_marker(self, len - 1) {
+            return;
+        }
+        let mut first = len - 1;
+        while first > 0 && !is_open_or_marker(self, first - 1) {
+            first -= 1;
+        }
+        for index in first..len {
+            let tag = match &self.active_formatting_elements[index] {
+                FormattingEntry::Element(_, tag) => tag.clone(),
+                FormattingEntry::Marker => unreachable!("markers stop the rewinding"),
+            };
+            let element = self.insert_html_element(tag.clone());
+            self.active_formatting_elements[index] = FormattingEntry::Element(element, tag);
+        }
+    }
+
+    /// The adoption agency algorithm, which untangles misnested formatting
+    /// elements such as `<b><i></b></i>` by closing and reopening them.
+    fn adoption_agency(&mut self, subject: &str) {
+        let current_node = self.current_node();
+        if self.tag_name(current_node) == subject
+            && self.formatting_entry_position(current_node).is_none()
+        {
+            self.stack_of_open_elements.pop();
+            return;
+        }
+
+        for _ in 0..8 {
+            let formatting_element = self
+                .formatting_elements_after_last_marker()
+                .find(|&(node, _)| self.tag_name(node) == subject)
+                .map(|(node, _)| node);
+            let formatting_element = match formatting_element {
+                Some(node) => node,
+                None => return self.any_other_end_tag(subject),
+            };
+            let stack_index = match self
+                .stack_of_open_elements
+                .iter()
+                .position(|&node| node == formatting_element)
+            {
+                Some(index) => index,
+                None => {
+                    self.parse_error(ErrorCode::MisnestedTag);
+                    self.remove_from_active_formatting_elements(formatting_element);
+                    return;
+                }
+            };
+            if !self.has_node_in_scope(formatting_element) {
+                self.parse_error(ErrorCode::UnexpectedEndTag);
+                return;
+            }
+            if formatting_element != self.current_node() {
+                self.parse_error(ErrorCode::MisnestedTag);
+            }
+
+            let furthest_block = (stack_index + 1..self.stack_of_open_elements.len())
+                .find(|&index| self.is_special(self.stack_of_open_elements[index]));
+            let mut furthest_block_index = match furthest_block {
+                Some(index) => index,
+                None => {
+                    self.stack_of_open_elements.truncate(stack_index);
+                    self.remove_from_active_formatting_elements(formatting_element);
+                    return;
+                }
+            };
+            let furthest_block = self.stack_of_open_elements[furthest_block_index];
+            let common_ancestor = self.stack_of_open_elements[stack_index - 1];
+            let mut bookmark = Bookmark::Replace(formatting_element);
+
+            let mut node_index = furthest_block_index;
+            let mut last_node = furthest_block;
+            let mut inner_loop_counter = 0;
+            loop {
+                inner_loop_counter += 1;
+                node_index -= 1;
+                let node = self.stack_of_open_elements[node_index];
+                if node == formatting_element {
+                    break;
+                }
+                let mut entry = self.formatting_entry_position(node);
+                if inner_loop_counter > 3 {
+                    if let Some(position) = entry.take() {
+                        self.active_formatting_elements.remove(position);
+                    }
+                }
+                let entry = match entry {
+                    Some(entry) => entry,
+                    None => {
+                        self.stack_of_open_elements.remove(node_index);
+                        furthest_block_index -= 1;
+                        continue;
+                    }
+                };
+                let tag = match &self.active_formatting_elements[entry] {
+                    FormattingEntry::Element(_, tag) => tag.clone(),
+                    FormattingEntry::Marker => unreachable!("entry belongs to an element"),
+                };
+                let new_element = self.create_html_element(&tag);
+                self.active_formatting_elements[entry] = FormattingEntry::Element(new_element, tag);
+                self.stack_of_open_elements[node_index] = new_element;
+                if last_node == furthest_block {
+                    bookmark = Bookmark::InsertAfter(new_element);
+                }
+                self.document.append(new_element, last_node);
+                last_node = new_element;
+            }
+
+            let (parent, before) =
+                self.appropriate_place_for_inserting_a_node(Some(common_ancestor));
+            self.document.insert(parent, last_node, before);
+
+            let tag = match &self.active_formatting_elements
+                [self.formatting_entry_position(formatting_element).unwrap()]
+            {
+                FormattingEntry::Element(_, tag) => tag.clone(),
+                FormattingEntry::Marker => unreachable!("entry belongs to an element"),
+            };
+            let new_element = self.create_html_element(&tag);
+            self.document.move_children(furthest_block, new_element);
+            self.document.append(furthest_block, new_element);
+
+            let new_entry = FormattingEntry::Element(new_element, tag);
+            match bookmark {
+                Bookmark::Replace(node) => {
+                    let position = self.formatting_entry_position(node).unwrap();
+                    self.active_formatting_elements[position] = new_entry;
+                }
+                Bookmark::InsertAfter(node) => {
+                    self.remove_from_active_formatting_elements(formatting_element);
+                    let position = self.formatting_entry_position(node).unwrap();
+                    self.active_formatting_elements
+                        .insert(position + 1, new_entry);
+                }
+            }
+
+            self.stack_of_open_elements.remove(stack_index);
+            furthest_block_index -= 1;
+            self.stack_of_open_elements
+                .insert(furthest_block_index + 1, new_element);
+        }
+    }
+
+    /// The "any other end tag" steps of the in body insertion mode: closes the
+    /// nearest element named `tag_name`, unless a special element is in the
+    /// way.
+    fn any_other_end_tag(&mut self, tag_name: &str) {
+        for index in (0..self.stack_of_open_elements.len()).rev() {
+            let node = self.stack_of_open_elements[index];
+            if self.tag_name(node) == tag_name {
+                self.generate_implied_end_tags_except(tag_name);
+                if self.current_node() != node {
+                    self.parse_error(ErrorCode::MissingEndTag);
+                }
+                self.pop_until_node(node);
+                return;
+            }
+            if self.is_special(node) {
+                self.parse_error(ErrorCode::UnexpectedEndTag);
+                return;
+            }
+        }
+    }
+
+    fn in_table(&mut self, token: Token) {
+        match token {
+            Token::Character(..)
+                if matches!(
+                    self.tag_name(self.current_node()),
+                    "table" | "tbody" | "template" | "tfoot" | "thead" | "tr"
+                ) =>
+            {
+                self.pending_table_character_tokens.clear();
+                self.original_insertion_mode = self.insertion_mode;
+                self.insertion_mode = InsertionMode::InTableText;
+                self.process(token);
+            }
+            Token::Comment(data, _) => self.insert_comment(data),
+            Token::DOCTYPE(_) => self.parse_error(ErrorCode::UnexpectedDoctype),
+            Token::StartTag(tag) if tag.tag_name == "caption" => {
+                self.clear_the_stack_back_to_a_table_context();
+                self.active_formatting_elements
+                    .push(FormattingEntry::Marker);
+                self.insert_html_element(tag);
+                self.insertion_mode = InsertionMode::InCaption;
+            }
+            Token::StartTag(tag) if tag.tag_name == "colgroup" => {
+                self.clear_the_stack_back_to_a_table_context();
+                self.insert_html_element(tag);
+                self.insertion_mode = InsertionMode::InColumnGroup;
+            }
+            Token::StartTag(ref tag) if tag.tag_name == "col" => {
+                self.clear_the_stack_back_to_a_table_context();
+                self.insert_implied_element("colgroup");
+                self.insertion_mode = InsertionMode::InColumnGroup;
+                self.process(token);
+            }
+            Token::StartTag(tag)
+                if matches!(tag.tag_name.as_str(), "tbody" | "tfoot" | "thead") =>
+            {
+                self.clear_the_stack_back_to_a_table_context();
+                self.insert_html_element(tag);
+                self.insertion_mode = InsertionMode::InTableBody;
+            }
+            Token::StartTag(ref tag) if matches!(tag.tag_name.as_str(), "td" | "th" | "tr") => {
+                self.clear_the_stack_back_to_a_table_context();
+                self.insert_implied_element("tbody");
+                self.insertion_mode = InsertionMode::InTableBody;
+                self.process(token);
+            }
+            Token::StartTag(ref tag) if tag.tag_name == "table" => {
+                self.parse_error(ErrorCode::UnexpectedStartTag);
+                if self.has_an_element_in_table_scope("table") {
+                    self.pop_until("table");
+                    self.reset_the_insertion_mode_appropriately();
+                    self.process(token);
+                }
+            }
+            Token::EndTag(ref tag) if tag.tag_name == "table" => {
+                if self.has_an_element_in_table_scope("table") {
+                    self.pop_until("table");
+                    self.reset_the_insertion_mode_appropriately();
+                } else {
+                    self.parse_error(ErrorCode::UnexpectedEndTag);
+                }
+            }
+            Token::EndTag(ref tag)
+                if matches!(
+                    tag.tag_name.as_str(),
+                    "body"
+                        | "caption"
+                        | "col"
+                        | "colgroup"
+                        | "html"
+                        | "tbody"
+                        | "td"
+                        | "tfoot"
+                        | "th"
+                        | "thead"
+                        | "tr"
+                ) =>
+            {
+                self.parse_error(ErrorCode::UnexpectedEndTag);
+            }
+            Token::StartTag(ref tag)
+                if matches!(tag.tag_name.as_str(), "style" | "script" | "template") =>
+            {
+                self.process_using(InsertionMode::InHead, token);
+            }
+            Token::EndTag(ref tag) if tag.tag_name == "template" => {
+                self.process_using(InsertionMode::InHead, token);
+            }
+            Token::StartTag(tag)
+                if tag.tag_name == "input"
+                    && tag.attributes.iter().any(|attribute| {
+                        attribute.name == "type" && attribute.value.eq_ignore_ascii_case("hidden")
+                    }) =>
+            {
+                self.parse_error(ErrorCode::UnexpectedStartTag);
+                self.insert_html_element(tag);
+                self.stack_of_open_elements.pop();
+                self.acknowledge_self_closing_flag();
+            }
+            Token::StartTag(tag) if tag.tag_name == "form" => {
+                self.parse_error(ErrorCode::UnexpectedStartTag);
+                if !self.stack_contains("template") && self.form_element_pointer.is_none() {
+                    let form = self.insert_html_element(tag);
+                    self.form_element_pointer = Some(form);
+                    self.stack_of_open_elements.pop();
+                }
+            }
+            Token::EndOfFile(_) => self.process_using(InsertionMode::InBody, token),
+            token => {
+                self.parse_error(unexpected(&token));
+                self.process_using_foster_parenting(token);
+            }
+        }
+    }
+
+    /// The "anything else" steps of the in table insertion mode: content that
+    /// doesn't belong in a table is moved in front of it.
+    fn process_using_foster_parenting(&mut self, token: Token) {
+        self.foster_parenting = true;
+        self.process_using(InsertionMode::InBody, token);
+        self.foster_parenting = false;
+    }
+
+    fn in_table_text(&mut self, token: Token) {
+        match token {
+            Token::Character('\0', _) => self.parse_error(ErrorCode::UnexpectedNullCharacter),
+            Token::Character(c, span) => self.pending_table_character_tokens.push((c, span)),
+            token => {
+                let pending = std::mem::take(&mut self.pending_table_character_tokens);
+                if pending.iter().any(|&(c, _)| !is_whitespace(c)) {
+                    self.current_span = pending[0].1;
+                    self.parse_error(ErrorCode::UnexpectedCharacter);
+                    for (c, span) in pending {
+                        self.process_using_foster_parenting(Token::Character(c, span));
+                    }
+                    self.current_span = token.span();
+                } else {
+                    for (c, _) in pending {
+                        self.insert_character(c);
+                    }
+                }
+                self.insertion_mode = self.original_insertion_mode;
+                self.process(token);
+            }
+        }
+    }
+
+    fn in_caption(&mut self, token: Token) {
+        match token {
+            Token::EndTag(ref tag) if tag.tag_name == "caption" => self.close_the_caption(token),
+            Token::StartTag(ref tag)
+                if matches!(
+                    tag.tag_name.as_str(),
+                    "caption"
+                        | "col"
+                        | "colgroup"
+                        | "tbody"
+                        | "td"
+                        | "tfoot"
+                        | "th"
+                        | "thead"
+                        | "tr"
+                ) =>
+            {
+                self.close_the_caption(token);
+            }
+            Token::EndTag(ref tag) if tag.tag_name == "table" => self.close_the_caption(token),
+            Token::EndTag(ref tag)
+                if matches!(
+                    tag.tag_name.as_str(),
+                    "body"
+                        | "col"
+                        | "colgroup"
+                        | "html"
+                        | "tbody"
+                        | "td"
+                        | "tfoot"
+                        | "th"
+                        | "thead"
+                        | "tr"
+                ) =>
+            {
+                self.parse_error(ErrorCode::UnexpectedEndTag);
+            }
+            token => self.process_using(InsertionMode::InBody, token),
+        }
+    }
+
+    /// Closes the caption, if there is one, and reprocesses `token` unless it
+    /// is the caption's end tag.
+    fn close_the_caption(&mut self, token: Token) {
+        if !self.has_an_element_in_table_scope("caption") {
+            self.parse_error(unexpected(&token));
+            return;
+        }
+        self.generate_implied_end_tags();
+        if self.tag_name(self.current_node()) != "caption" {
+            self.parse_error(ErrorCode::MissingEndTag);
+        }
+        self.pop_until("caption");
+        self.clear_the_list_of_active_formatting_elements_up_to_the_last_marker();
+        self.insertion_mode = InsertionMode::InTable;
+        if !matches!(&token, Token::EndTag(tag) if tag.tag_name == "caption") {
+            self.process(token);
+        }
+    }
+
+    fn in_column_group(&mut self, token: Token) {
+        match token {
+            Token::Character(c, _) if is_whitespace(c) => self.insert_character(c),
+            Token::Comment(data, _) => self.insert_comment(data),
+            Token::DOCTYPE(_) => self.parse_error(ErrorCode::UnexpectedDoctype),
+            Token::StartTag(ref tag) if tag.tag_name == "html" => {
+                self.process_using(InsertionMode::InBody, token);
+            }
+            Token::StartTag(tag) if tag.tag_name == "col" => {
+                self.insert_html_element(tag);
+                self.stack_of_open_elements.pop();
+                self.acknowledge_self_closing_flag();
+            }
+            Token::EndTag(ref tag) if tag.tag_name == "colgroup" => {
+                if self.tag_name(self.current_node()) == "colgroup" {
+                    self.stack_of_open_elements.pop();
+                    self.insertion_mode = InsertionMode::InTable;
+                } else {
+                    self.parse_error(ErrorCode::UnexpectedEndTag);
+                }
+            }
+            Token::EndTag(ref tag) if tag.tag_name == "col" => {
+                self.parse_error(ErrorCode::UnexpectedEndTag);
+            }
+            Token::StartTag(ref tag) if tag.tag_name == "template" => {
+                self.process_using(InsertionMode::InHead, token);
+            }
+            Token::EndTag(ref tag) if tag.tag_name == "template" => {
+                self.process_using(InsertionMode::InHead, token);
+            }
+            Token::EndOfFile(_) => self.process_using(InsertionMode::InBody, token),
+            token => {
+                if self.tag_name(self.current_node()) == "colgroup" {
+                    self.stack_of_open_elements.pop();
+                    self.insertion_mode = InsertionMode::InTable;
+                    self.process(token);
+                } else {
+                    self.parse_error(unexpected(&token));
+                }
+            }
+        }
+    }
+
+    fn in_table_body(&mut self, token: Token) {
+        match token {
+            Token::StartTag(tag) if tag.tag_name == "tr" => {
+                self.clear_the_stack_back_to_a_table_body_context();
+                self.insert_html_element(tag);
+                self.insertion_mode = InsertionMode::InRow;
+            }
+            Token::StartTag(ref tag) if matches!(tag.tag_name.as_str(), "th" | "td") => {
+                self.parse_error(ErrorCode::UnexpectedStartTag);
+                self.clear_the_stack_back_to_a_table_body_context();
+                self.insert_implied_element("tr");
+                self.insertion_mode = InsertionMode::InRow;
+                self.process(token);
+            }
+            Token::EndTag(ref tag)
+                if matches!(tag.tag_name.as_str(), "tbody" | "tfoot" | "thead") =>
+            {
+                if self.has_an_element_in_table_scope(&tag.tag_name) {
+                    self.clear_the_stack_back_to_a_table_body_context();
+                    self.stack_of_open_elements.pop();
+                    self.insertion_mode = InsertionMode::InTable;
+                } else {
+                    self.parse_error(ErrorCode::UnexpectedEndTag);
+                }
+            }
+            Token::StartTag(ref tag)
+                if matches!(
+                    tag.tag_name.as_str(),
+                    "caption" | "col" | "colgroup" | "tbody" | "tfoot" | "thead"
+                ) =>
+            {
+                self.close_the_table_body(token);
+            }
+            Token::EndTag(ref tag) if tag.tag_name == "table" => self.close_the_table_body(token),
+            Token::EndTag(ref tag)
+                if matches!(
+                    tag.tag_name.as_str(),
+                    "body" | "caption" | "col" | "colgroup" | "html" | "td" | "th" | "tr"
+                ) =>
+            {
+                self.parse_error(ErrorCode::UnexpectedEndTag);
+            }
+            token => self.process_using(InsertionMode::InTable, token),
+        }
+    }
+
+    /// Closes the table body that is open, if any, and reprocesses `token`.
+    fn close_the_table_body(&mut self, token: Token) {
+        if !self.has_an_element_in_table_scope("tbody")
+            && !self.has_an_element_in_table_scope("thead")
+            && !self.has_an_element_in_table_scope("tfoot")
+        {
+            self.parse_error(unexpected(&token));
+            return;
+        }
+        self.clear_the_stack_back_to_a_table_body_context();
+        self.stack_of_open_elements.pop();
+        self.insertion_mode = InsertionMode::InTable;
+        self.process(token);
+    }
+
+    fn in_row(&mut self, token: Token) {
+        match token {
+            Token::StartTag(tag) if matches!(tag.tag_name.as_str(), "th" | "td") => {
+                self.clear_the_stack_back_to_a_table_row_context();
+                self.insert_html_element(tag);
+                self.insertion_mode = InsertionMode::InCell;
+                self.active_formatting_elements
+                    .push(FormattingEntry::Marker);
+            }
+            Token::EndTag(ref tag) if tag.tag_name == "tr" => self.close_the_row(token),
+            Token::StartTag(ref tag)
+                if matches!(
+                    tag.tag_name.as_str(),
+                    "caption" | "col" | "colgroup" | "tbody" | "tfoot" | "thead" | "tr"
+                ) =>
+            {
+                self.close_the_row(token);
+            }
+            Token::EndTag(ref tag) if tag.tag_name == "table" => self.close_the_row(token),
+            Token::EndTag(ref tag)
+                if matches!(tag.tag_name.as_str(), "tbody" | "tfoot" | "thead") =>
+            {
+                if !self.has_an_element_in_table_scope(&tag.tag_name) {
+                    self.parse_error(ErrorCode::UnexpectedEndTag);
+                } else if self.has_an_element_in_table_scope("tr") {
+                    self.close_the_row(token);
+                }
+            }
+            Token::EndTag(ref tag)
+                if matches!(
+                    tag.tag_name.as_str(),
+                    "body" | "caption" | "col" | "colgroup" | "html" | "td" | "th"
+                ) =>
+            {
+                self.parse_error(ErrorCode::UnexpectedEndTag);
+            }
+            token => self.process_using(InsertionMode::InTable, token),
+        }
+    }
+
+    /// Closes the row, if there is one, and reprocesses `token` unless it is
+    /// the row's end tag.
+    fn close_the_row(&mut self, token: Token) {
+        if !self.has_an_element_in_table_scope("tr") {
+            self.parse_error(unexpected(&token));
+            return;
+        }
+        self.clear_the_stack_back_to_a_table_row_context();
+        self.stack_of_open_elements.pop();
+        self.insertion_mode = InsertionMode::InTableBody;
+        if !matches!(&token, Token::EndTag(tag) if tag.tag_name == "tr") {
+            self.process(token);
+        }
+    }
+
+    fn in_cell(&mut self, token: Token) {
+        match token {
+            Token::EndTag(ref tag) if matches!(tag.tag_name.as_str(), "td" | "th") => {
+                if !self.has_an_element_in_table_scope(&tag.tag_name) {
+                    self.parse_error(ErrorCode::UnexpectedEndTag);
+                    return;
+                }
+                self.generate_implied_end_tags();
+                if self.tag_name(self.current_node()) != tag.tag_name {
+                    self.parse_error(ErrorCode::MissingEndTag);
+                }
+                self.pop_until(&tag.tag_name);
+                self.clear_the_list_of_active_formatting_elements_up_to_the_last_marker();
+                self.insertion_mode = InsertionMode::InRow;
+            }
+            Token::StartTag(ref tag)
+                if matches!(
+                    tag.tag_name.as_str(),
+                    "caption"
+                        | "col"
+                        | "colgroup"
+                        | "tbody"
+                        | "td"
+                        | "tfoot"
+                        | "th"
+                        | "thead"
+                        | "tr"
+                ) =>
+            {
+                if self.has_an_element_in_table_scope("td")
+                    || self.has_an_element_in_table_scope("th")
+                {
+                    self.close_the_cell();
+                    self.process(token);
+                } else {
+                    self.parse_error(ErrorCode::UnexpectedStartTag);
+                }
+            }
+            Token::EndTag(ref tag)
+                if matches!(
+                    tag.tag_name.as_str(),
+                    "body" | "caption" | "col" | "colgroup" | "html"
+                ) =>
+            {
+                self.parse_error(ErrorCode::UnexpectedEndTag);
+            }
+            Token::EndTag(ref tag)
+                if matches!(
+                    tag.tag_name.as_str(),
+                    "table" | "tbody" | "tfoot" | "thead" | "tr"
+                ) =>
+            {
+                if self.has_an_element_in_table_scope(&tag.tag_name) {
+                    self.close_the_cell();
+                    self.process(token);
+                } else {
+                    self.parse_error(ErrorCode::UnexpectedEndTag);
+                }
+            }
+            token => self.process_using(InsertionMode::InBody, token),
+        }
+    }
+
+    fn close_the_cell(&mut self) {
+        self.generate_implied_end_tags();
+        if !matches!(self.tag_name(self.current_node()), "td" | "th") {
+            self.parse_error(ErrorCode::MissingEndTag);
+        }
+        while let Some(node) = self.stack_of_open_elements.pop() {
+            if matches!(self.tag_name(node), "td" | "th") {
+                break;
+            }
+        }
+        self.clear_the_list_of_active_formatting_elements_up_to_the_last_marker();
+        self.insertion_mode = InsertionMode::InRow;
+    }
+
+    fn after_body(&mut self, token: Token) {
+        match token {
+            Token::Character(c, _) if is_whitespace(c) => {
+                self.process_using(InsertionMode::InBody, token);
+            }
+            Token::Comment(data, _) => {
+                let html = self.stack_of_open_elements[0];
+                self.insert_comment_into(data, html);
+            }
+            Token::DOCTYPE(_) => self.parse_error(ErrorCode::UnexpectedDoctype),
+            Token::StartTag(ref tag) if tag.tag_name == "html" => {
+                self.process_using(InsertionMode::InBody, token);
+            }
+            Token::EndTag(ref tag) if tag.tag_name == "html" => {
+                if self.context_element.is_some() {
+                    self.parse_error(ErrorCode::UnexpectedEndTag);
+                    return;
+                }
+                self.insertion_mode = InsertionMode::AfterAfterBody;
+            }
+            Token::EndOfFile(_) => self.stop_parsing(),
+            token => {
+                self.parse_error(unexpected(&token));
+                self.insertion_mode = InsertionMode::InBody;
+                self.process(token);
+            }
+        }
+    }
+
+    fn in_select(&mut self, token: Token) {
+        match token {
+            Token::Character('\0', _) => self.parse_error(ErrorCode::UnexpectedNullCharacter),
+            Token::Character(c, _) => self.insert_character(c),
+            Token::Comment(data, _) => self.insert_comment(data),
+            Token::DOCTYPE(_) => self.parse_error(ErrorCode::UnexpectedDoctype),
+            Token::StartTag(ref tag) if tag.tag_name == "html" => {
+                self.process_using(InsertionMode::InBody, token);
+            }
+            Token::StartTag(tag) if tag.tag_name == "option" => {
+                if self.tag_name(self.current_node()) == "option" {
+                    self.stack_of_open_elements.pop();
+                }
+                self.insert_html_element(tag);
+            }
+            Token::StartTag(tag) if matches!(tag.tag_name.as_str(), "optgroup" | "hr") => {
+                if self.tag_name(self.current_node()) == "option" {
+                    self.stack_of_open_elements.pop();
+                }
+                if self.tag_name(self.current_node()) == "optgroup" {
+                    self.stack_of_open_elements.pop();
+                }
+                let is_hr = tag.tag_name == "hr";
+                self.insert_html_element(tag);
+                if is_hr {
+                    self.stack_of_open_elements.pop();
+                    self.acknowledge_self_closing_flag();
+                }
+            }
+            Token::EndTag(ref tag) if tag.tag_name == "optgroup" => {
+                let len = self.stack_of_open_elements.len();
+                if self.tag_name(self.current_node()) == "option"
+                    && len >= 2
+                    && self.tag_name(self.stack_of_open_elements[len - 2]) == "optgroup"
+                {
+                    self.stack_of_open_elements.pop();
+                }
+                if self.tag_name(self.current_node()) == "optgroup" {
+                    self.stack_of_open_elements.pop();
+                } else {
+                    self.parse_error(ErrorCode::UnexpectedEndTag);
+                }
+            }
+            Token::EndTag(ref tag) if tag.tag_name == "option" => {
+                if self.tag_name(self.current_node()) == "option" {
+                    self.stack_of_open_elements.pop();
+                } else {
+                    self.parse_error(ErrorCode::UnexpectedEndTag);
+                }
+            }
+            Token::EndTag(ref tag) if tag.tag_name == "select" => {
+                if !self.has_an_element_in_select_scope("select") {
+                    self.parse_error(ErrorCode::UnexpectedEndTag);
+                    return;
+                }
+                self.pop_until("select");
+                self.reset_the_insertion_mode_appropriately();
+            }
+            Token::StartTag(ref tag) if tag.tag_name == "select" => {
+                self.parse_error(ErrorCode::UnexpectedStartTag);
+                if self.has_an_element_in_select_scope("select") {
+                    self.pop_until("select");
+                    self.reset_the_insertion_mode_appropriately();
+                }
+            }
+            Token::StartTag(ref tag)
+                if matches!(tag.tag_name.as_str(), "input" | "keygen" | "textarea") =>
+            {
+                self.parse_error(ErrorCode::UnexpectedStartTag);
+                if self.has_an_element_in_select_scope("select") {
+                    self.pop_until("select");
+                    self.reset_the_insertion_mode_appropriately();
+                    self.process(token);
+                }
+            }
+            Token::StartTag(ref tag) if matches!(tag.tag_name.as_str(), "script" | "template") => {
+                self.process_using(InsertionMode::InHead, token);
+            }
+            Token::EndTag(ref tag) if tag.tag_name == "template" => {
+                self.process_using(InsertionMode::InHead, token);
+            }
+            Token::EndOfFile(_) => self.process_using(InsertionMode::InBody, token),
+            token => self.parse_error(unexpected(&token)),
+        }
+    }
+
+    fn in_select_table(&mut self, token: Token) {
+        match token {
+            Token::StartTag(ref tag) if is_table_element(&tag.tag_name) => {
+                self.parse_error(ErrorCode::UnexpectedStartTag);
+                self.pop_until("select");
+                self.reset_the_insertion_mode_appropriately();
+                self.process(token);
+            }
+            Token::EndTag(ref tag) if is_table_element(&tag.tag_name) => {
+                self.parse_error(ErrorCode::UnexpectedEndTag);
+                if self.has_an_element_in_table_scope(&tag.tag_name) {
+                    self.pop_until("select");
+                    self.reset_the_insertion_mode_appropriately();
+                    self.process(token);
+                }
+            }
+            token => self.process_using(InsertionMode::InSelect, token),
+        }
+    }
+
+    fn in_template(&mut self, token: Token) {
+        match token {
+            Token::Character(..) | Token::Comment(..) | Token::DOCTYPE(_) => {
+                self.process_using(InsertionMode::InBody, token);
+            }
+            Token::StartTag(ref tag)
+                if matches!(
+                    tag.tag_name.as_str(),
+                    "base"
+                        | "basefont"
+                        | "bgsound"
+                        | "link"
+                        | "meta"
+                        | "noframes"
+                        | "script"
+                        | "style"
+                        | "template"
+                        | "title"
+                ) =>
+            {
+                self.process_using(InsertionMode::InHead, token);
+            }
+            Token::EndTag(ref tag) if tag.tag_name == "template" => {
+                self.process_using(InsertionMode::InHead, token);
+            }
+            Token::StartTag(ref tag)
+                if matches!(
+                    tag.tag_name.as_str(),
+                    "caption" | "colgroup" | "tbody" | "tfoot" | "thead"
+                ) =>
+            {
+                self.switch_template_insertion_mode(InsertionMode::InTable, token);
+            }
+            Token::StartTag(ref tag) if tag.tag_name == "col" => {
+                self.switch_template_insertion_mode(InsertionMode::InColumnGroup, token);
+            }
+            Token::StartTag(ref tag) if tag.tag_name == "tr" => {
+                self.switch_template_insertion_mode(InsertionMode::InTableBody, token);
+            }
+            Token::StartTag(ref tag) if matches!(tag.tag_name.as_str(), "td" | "th") => {
+                self.switch_template_insertion_mode(InsertionMode::InRow, token);
+            }
+            Token::StartTag(_) => {
+                self.switch_template_insertion_mode(InsertionMode::InBody, token);
+            }
+            Token::EndTag(_) => self.parse_error(ErrorCode::UnexpectedEndTag),
+            Token::EndOfFile(_) => {
+                if !self.stack_contains("template") {
+                    self.stop_parsing();
+                    return;
+                }
+                self.parse_error(ErrorCode::MissingEndTag);
+                self.close_the_template();
+                self.process(token);
+            }
+        }
+    }
+
+    fn in_frameset(&mut self, token: Token) {
+        match token {
+            Token::Character(c, _) if is_whitespace(c) => self.insert_character(c),
+            Token::Comment(data, _) => self.insert_comment(data),
+            Token::DOCTYPE(_) => self.parse_error(ErrorCode::UnexpectedDoctype),
+            Token::StartTag(ref tag) if tag.tag_name == "html" => {
+                self.process_using(InsertionMode::InBody, token);
+            }
+            Token::StartTag(tag) if tag.tag_name == "frameset" => {
+                self.insert_html_element(tag);
+            }
+            Token::EndTag(ref tag) if tag.tag_name == "frameset" => {
+                if self.stack_of_open_elements.len() == 1 {
+                    self.parse_error(ErrorCode::UnexpectedEndTag);
+                    return;
+                }
+                self.stack_of_open_elements.pop();
+                if self.context_element.is_none()
+                    && self.tag_name(self.current_node()) != "frameset"
+                {
+                    self.insertion_mode = InsertionMode::AfterFrameset;
+                }
+            }
+            Token::StartTag(tag) if tag.tag_name == "frame" => {
+                self.insert_html_element(tag);
+                self.stack_of_open_elements.pop();
+                self.acknowledge_self_closing_flag();
+            }
+            Token::StartTag(ref tag) if tag.tag_name == "noframes" => {
+                self.process_using(InsertionMode::InHead, token);
+            }
+            Token::EndOfFile(_) => {
+                if self.stack_of_open_elements.len() > 1 {
+                    self.parse_error(ErrorCode::MissingEndTag);
+                }
+                self.stop_parsing();
+            }
+            token => self.parse_error(unexpected(&token)),
+        }
+    }
+
+    fn after_frameset(&mut self, token: Token) {
+        match token {
+            Token::Character(c, _) if is_whitespace(c) => self.insert_character(c),
+            Token::Comment(data, _) => self.insert_comment(data),
+            Token::DOCTYPE(_) => self.parse_error(ErrorCode::UnexpectedDoctype),
+            Token::StartTag(ref tag) if tag.tag_name == "html" => {
+                self.process_using(InsertionMode::InBody, token);
+            }
+            Token::EndTag(ref tag) if tag.tag_name == "html" => {
+                self.insertion_mode = InsertionMode::AfterAfterFrameset;
+            }
+            Token::StartTag(ref tag) if tag.tag_name == "noframes" => {
+                self.process_using(InsertionMode::InHead, token);
+            }
+            Token::EndOfFile(_) => self.stop_parsing(),
+            token => self.parse_error(unexpected(&token)),
+        }
+    }
+
+    fn after_after_body(&mut self, token: Token) {
+        match token {
+            Token::Comment(data, _) => self.insert_comment_into(data, Document::ROOT),
+            Token::DOCTYPE(_) => self.process_using(InsertionMode::InBody, token),
+            Token::Character(c, _) if is_whitespace(c) => {
+                self.process_using(InsertionMode::InBody, token);
+            }
+            Token::StartTag(ref tag) if tag.tag_name == "html" => {
+                self.process_using(InsertionMode::InBody, token);
+            }
+            Token::EndOfFile(_) => self.stop_parsing(),
+            token => {
+                self.parse_error(unexpected(&token));
+                self.insertion_mode = InsertionMode::InBody;
+                self.process(token);
+            }
+        }
+    }
+
+    fn after_after_frameset(&mut self, token: Token) {
+        match token {
+            Token::Comment(data, _) => self.insert_comment_into(data, Document::ROOT),
+            Token::DOCTYPE(_) => self.process_using(InsertionMode::InBody, token),
+            Token::Character(c, _) if is_whitespace(c) => {
+                self.process_using(InsertionMode::InBody, token);
+            }
+            Token::StartTag(ref tag) if tag.tag_name == "html" => {
+                self.process_using(InsertionMode::InBody, token);
+            }
+            Token::EndOfFile(_) => self.stop_parsing(),
+            Token::StartTag(ref tag) if tag.tag_name == "noframes" => {
+                self.process_using(InsertionMode::InHead, token);
+            }
+            token => self.parse_error(unexpected(&token)),
+        }
+    }
+
+    fn text(&mut self, token: Token) {
+        match token {
+            Token::Character(c, _) => self.insert_character(c),
+            Token::EndOfFile(_) => {
+                self.parse_error(ErrorCode::MissingEndTag);
+                self.stack_of_open_elements.pop();
+                self.insertion_mode = self.original_insertion_mode;
+                self.process(token);
+            }
+            // Any end tag closes the element, a `</script>` doesn't need to
+            // run anything.
+            _ => {
+                self.stack_of_open_elements.pop();
+                self.insertion_mode = self.original_insertion_mode;
+            }
+        }
+    }
+
+    fn in_foreign_content(&mut self, token: Token) {
+        match token {
+            Token::Character('\0', _) => {
+                self.parse_error(ErrorCode::UnexpectedNullCharacter);
+                self.insert_character('\u{FFFD}');
+            }
+            Token::Character(c, _) => {
+                if !is_whitespace(c) {
+                    self.frameset_ok = false;
+                }
+                self.insert_character(c);
+            }
+            Token::Comment(data, _) => self.insert_comment(data),
+            Token::DOCTYPE(_) => self.parse_error(ErrorCode::UnexpectedDoctype),
+            Token::StartTag(ref tag) if breaks_out_of_foreign_content(tag) => {
+                self.leave_foreign_content(token);
+            }
+            Token::EndTag(ref tag) if matches!(tag.tag_name.as_str(), "br" | "p") => {
+                self.leave_foreign_content(token);
+            }
+            Token::StartTag(tag) => {
+                let node = self
+                    .adjusted_current_node()
+                    .expect("foreign content has an adjusted current node");
+                let namespace = self.element(node).namespace;
+                self.insert_foreign_start_tag(tag, namespace);
+            }
+            Token::EndTag(tag) => {
+                let mut index = self.stack_of_open_elements.len() - 1;
+                let local_name = |parser: &Self, index: usize| {
+                    parser
+                        .element(parser.stack_of_open_elements[index])
+                        .local_name
+                        .to_ascii_lowercase()
+                };
+                if local_name(self, index) != tag.tag_name {
+                    self.parse_error(ErrorCode::UnexpectedEndTag);
+                }
+                while index > 0 {
+                    if local_name(self, index) == tag.tag_name {
+                        self.stack_of_open_elements.truncate(index);
+                        return;
+                    }
+                    index -= 1;
+                    let node = self.stack_of_open_elements[index];
+                    if self.element(node).namespace == Namespace::HTML {
+                        self.process(Token::EndTag(tag));
+                        return;
+                    }
+                }
+            }
+            Token::EndOfFile(_) => self.process(token),
+        }
+    }
+
+    /// An HTML tag in foreign content closes the foreign elements up to the
+    /// nearest one that may contain HTML.
+    fn leave_foreign_content(&mut self, token: Token) {
+        self.parse_error(unexpected(&token));
+        loop {
+            let node = self.current_node();
+            if self.element(node).namespace == Namespace::HTML
+                || self.is_mathml_text_integration_point(node)
+                || self.is_html_integration_point(node)
+            {
+                break;
+            }
+            self.stack_of_open_elements.pop();
+        }
+        self.process(token);
+    }
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TokenSink for Parser {
+    fn process_token(&mut self, token: Token) -> TokenSinkResult {
+        self.current_span = token.span();
+        let self_closing = matches!(&token, Token::StartTag(tag) if tag.self_closing);
+        self.self_closing_flag_acknowledged = false;
+        let ignore_line_feed = std::mem::take(&mut self.ignore_next_line_feed);
+        if !(ignore_line_feed && matches!(token, Token::Character('\n', _))) {
+            self.tree_construction_dispatcher(token);
+        }
+        if self_closing && !self.self_closing_flag_acknowledged {
+            self.parse_error(ErrorCode::NonVoidHtmlElementStartTagWithTrailingSolidus);
+        }
+        std::mem::replace(&mut self.tokenizer_state, TokenSinkResult::Continue)
+    }
+
+    fn parse_error(&mut self, error: ParseError) {
+        self.errors.push(error);
+    }
+
+    fn adjusted_current_node_present_but_not_in_html_namespace(&self) -> bool {
+        self.adjusted_current_node()
+            .is_some_and(|node| self.element(node).namespace != Namespace::HTML)
+    }
+}
+
+/// Parses `input` as a whole document, with scripting off (see
+/// [`Parser::set_scripting`]). The returned parser holds the document and
+/// the parse errors.
+pub fn parse_document(input: String) -> Parser {
+    Parser::new().parse_document(input)
+}
+
+/// Parses `input` as the contents of `context_element`, a node of
+/// `document`, the way setting `innerHTML` does, with scripting off.
+///
+/// The fragment is the returned parser's document: its root,
+/// [`Document::ROOT`], is a document fragment node holding the parsed nodes,
+/// and the parser also holds the parse errors. It is a tree of its own
+/// rather than nodes of `document`, because nodes can't move between
+/// documents. The context is passed along with its document for the same
+/// reason, as a [`NodeId`] means nothing on its own.
+pub fn parse_fragment(document: &Document, context_element: NodeId, input: String) -> Parser {
+    Parser::new().parse_fragment(document, context_element, input)
+}
+
+/// Public identifiers of legacy DOCTYPEs that trigger quirks mode, by prefix.
+static QUIRKY_PUBLIC_IDENTIFIER_PREFIXES: &[&str] = &[
+    "+//Silmaril//dtd html Pro v0r11 19970101//",
+    "-//AS//DTD HTML 3.0 asWedit + extensions//",
+    "-//AdvaSoft Ltd//DTD HTML 3.0 asWedit + extensions//",
+    "-//IETF//DTD HTML 2.0 Level 1//",
+    "-//IETF//DTD HTML 2.0 Level 2//",
+    "-//IETF//DTD HTML 2.0 Strict Level 1//",
+    "-//IETF//DTD HTML 2.0 Strict Level 2//",
+    "-//IETF//DTD HTML 2.0 Strict//",
+    "-//IETF//DTD HTML 2.0//",
+    "-//IETF//DTD HTML 2.1E//",
+    "-//IETF//DTD HTML 3.0//",
+    "-//IETF//DTD HTML 3.2 Final//",
+    "-//IETF//DTD HTML 3.2//",
+    "-//IETF//DTD HTML 3//",
+    "-//IETF//DTD HTML Level 0//",
+    "-//IETF//DTD HTML Level 1//",
+    "-//IETF//DTD HTML Level 2//",
+    "-//IETF//DTD HTML Level 3//",
+    "-//IETF//DTD HTML Strict Level 0//",
+    "-//IETF//DTD HTML Strict Level 1//",
+    "-//IETF//DTD HTML Strict Level 2//",
+    "-//IETF//DTD HTML Strict Level 3//",
+    "-//IETF//DTD HTML Strict//",
+    "-//IETF//DTD HTML//",
+    "-//Metrius//DTD Metrius Presentational//",
+    "-//Microsoft//DTD Internet Explorer 2.0 HTML Strict//",
+    "-//Microsoft//DTD Internet Explorer 2.0 HTML//",
+    "-//Microsoft//DTD Internet Explorer 2.0 Tables//",
+    "-//Microsoft//DTD Internet Explorer 3.0 HTML Strict//",
+    "-//Microsoft//DTD Internet Explorer 3.0 HTML//",
+    "-//Microsoft//DTD Internet Explorer 3.0 Tables//",
+    "-//Netscape Comm. Corp.//DTD HTML//",
+    "-//Netscape Comm. Corp.//DTD Strict HTML//",
+    "-//O'Reilly and Associates//DTD HTML 2.0//",
+    "-//O'Reilly and Associates//DTD HTML Extended 1.0//",
+    "-//O'Reilly and Associates//DTD HTML Extended Relaxed 1.0//",
+    "-//SQ//DTD HTML 2.0 HoTMetaL + extensions//",
+    "-//SoftQuad Software//DTD HoTMetaL PRO 6.0::19990601::extensions to HTML 4.0//",
+    "-//SoftQuad//DTD HoTMetaL PRO 4.0::19970916::extensions to HTML 4.0//",
+    "-//Spyglass//DTD HTML 2.0 Extended//",
+    "-//Sun Microsystems Corp.//DTD HotJava HTML//",
+    "-//Sun Microsystems Corp.//DTD HotJava Strict HTML//",
+    "-//W3C//DTD HTML 3 1995-03-24//",
+    "-//W3C//DTD HTML 3.2 Draft//",
+    "-//W3C//DTD HTML 3.2 Final//",
+    "-//W3C//DTD HTML 3.2//",
+    "-//W3C//DTD HTML 3.2S Draft//",
+    "-//W3C//DTD HTML 4.0 Frameset//",
+    "-//W3C//DTD HTML 4.0 Transitional//",
+    "-//W3C//DTD HTML Experimental 19960712//",
+    "-//W3C//DTD HTML Experimental 970421//",
+    "-//W3C//DTD W3 HTML//",
+    "-//W3O//DTD W3 HTML 3.0//",
+    "-//WebTechs//DTD Mozilla HTML 2.0//",
+    "-//WebTechs//DTD Mozilla HTML//",
+];
+
+/// Decides the quirks mode of a document from its DOCTYPE. Identifiers are
+/// compared case-insensitively.
+fn quirks_mode(doctype: &DOCTYPE) -> QuirksMode {
+    let public = doctype.public_identifier.as_deref();
+    let system = doctype.system_identifier.as_deref();
+    let public_is = |expected: &str| public.is_some_and(|id| id.eq_ignore_ascii_case(expected));
+    let public_starts_with = |prefix: &str| {
+        public.is_some_and(|id| {
+            id.len() >= prefix.len()
+                && id.as_bytes()[..prefix.len()].eq_ignore_ascii_case(prefix.as_bytes())
+        })
+    };
+    let html_401 = public_starts_with("-//W3C//DTD HTML 4.01 Frameset//")
+        || public_starts_with("-//W3C//DTD HTML 4.01 Transitional//");
+
+    if doctype.force_quirks
+        || doctype.name != "html"
+        || public_is("-//W3O//DTD W3 HTML Strict 3.0//EN//")
+        || public_is("-/W3C/DTD HTML 4.0 Transitional/EN")
+        || public_is("HTML")
+        || system.is_some_and(|id| {
+            id.eq_ignore_ascii_case("http://www.ibm.com/data/dtd/v11/ibmxhtml1-transitional.dtd")
+        })
+        || QUIRKY_PUBLIC_IDENTIFIER_PREFIXES
+            .iter()
+            .any(|prefix| public_starts_with(prefix))
+        || (system.is_none() && html_401)
+    {
+        QuirksMode::Quirks
+    } else if public_starts_with("-//W3C//DTD XHTML 1.0 Frameset//")
+        || public_starts_with("-//W3C//DTD XHTML 1.0 Transitional//")
+        || (system.is_some() && html_401)
+    {
+        QuirksMode::LimitedQuirks
+    } else {
+        QuirksMode::NoQuirks
+    }
+}
+
+/// An entry in the list of active formatting elements. Elements keep the
+/// token they were created for, as they may have to be recreated.
+enum FormattingEntry {
+    Marker,
+    Element(NodeId, Tag),
+}
+
+/// Where the adoption agency algorithm puts the formatting element it
+/// recreates in the list of active formatting elements.
+enum Bookmark {
+    Replace(NodeId),
+    InsertAfter(NodeId),
+}
+
+fn attributes_of(attributes: Vec<token::Attribute>) -> Vec<Attribute> {
+    attributes.into_iter().map(Attribute::from).collect()
+}
+
+/// Attribute lists are compared regardless of order for the Noah's Ark clause.
+fn same_attributes(a: &Tag, b: &Tag) -> bool {
+    a.attributes.len() == b.attributes.len()
+        && a.attributes.iter().all(|attribute| {
+            b.attributes
+                .iter()
+                .any(|other| other.name == attribute.name && other.value == attribute.value)
+        })
+}
+
+/// The error for a token the current insertion mode doesn't expect.
+fn unexpected(token: &Token) -> ErrorCode {
+    match token {
+        Token::DOCTYPE(_) => ErrorCode::UnexpectedDoctype,
+        Token::StartTag(_) => ErrorCode::UnexpectedStartTag,
+        Token::EndTag(_) => ErrorCode::UnexpectedEndTag,
+        Token::EndOfFile(_) => ErrorCode::MissingEndTag,
+        Token::Character(..) | Token::Comment(..) => ErrorCode::UnexpectedCharacter,
+    }
+}
+
+/// ASCII whitespace as far as the tree builder is concerned. The tokenizer
+/// never emits a U+000D CARRIAGE RETURN.
+fn is_whitespace(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\x0C' | '\r' | ' ')
+}
+
+/// HTML start tags that can't appear inside SVG or MathML. A `<font>` only
+/// counts as HTML when it has presentational attributes.
+fn breaks_out_of_foreign_content(tag: &Tag) -> bool {
+    match tag.tag_name.as_str() {
+        "b" | "big" | "blockquote" | "body" | "br" | "center" | "code" | "dd" | "div" | "dl"
+        | "dt" | "em" | "embed" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "head" | "hr" | "i"
+        | "img" | "li" | "listing" | "menu" | "meta" | "nobr" | "ol" | "p" | "pre" | "ruby"
+        | "s" | "small" | "span" | "strong" | "strike" | "sub" | "sup" | "table" | "tt" | "u"
+        | "ul" | "var" => true,
+        "font" => tag
+            .attributes
+            .iter()
+            .any(|attribute| matches!(attribute.name.as_str(), "color" | "face" | "size")),
+        _ => false,
+    }
+}
+
+/// The tags that close a `<select>` inside a table.
+fn is_table_element(tag_name: &str) -> bool {
+    matches!(
+        tag_name,
+        "caption" | "table" | "tbody" | "tfoot" | "thead" | "tr" | "td" | "th"
+    )
+}
+
+fn is_heading(tag_name: &str) -> bool {
+    matches!(tag_name, "h1" | "h2" | "h3" | "h4" | "h5" | "h6")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quirks_mode_of(input: &str) -> QuirksMode {
+        parse_document(input.to_string()).document().quirks_mode
+    }
+
+    #[test]
+    fn decides_quirks_mode_from_the_doctype() {
+        let cases = [
+            ("<p>", QuirksMode::Quirks),
+            ("<!DOCTYPE html>", QuirksMode::NoQuirks),
+            (
+                "<!doctype HTML SYSTEM 'about:legacy-compat'>",
+                QuirksMode::NoQuirks,
+            ),
+            ("<!DOCTYPE>", QuirksMode::Quirks),
+            ("<!DOCTYPE html PUBLIC>", QuirksMode::Quirks),
+            ("<!DOCTYPE svg>", QuirksMode::Quirks),
+            (
+                "<!DOCTYPE html PUBLIC '-//IETF//DTD HTML 2.0//EN'>",
+                QuirksMode::Quirks,
+            ),
+            (
+                "<!DOCTYPE html PUBLIC '-//ietf//dtd html 2.0//en'>",
+                QuirksMode::Quirks,
+            ),
+            ("<!DOCTYPE html PUBLIC 'html'>", QuirksMode::Quirks),
+            (
+                "<!DOCTYPE html PUBLIC '-//W3O//DTD W3 HTML Strict 3.0//EN//'>",
+                QuirksMode::Quirks,
+            ),
+            (
+                "<!DOCTYPE html SYSTEM \
+                 'http://www.ibm.com/data/dtd/v11/ibmxhtml1-transitional.dtd'>",
+                QuirksMode::Quirks,
+            ),
+            (
+                "<!DOCTYPE html PUBLIC '-//W3C//DTD HTML 4.01 Transitional//EN'>",
+                QuirksMode::Quirks,
+            ),
+            (
+                "<!DOCTYPE html PUBLIC '-//W3C//DTD HTML 4.01 Frameset//EN'>",
+                QuirksMode::Quirks,
+            ),
+            (
+                "<!DOCTYPE html PUBLIC '-//W3C//DTD HTML 4.01 Transitional//EN' \
+                 'http://www.w3.org/TR/html4/loose.dtd'>",
+                QuirksMode::LimitedQuirks,
+            ),
+            (
+                "<!DOCTYPE html PUBLIC '-//W3C//DTD XHTML 1.0 Transitional//EN' \
+                 'http://www.w3.org/TR/xhtml1/DTD/xhtml1-transitional.dtd'>",
+                QuirksMode::LimitedQuirks,
+            ),
+            (
+                "<!DOCTYPE html PUBLIC '-//W3C//DTD XHTML 1.0 Frameset//EN'>",
+                QuirksMode::LimitedQuirks,
+            ),
+            (
+                "<!DOCTYPE html PUBLIC '-//W3C//DTD HTML 4.01//EN' \
+                 'http://www.w3.org/TR/html4/strict.dtd'>",
+                QuirksMode::NoQuirks,
+            ),
+            (
+                "<!DOCTYPE html PUBLIC '-//W3C//DTD HTML 4.01//EN'>",
+                QuirksMode::NoQuirks,
+            ),
+        ];
+        for (input, quirks_mode) in cases {
+            assert_eq!(quirks_mode_of(input), quirks_mode, "{}", input);
+        }
+    }
+
+    #[test]
+    fn only_the_first_doctype_counts() {
+        assert_eq!(
+            quirks_mode_of("<!DOCTYPE html><!DOCTYPE html PUBLIC 'html'>"),
+            QuirksMode::NoQuirks
+        );
+        assert_eq!(quirks_mode_of("<p><!DOCTYPE html>"), QuirksMode::Quirks);
+    }
+
+    #[test]
+    fn only_quirks_mode_keeps_tables_inside_paragraphs() {
+        let table_parent = |input: &str| {
+            let document = parse_document(input.to_string()).into_document();
+            let table = document.get_elements_by_tag_name("table")[0];
+            let parent = document.parent(table).unwrap();
+            document[parent]
+                .as_element()
+                .unwrap()
+                .local_name()
+                .to_string()
+        };
+        assert_eq!(table_parent("<p><table>"), "p");
+        assert_eq!(
+            table_parent(
+                "<!DOCTYPE html PUBLIC '-//W3C//DTD XHTML 1.0 Transitional//EN' ''><p><table>"
+            ),
+            "body"
+        );
+        assert_eq!(table_parent("<!DOCTYPE html><p><table>"), "body");
+    }
+
+    #[test]
+    fn fragments_are_rooted_at_a_document_fragment() {
+        let mut document = Document::new();
+        let tbody = document.create_element("tbody".to_string(), Namespace::HTML, Vec::new());
+        let parser = parse_fragment(&document, tbody, "<tr><td>a</td></tr>".to_string());
+        assert!(parser.errors().is_empty());
+        let fragment = parser.into_document();
+        assert!(matches!(
+            fragment[Document::ROOT].data(),
+            NodeData::DocumentFragment
+        ));
+        assert_eq!(fragment.to_string(), "| <tr>\n|   <td>\n|     \"a\"\n");
+        let implied = parse_fragment(&document, tbody, "<td>".to_string());
+        assert_eq!(implied.document().to_string(), "| <tr>\n|   <td>\n");
+    }
+
+    #[test]
+    fn fragments_take_the_quirks_mode_of_their_context() {
+        let document = parse_document("<div>".to_string()).into_document();
+        let div = document.get_elements_by_tag_name("div")[0];
+        let fragment = parse_fragment(&document, div, "<p><table>".to_string()).into_document();
+        assert_eq!(fragment.quirks_mode, QuirksMode::Quirks);
+        assert_eq!(fragment.to_string(), "| <p>\n|   <table>\n");
+    }
 }
@@ -3,17 +3,51 @@ pub enum Token {
     DOCTYPE(DOCTYPE),
     StartTag(Tag),
     EndTag(Tag),
-    Comment(String),
-    Character(char),
-    EndOfFile,
+    Comment(String, Span),
+    Character(char, Span),
+    EndOfFile(Span),
+}
+
+impl Token {
+    /// Where the token appears in the original input. Characters produced by
+    /// a character reference all span the whole reference.
+    pub fn span(&self) -> Span {
+        match self {
+            Token::DOCTYPE(doctype) => doctype.span,
+            Token::StartTag(tag) | Token::EndTag(tag) => tag.span,
+            Token::Comment(_, span) | Token::Character(_, span) | Token::EndOfFile(span) => *span,
+        }
+    }
+}
+
+/// A location in the original input, before newlines were normalized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Position {
+    /// Byte offset from the start of the input.
+    pub offset: usize,
+    /// Line number, starting at 1. CR, LF and CRLF all end a line.
+    pub line: usize,
+    /// Column number in characters, starting at 1.
+    pub column: usize,
+}
+
+/// The range of the original input between `start` (inclusive) and `end`
+/// (exclusive).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct DOCTYPE {
     pub name: String,
-    pub public_identifier: String,
-    pub system_public_identifier: String,
+    /// `None` when the identifier is missing, which quirks mode detection
+    /// treats differently from an empty one.
+    pub public_identifier: Option<String>,
+    pub system_identifier: Option<String>,
     pub force_quirks: bool,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -21,10 +55,15 @@ pub struct Tag {
     pub tag_name: String,
     pub self_closing: bool,
     pub attributes: Vec<Attribute>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct Attribute {
     pub name: String,
     pub value: String,
+    pub name_span: Span,
+    /// Excludes the quotes. An attribute without a value gets an empty span
+    /// at the end of its name.
+    pub value_span: Span,
 }
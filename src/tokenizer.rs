@@ -1,7 +1,10 @@
-use crate::token::{Attribute, Tag, Token, DOCTYPE};
+use crate::entities;
+use crate::error::{ErrorCode, ParseError};
+use crate::token::{Attribute, Position, Span, Tag, Token, DOCTYPE};
+use std::collections::VecDeque;
 
-#[derive(Debug)]
-enum State {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
     Data,
     RCDATA,
     RAWTEXT,
@@ -84,787 +87,2208 @@ enum State {
     NumericCharacterReferenceEnd,
 }
 
-pub struct Tokenizer {
+/// What the tokenizer should do after a [`TokenSink`] processed a token.
+///
+/// The tree builder is the one that knows when a start tag begins an element
+/// whose content isn't regular markup (`<title>`, `<style>`, `<script>`, ...),
+/// so it tells the tokenizer which state to continue in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenSinkResult {
+    Continue,
+    RCDATA,
+    RAWTEXT,
+    ScriptData,
+    PLAINTEXT,
+}
+
+/// Consumer of the tokens produced by a [`Tokenizer`].
+pub trait TokenSink {
+    fn process_token(&mut self, token: Token) -> TokenSinkResult;
+
+    /// Called whenever the input is malformed. Parsing always continues
+    /// afterwards.
+    fn parse_error(&mut self, _error: ParseError) {}
+
+    /// Whether there is an adjusted current node and it is an SVG or MathML
+    /// element, which is the only place `<![CDATA[` starts a CDATA section.
+    fn adjusted_current_node_present_but_not_in_html_namespace(&self) -> bool {
+        false
+    }
+}
+
+/// Sink that stores every token it receives, mostly useful for tests.
+#[derive(Debug, Default)]
+pub struct TokenCollector {
+    pub tokens: Vec<Token>,
+    pub errors: Vec<ParseError>,
+}
+
+impl TokenSink for TokenCollector {
+    fn process_token(&mut self, token: Token) -> TokenSinkResult {
+        self.tokens.push(token);
+        TokenSinkResult::Continue
+    }
+
+    fn parse_error(&mut self, error: ParseError) {
+        self.errors.push(error);
+    }
+}
+
+/// Sink behind the [`Iterator`] implementation of [`Tokenizer`].
+///
+/// Without a tree builder nobody decides when to leave the data state, so it
+/// switches to the state the spec uses for the content of the opened element.
+#[derive(Debug, Default)]
+pub struct TokenQueue {
+    tokens: VecDeque<Token>,
+    pub errors: Vec<ParseError>,
+}
+
+impl TokenSink for TokenQueue {
+    fn process_token(&mut self, token: Token) -> TokenSinkResult {
+        let result = match &token {
+            Token::StartTag(tag) => text_state_for(&tag.tag_name),
+            _ => TokenSinkResult::Continue,
+        };
+        self.tokens.push_back(token);
+        result
+    }
+
+    fn parse_error(&mut self, error: ParseError) {
+        self.errors.push(error);
+    }
+}
+
+/// The state the generic RCDATA and raw text element parsing algorithms put
+/// the tokenizer in for the content of an element named `tag_name`.
+pub fn text_state_for(tag_name: &str) -> TokenSinkResult {
+    match tag_name {
+        "title" | "textarea" => TokenSinkResult::RCDATA,
+        "style" | "xmp" | "iframe" | "noembed" | "noframes" => TokenSinkResult::RAWTEXT,
+        "script" => TokenSinkResult::ScriptData,
+        "plaintext" => TokenSinkResult::PLAINTEXT,
+        _ => TokenSinkResult::Continue,
+    }
+}
+
+/// Input stream preprocessing: newlines are normalized to LF and code points
+/// that are errors wherever they appear are recorded in `errors` along with
+/// their preprocessed offset. A leading byte order mark is dropped.
+///
+/// Surrogates can't occur in a `str`, so `surrogate-in-input-stream` never
+/// gets reported.
+fn preprocess_input(input: &str, errors: &mut Vec<(ErrorCode, usize)>) -> (String, SourceMap) {
+    let mut source_map = SourceMap::default();
+    let input = match input.strip_prefix('\u{FEFF}') {
+        Some(rest) => {
+            source_map.bom_len = '\u{FEFF}'.len_utf8();
+            rest
+        }
+        None => input,
+    };
+    let mut output = String::with_capacity(input.len());
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        if is_noncharacter(c) {
+            errors.push((ErrorCode::NoncharacterInInputStream, output.len()));
+        } else if is_control(c) && !c.is_ascii_whitespace() && c != '\0' {
+            errors.push((ErrorCode::ControlCharacterInInputStream, output.len()));
+        }
+        if c == '\r' {
+            if chars.peek() == Some(&'\n') {
+                chars.next();
+                source_map.dropped_crs.push(output.len() + 1);
+            }
+            output.push('\n');
+        } else {
+            output.push(c);
+        }
+    }
+    (output, source_map)
+}
+
+/// Maps offsets in the preprocessed input back to positions in the original
+/// input.
+#[derive(Debug, Default)]
+struct SourceMap {
+    bom_len: usize,
+    /// Preprocessed offsets from which the original input is one more byte
+    /// ahead, one for every CR dropped from a CRLF pair.
+    dropped_crs: Vec<usize>,
+    /// Offset, line and column of the last lookup. Lookups mostly move
+    /// forward, so they only have to count the characters in between.
+    last: (usize, usize, usize),
+}
+
+impl SourceMap {
+    fn position(&mut self, input: &str, offset: usize) -> Position {
+        let (mut from, mut line, mut column) = self.last;
+        if from == 0 {
+            (line, column) = (1, 1);
+        }
+        if offset < from {
+            let skipped = &input[offset..from];
+            let newlines = skipped.matches('\n').count();
+            if newlines == 0 {
+                column -= skipped.chars().count();
+                from = offset;
+            } else {
+                line -= newlines;
+                column = 1;
+                from = input[..offset].rfind('\n').map_or(0, |i| i + 1);
+            }
+        }
+        for c in input[from..offset].chars() {
+            if c == '\n' {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+        }
+        self.last = (offset, line, column);
+        let dropped = self.dropped_crs.partition_point(|&o| o <= offset);
+        Position {
+            offset: offset + self.bom_len + dropped,
+            line,
+            column,
+        }
+    }
+}
+
+fn is_noncharacter(c: char) -> bool {
+    let c = c as u32;
+    (0xFDD0..=0xFDEF).contains(&c) || c & 0xFFFE == 0xFFFE
+}
+
+/// C0 controls, DEL and the C1 controls.
+fn is_control(c: char) -> bool {
+    matches!(c, '\u{0}'..='\u{1F}' | '\u{7F}'..='\u{9F}')
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TagKind {
+    StartTag,
+    EndTag,
+}
+
+pub struct Tokenizer<Sink> {
+    sink: Sink,
     input: String,
+    source_map: SourceMap,
+    /// Byte offset of the current input character.
     pos: usize,
+    /// Where the `<` of the tag, comment or DOCTYPE being tokenized is.
+    token_start: usize,
     current_state: State,
     return_state: State,
+    current_tag_kind: TagKind,
+    current_tag: Tag,
+    /// Set when the attribute being tokenized repeats an earlier name; it is
+    /// dropped once its value has been consumed.
+    current_attribute_is_duplicate: bool,
+    current_comment: String,
+    current_doctype: DOCTYPE,
+    temp_buffer: String,
+    character_reference_code: u32,
+    /// Where the `&` of the character reference being tokenized is.
+    character_reference_start: usize,
+    last_start_tag_name: String,
+    emitted_eof: bool,
 }
 
-impl Tokenizer {
-    pub fn new(input: String) -> Self {
-        let input = input.trim().to_string().replace('\n', "");
-        Self {
+impl<Sink: TokenSink> Tokenizer<Sink> {
+    pub fn new(input: String, sink: Sink) -> Self {
+        let mut errors = Vec::new();
+        let (input, source_map) = preprocess_input(&input, &mut errors);
+        let mut tokenizer = Self {
+            sink,
             input,
+            source_map,
             pos: 0,
+            token_start: 0,
             current_state: State::Data,
             return_state: State::Data,
+            current_tag_kind: TagKind::StartTag,
+            current_tag: Tag::default(),
+            current_attribute_is_duplicate: false,
+            current_comment: String::new(),
+            current_doctype: DOCTYPE::default(),
+            temp_buffer: String::new(),
+            character_reference_code: 0,
+            character_reference_start: 0,
+            last_start_tag_name: String::new(),
+            emitted_eof: false,
+        };
+        for (code, offset) in errors {
+            tokenizer.parse_error_at(code, offset);
         }
+        tokenizer
+    }
+
+    pub fn sink(&self) -> &Sink {
+        &self.sink
     }
 
+    pub fn sink_mut(&mut self) -> &mut Sink {
+        &mut self.sink
+    }
+
+    pub fn into_sink(self) -> Sink {
+        self.sink
+    }
+
+    /// Overrides the state the tokenizer continues in, e.g. to tokenize the
+    /// content of an element the sink didn't know about as RAWTEXT.
+    pub fn set_state(&mut self, state: State) {
+        self.current_state = state;
+    }
+
+    /// Switches to the state a sink asked for, if any. Also used to start in
+    /// the right state when tokenizing the content of a given element.
+    pub fn switch_to_text_state(&mut self, state: TokenSinkResult) {
+        match state {
+            TokenSinkResult::Continue => {}
+            TokenSinkResult::RCDATA => self.current_state = State::RCDATA,
+            TokenSinkResult::RAWTEXT => self.current_state = State::RAWTEXT,
+            TokenSinkResult::ScriptData => self.current_state = State::ScriptData,
+            TokenSinkResult::PLAINTEXT => self.current_state = State::PLAINTEXT,
+        }
+    }
+
+    /// The character starting at the byte offset `pos`. Callers make sure
+    /// the end of the input hasn't been reached yet.
     fn current_char(&self) -> char {
-        self.input.chars().nth(self.pos).unwrap()
+        self.input[self.pos..].chars().next().unwrap()
+    }
+
+    /// Byte offset of the character following the current one.
+    fn next_pos(&self) -> usize {
+        self.pos + self.current_char().len_utf8()
     }
 
-    #[allow(non_snake_case)]
-    fn switch_to(&mut self, State: State) {
-        self.current_state = State;
-        self.pos += 1;
+    fn consume(&mut self) {
+        self.pos = self.next_pos();
     }
 
-    #[allow(non_snake_case)]
-    fn switch_to_without_consuming(&mut self, State: State) {
-        self.current_state = State;
+    fn switch_to(&mut self, state: State) {
+        self.current_state = state;
+        self.consume();
+    }
+
+    fn switch_to_without_consuming(&mut self, state: State) {
+        self.current_state = state;
     }
 
     fn next_chars(&self, string: &str) -> bool {
-        if self.input[self.pos + 1..].starts_with(string) {
-            true
-        } else {
-            false
-        }
+        self.input[self.next_pos()..].starts_with(string)
+    }
+
+    fn next_chars_ignore_ascii_case(&self, string: &str) -> bool {
+        self.input
+            .get(self.next_pos()..self.next_pos() + string.len())
+            .is_some_and(|s| s.eq_ignore_ascii_case(string))
+    }
+
+    /// Like [`Self::next_chars_ignore_ascii_case`], but starting with the
+    /// current input character.
+    fn current_chars_ignore_ascii_case(&self, string: &str) -> bool {
+        self.input
+            .get(self.pos..self.pos + string.len())
+            .is_some_and(|s| s.eq_ignore_ascii_case(string))
     }
 
+    /// Skips `n` bytes, only used after matching ASCII keywords.
     fn advance_by(&mut self, n: usize) {
         self.pos += n
     }
 
-    fn consume_if(&mut self, condition: bool) {
-        if condition {
-            self.pos += 1
+    fn parse_error(&mut self, code: ErrorCode) {
+        self.parse_error_at(code, self.pos);
+    }
+
+    fn parse_error_at(&mut self, code: ErrorCode, offset: usize) {
+        let position = self.position(offset);
+        self.sink.parse_error(ParseError { code, position });
+    }
+
+    /// Maps a byte offset in the preprocessed input to the original input.
+    fn position(&mut self, offset: usize) -> Position {
+        self.source_map.position(&self.input, offset)
+    }
+
+    fn span(&mut self, start: usize, end: usize) -> Span {
+        Span {
+            start: self.position(start),
+            end: self.position(end),
         }
     }
 
-    fn emit_token(&self, token: &Token) {
-        match token {
-            Token::Character(c) => {
-                if c == &' ' {
-                    return;
+    fn create_tag(&mut self, kind: TagKind) {
+        self.current_tag_kind = kind;
+        self.current_tag = Tag {
+            ..Default::default()
+        };
+        self.current_attribute_is_duplicate = false;
+    }
+
+    fn start_new_attribute(&mut self) {
+        self.drop_duplicate_attribute();
+        let name_span = self.span(self.pos, self.pos);
+        self.current_tag.attributes.push(Attribute {
+            name_span,
+            ..Default::default()
+        });
+    }
+
+    fn current_attribute(&mut self) -> &mut Attribute {
+        self.current_tag
+            .attributes
+            .last_mut()
+            .expect("attribute states always have a current attribute")
+    }
+
+    /// Called when leaving the attribute name state: a repeated name is an
+    /// error and the attribute gets removed from the token.
+    fn finish_attribute_name(&mut self) {
+        let end = self.position(self.pos);
+        let attribute = self.current_attribute();
+        attribute.name_span.end = end;
+        attribute.value_span = Span { start: end, end };
+        if let Some((current, previous)) = self.current_tag.attributes.split_last() {
+            if previous.iter().any(|a| a.name == current.name) {
+                self.parse_error(ErrorCode::DuplicateAttribute);
+                self.current_attribute_is_duplicate = true;
+            }
+        }
+    }
+
+    fn start_attribute_value(&mut self) {
+        let start = self.position(self.pos);
+        self.current_attribute().value_span.start = start;
+    }
+
+    fn finish_attribute_value(&mut self) {
+        let end = self.position(self.pos);
+        self.current_attribute().value_span.end = end;
+    }
+
+    fn drop_duplicate_attribute(&mut self) {
+        if self.current_attribute_is_duplicate {
+            self.current_tag.attributes.pop();
+            self.current_attribute_is_duplicate = false;
+        }
+    }
+
+    /// Hands `token` to the sink and switches to whatever text state the sink
+    /// asked for. Tags must be emitted *after* switching back to the data
+    /// state, otherwise the sink's request would be overwritten.
+    fn emit_token(&mut self, token: Token) {
+        let state = self.sink.process_token(token);
+        self.switch_to_text_state(state);
+    }
+
+    fn emit_current_tag(&mut self) {
+        self.drop_duplicate_attribute();
+        let mut tag = std::mem::take(&mut self.current_tag);
+        tag.span = self.span(self.token_start, self.pos);
+        match self.current_tag_kind {
+            TagKind::StartTag => {
+                self.last_start_tag_name = tag.tag_name.clone();
+                self.emit_token(Token::StartTag(tag));
+            }
+            TagKind::EndTag => {
+                if !tag.attributes.is_empty() {
+                    self.parse_error(ErrorCode::EndTagWithAttributes);
                 }
+                if tag.self_closing {
+                    self.parse_error(ErrorCode::EndTagWithTrailingSolidus);
+                }
+                self.emit_token(Token::EndTag(tag));
             }
-            _ => {}
         }
-        println!("{:?}", token);
+    }
+
+    fn emit_current_comment(&mut self) {
+        let comment = std::mem::take(&mut self.current_comment);
+        let span = self.span(self.token_start, self.pos);
+        self.emit_token(Token::Comment(comment, span));
+    }
+
+    fn emit_current_doctype(&mut self) {
+        let mut doctype = std::mem::take(&mut self.current_doctype);
+        doctype.span = self.span(self.token_start, self.pos);
+        self.emit_token(Token::DOCTYPE(doctype));
+    }
+
+    fn emit_char(&mut self, c: char, span: Span) {
+        self.emit_token(Token::Character(c, span));
     }
 
     fn emit_current_char_as_token(&mut self) {
-        self.emit_token(&Token::Character(self.current_char()));
-        self.pos += 1;
+        let span = self.span(self.pos, self.next_pos());
+        self.emit_char(self.current_char(), span);
+        self.consume();
+    }
+
+    /// What most states do with a U+0000 NULL in the input.
+    fn emit_replacement_character(&mut self) {
+        self.parse_error(ErrorCode::UnexpectedNullCharacter);
+        let span = self.span(self.pos, self.next_pos());
+        self.emit_char('\u{FFFD}', span);
+        self.consume();
+    }
+
+    /// Emits characters that were consumed as they appear in the input,
+    /// starting at the byte offset `start`.
+    fn emit_chars(&mut self, chars: &str, start: usize) {
+        let mut offset = start;
+        for c in chars.chars() {
+            let span = self.span(offset, offset + c.len_utf8());
+            self.emit_char(c, span);
+            offset += c.len_utf8();
+        }
+    }
+
+    /// Emits the `<` just consumed as text.
+    fn emit_less_than_sign(&mut self) {
+        self.emit_chars("<", self.pos - 1);
+    }
+
+    /// The current input character, with U+0000 NULL replaced by U+FFFD as
+    /// an `unexpected-null-character` error, for appending to a token.
+    fn current_char_for_token(&mut self) -> char {
+        if self.current_char() == '\0' {
+            self.parse_error(ErrorCode::UnexpectedNullCharacter);
+            '\u{FFFD}'
+        } else {
+            self.current_char()
+        }
+    }
+
+    /// An appropriate end tag token is an end tag token whose tag name matches
+    /// the tag name of the last start tag to have been emitted.
+    fn is_appropriate_end_tag(&self) -> bool {
+        self.current_tag_kind == TagKind::EndTag
+            && !self.last_start_tag_name.is_empty()
+            && self.current_tag.tag_name == self.last_start_tag_name
+    }
+
+    /// Shared "end tag open" state of the RCDATA, RAWTEXT and script data
+    /// states: starts an end tag if a letter follows `</`, otherwise emits the
+    /// `</` as text and goes back to `text_state`.
+    fn end_tag_open(&mut self, name_state: State, text_state: State) {
+        if self.current_char().is_ascii_alphabetic() {
+            self.create_tag(TagKind::EndTag);
+            self.switch_to_without_consuming(name_state);
+        } else {
+            self.end_tag_as_text(text_state);
+        }
+    }
+
+    /// Shared "end tag name" state of the RCDATA, RAWTEXT and script data
+    /// states. Only an appropriate end tag can close the element; anything
+    /// else is emitted back as text.
+    fn end_tag_name(&mut self, text_state: State) {
+        let c = self.current_char();
+        let appropriate = self.is_appropriate_end_tag();
+        if c.is_ascii_whitespace() && appropriate {
+            self.switch_to(State::BeforeAttributeName);
+        } else if c == '/' && appropriate {
+            self.switch_to(State::SelfClosingStartTag);
+        } else if c == '>' && appropriate {
+            self.switch_to(State::Data);
+            self.emit_current_tag();
+        } else if c.is_ascii_alphabetic() {
+            self.current_tag.tag_name.push(c.to_ascii_lowercase());
+            self.temp_buffer.push(c);
+            self.consume();
+        } else {
+            self.end_tag_as_text(text_state);
+        }
+    }
+
+    /// Emits the `</` and the tag name consumed so far as text after all.
+    fn end_tag_as_text(&mut self, text_state: State) {
+        let temp_buffer = std::mem::take(&mut self.temp_buffer);
+        self.emit_chars("</", self.token_start);
+        self.emit_chars(&temp_buffer, self.token_start + 2);
+        self.switch_to_without_consuming(text_state);
+    }
+
+    /// Shared by the script data double escape start and end states, which
+    /// only differ in the direction they switch. A `script` tag name (start or
+    /// end, the content is just text at this point) followed by whitespace,
+    /// `/` or `>` toggles between escaped and double escaped script data.
+    fn double_escape_boundary(&mut self, script_state: State, other_state: State) {
+        let c = self.current_char();
+        if c.is_ascii_whitespace() || c == '/' || c == '>' {
+            if self.temp_buffer == "script" {
+                self.switch_to_without_consuming(script_state);
+            } else {
+                self.switch_to_without_consuming(other_state);
+            }
+            self.emit_current_char_as_token();
+        } else if c.is_ascii_alphabetic() {
+            self.temp_buffer.push(c.to_ascii_lowercase());
+            self.emit_current_char_as_token();
+        } else {
+            self.switch_to_without_consuming(other_state);
+        }
+    }
+
+    /// What the after DOCTYPE public keyword and before DOCTYPE public
+    /// identifier states do with anything but whitespace.
+    fn before_doctype_public_identifier(&mut self) {
+        if self.current_char() == '"' {
+            self.current_doctype.public_identifier = Some(String::new());
+            self.switch_to(State::DOCTYPEPublicIdentifierDoubleQuoted);
+        } else if self.current_char() == '\'' {
+            self.current_doctype.public_identifier = Some(String::new());
+            self.switch_to(State::DOCTYPEPublicIdentifierSingleQuoted);
+        } else if self.current_char() == '>' {
+            self.parse_error(ErrorCode::MissingDoctypePublicIdentifier);
+            self.current_doctype.force_quirks = true;
+            self.switch_to(State::Data);
+            self.emit_current_doctype();
+        } else {
+            self.parse_error(ErrorCode::MissingQuoteBeforeDoctypePublicIdentifier);
+            self.current_doctype.force_quirks = true;
+            self.switch_to_without_consuming(State::BogusDOCTYPE);
+        }
+    }
+
+    /// Counterpart of [`Self::before_doctype_public_identifier`] for the
+    /// states that may be followed by a system identifier. Callers handle `>`
+    /// themselves as not all of them force quirks mode for it.
+    fn before_doctype_system_identifier(&mut self) {
+        if self.current_char() == '"' {
+            self.current_doctype.system_identifier = Some(String::new());
+            self.switch_to(State::DOCTYPESystemIdentifierDoubleQuoted);
+        } else if self.current_char() == '\'' {
+            self.current_doctype.system_identifier = Some(String::new());
+            self.switch_to(State::DOCTYPESystemIdentifierSingleQuoted);
+        } else {
+            self.parse_error(ErrorCode::MissingQuoteBeforeDoctypeSystemIdentifier);
+            self.current_doctype.force_quirks = true;
+            self.switch_to_without_consuming(State::BogusDOCTYPE);
+        }
+    }
+
+    fn is_consumed_as_part_of_an_attribute(&self) -> bool {
+        matches!(
+            self.return_state,
+            State::AttributeValueDoubleQuoted
+                | State::AttributeValueSingleQuoted
+                | State::AttributeValueUnquoted
+        )
+    }
+
+    fn flush_code_points_consumed_as_character_reference(&mut self) {
+        let temp_buffer = std::mem::take(&mut self.temp_buffer);
+        if self.is_consumed_as_part_of_an_attribute() {
+            self.current_attribute().value.push_str(&temp_buffer);
+        } else if self.input[self.character_reference_start..self.pos] == temp_buffer {
+            self.emit_chars(&temp_buffer, self.character_reference_start);
+        } else {
+            let span = self.span(self.character_reference_start, self.pos);
+            for c in temp_buffer.chars() {
+                self.emit_char(c, span);
+            }
+        }
+    }
+
+    /// Finds the longest named character reference the input starts with,
+    /// returning the length of its name and the characters it stands for.
+    fn match_named_character_reference(&self) -> Option<(usize, &'static str)> {
+        let rest = &self.input[self.pos..];
+        let candidate_len = rest
+            .bytes()
+            .take(entities::LONGEST_NAMED_CHARACTER_REFERENCE)
+            .take_while(|b| b.is_ascii_alphanumeric() || *b == b';')
+            .count();
+        (1..=candidate_len)
+            .rev()
+            .find_map(|len| entities::named_character_reference(&rest[..len]).map(|v| (len, v)))
+    }
+
+    /// Values past the Unicode range all end up as U+FFFD, so the code only
+    /// has to be kept from overflowing.
+    fn add_to_character_reference_code(&mut self, base: u32, digit: u32) {
+        self.character_reference_code = self
+            .character_reference_code
+            .saturating_mul(base)
+            .saturating_add(digit)
+            .min(0x110000);
     }
 
+    /// Tokenizes the whole input, handing every token to the sink.
     pub fn run(&mut self) {
-        let mut current_token = Token::EndOfFile;
-        let mut attribute_id = 0;
-        let mut temp_buffer = String::new();
-
-        while self.pos <= self.input.len() - 1 {
-            #[cfg(feature = "debug")]
-            {
-                println!("Current state: '{:?}'", self.current_state);
-                println!(
-                    "Pos: {}/{} | '{}'",
-                    self.pos,
-                    self.input.len(),
-                    self.current_char()
-                );
+        while self.step() {}
+    }
+
+    /// Runs a single iteration of the state machine. Returns `false` once the
+    /// input is exhausted and the `EndOfFile` token has been emitted.
+    fn step(&mut self) -> bool {
+        if self.emitted_eof {
+            return false;
+        }
+        if self.pos >= self.input.len() {
+            self.step_at_eof();
+            return true;
+        }
+
+        #[cfg(feature = "debug")]
+        {
+            println!("Current state: '{:?}'", self.current_state);
+            println!(
+                "Pos: {}/{} | '{}'",
+                self.pos,
+                self.input.len(),
+                self.current_char()
+            );
+        }
+        match self.current_state {
+            State::Data => {
+                if self.current_char() == '&' {
+                    self.return_state = State::Data;
+                    self.switch_to(State::CharacterReference);
+                } else if self.current_char() == '<' {
+                    self.token_start = self.pos;
+                    self.switch_to(State::TagOpen);
+                } else if self.current_char() == '\0' {
+                    // Unlike in the other text states the NULL is kept, the
+                    // tree builder decides what to do with it.
+                    self.parse_error(ErrorCode::UnexpectedNullCharacter);
+                    self.emit_current_char_as_token();
+                } else {
+                    self.emit_current_char_as_token();
+                }
             }
-            match self.current_state {
-                State::Data => {
-                    if self.current_char() == '&' {
-                        self.return_state = State::Data;
-                        self.switch_to_without_consuming(State::CharacterReference);
-                    } else if self.current_char() == '<' {
-                        self.switch_to(State::TagOpen);
-                    } else {
-                        self.emit_current_char_as_token();
-                    }
+            State::RCDATA => {
+                if self.current_char() == '&' {
+                    self.return_state = State::RCDATA;
+                    self.switch_to(State::CharacterReference);
+                } else if self.current_char() == '<' {
+                    self.token_start = self.pos;
+                    self.switch_to(State::RCDATALessThanSign);
+                } else if self.current_char() == '\0' {
+                    self.emit_replacement_character();
+                } else {
+                    self.emit_current_char_as_token();
                 }
-                State::RCDATA => {
-                    if self.current_char() == '&' {
-                        self.return_state = State::RCDATA;
-                        self.switch_to_without_consuming(State::CharacterReference);
-                    } else if self.current_char() == '<' {
-                        self.switch_to(State::RCDATALessThanSign);
-                    } else {
-                        self.emit_current_char_as_token();
-                    }
+            }
+            State::RAWTEXT => {
+                if self.current_char() == '<' {
+                    self.token_start = self.pos;
+                    self.switch_to(State::RAWTEXTLessThanSign);
+                } else if self.current_char() == '\0' {
+                    self.emit_replacement_character();
+                } else {
+                    self.emit_current_char_as_token();
                 }
-                State::RAWTEXT => {
-                    if self.current_char() == '<' {
-                        self.switch_to(State::RAWTEXTLessThanSign);
-                    } else {
-                        self.emit_current_char_as_token();
-                    }
+            }
+            State::ScriptData => {
+                if self.current_char() == '<' {
+                    self.token_start = self.pos;
+                    self.switch_to(State::ScriptDataLessThanSign);
+                } else if self.current_char() == '\0' {
+                    self.emit_replacement_character();
+                } else {
+                    self.emit_current_char_as_token();
                 }
-                State::ScriptData => {
-                    if self.current_char() == '<' {
-                        self.switch_to(State::ScriptDataLessThanSign);
-                    } else {
-                        self.emit_current_char_as_token();
-                    }
+            }
+            State::PLAINTEXT => {
+                if self.current_char() == '\0' {
+                    self.emit_replacement_character();
+                } else {
+                    self.emit_current_char_as_token();
                 }
-                State::PLAINTEXT => {
+            }
+            State::TagOpen => {
+                if self.current_char() == '!' {
+                    self.switch_to_without_consuming(State::MarkupDeclarationOpen);
+                } else if self.current_char() == '/' {
+                    self.switch_to(State::EndTagOpen);
+                } else if self.current_char().is_ascii_alphabetic() {
+                    self.create_tag(TagKind::StartTag);
+                    self.switch_to_without_consuming(State::TagName);
+                } else if self.current_char() == '?' {
+                    self.parse_error(ErrorCode::UnexpectedQuestionMarkInsteadOfTagName);
+                    self.current_comment = String::new();
+                    self.switch_to_without_consuming(State::BogusComment);
+                } else {
+                    self.parse_error(ErrorCode::InvalidFirstCharacterOfTagName);
+                    self.emit_less_than_sign();
+                    self.switch_to_without_consuming(State::Data);
+                }
+            }
+            State::EndTagOpen => {
+                if self.current_char().is_ascii_alphabetic() {
+                    self.create_tag(TagKind::EndTag);
+                    self.switch_to_without_consuming(State::TagName);
+                } else if self.current_char() == '>' {
+                    self.parse_error(ErrorCode::MissingEndTagName);
+                    self.switch_to(State::Data);
+                } else {
+                    self.parse_error(ErrorCode::InvalidFirstCharacterOfTagName);
+                    self.current_comment = String::new();
+                    self.switch_to_without_consuming(State::BogusComment);
+                }
+            }
+            State::TagName => {
+                if self.current_char().is_ascii_whitespace() {
+                    self.switch_to(State::BeforeAttributeName);
+                } else if self.current_char() == '/' {
+                    self.switch_to(State::SelfClosingStartTag);
+                } else if self.current_char() == '>' {
+                    self.switch_to(State::Data);
+                    self.emit_current_tag();
+                } else {
+                    let c = self.current_char_for_token().to_ascii_lowercase();
+                    self.current_tag.tag_name.push(c);
+                    self.consume();
+                }
+            }
+            State::RCDATALessThanSign => {
+                if self.current_char() == '/' {
+                    self.temp_buffer = String::new();
+                    self.switch_to(State::RCDATAEndTagOpen);
+                } else {
+                    self.emit_less_than_sign();
+                    self.switch_to_without_consuming(State::RCDATA);
+                }
+            }
+            State::RCDATAEndTagOpen => {
+                self.end_tag_open(State::RCDATAEndTagName, State::RCDATA);
+            }
+            State::RCDATAEndTagName => {
+                self.end_tag_name(State::RCDATA);
+            }
+            State::RAWTEXTLessThanSign => {
+                if self.current_char() == '/' {
+                    self.temp_buffer = String::new();
+                    self.switch_to(State::RAWTEXTEndTagOpen);
+                } else {
+                    self.emit_less_than_sign();
+                    self.switch_to_without_consuming(State::RAWTEXT);
+                }
+            }
+            State::RAWTEXTEndTagOpen => {
+                self.end_tag_open(State::RAWTEXTEndTagName, State::RAWTEXT);
+            }
+            State::RAWTEXTEndTagName => {
+                self.end_tag_name(State::RAWTEXT);
+            }
+            State::ScriptDataLessThanSign => {
+                if self.current_char() == '/' {
+                    self.temp_buffer = String::new();
+                    self.switch_to(State::ScriptDataEndTagOpen);
+                } else if self.current_char() == '!' {
+                    self.emit_chars("<!", self.pos - 1);
+                    self.switch_to(State::ScriptDataEscapeStart);
+                } else {
+                    self.emit_less_than_sign();
+                    self.switch_to_without_consuming(State::ScriptData);
+                }
+            }
+            State::ScriptDataEndTagOpen => {
+                self.end_tag_open(State::ScriptDataEndTagName, State::ScriptData);
+            }
+            State::ScriptDataEndTagName => {
+                self.end_tag_name(State::ScriptData);
+            }
+            State::ScriptDataEscapeStart => {
+                if self.current_char() == '-' {
+                    self.switch_to_without_consuming(State::ScriptDataEscapeStartDash);
                     self.emit_current_char_as_token();
+                } else {
+                    self.switch_to_without_consuming(State::ScriptData);
                 }
-                State::TagOpen => {
-                    if self.current_char() == '!' {
-                        self.switch_to_without_consuming(State::MarkupDeclarationOpen);
-                    } else if self.current_char() == '/' {
-                        self.switch_to(State::EndTagOpen);
-                    } else if self.current_char().is_ascii_alphabetic() {
-                        current_token = Token::StartTag(Tag {
-                            ..Default::default()
-                        });
-                        self.switch_to_without_consuming(State::TagName);
-                    } else if self.current_char() == '?' {
-                        current_token = Token::Comment("".to_string());
-                        self.switch_to_without_consuming(State::BogusComment);
-                    } else {
-                        self.emit_token(&Token::Character('>'));
-                        self.switch_to_without_consuming(State::Data);
-                    }
+            }
+            State::ScriptDataEscapeStartDash => {
+                if self.current_char() == '-' {
+                    self.switch_to_without_consuming(State::ScriptDataEscapedDashDash);
+                    self.emit_current_char_as_token();
+                } else {
+                    self.switch_to_without_consuming(State::ScriptData);
                 }
-                State::EndTagOpen => {
-                    if self.current_char().is_ascii_alphabetic() {
-                        current_token = Token::EndTag(Tag {
-                            ..Default::default()
-                        });
-                        self.switch_to_without_consuming(State::TagName);
-                    } else if self.current_char() == '>' {
-                        self.switch_to(State::Data);
-                    } else {
-                        current_token = Token::Comment("".to_string());
-                        self.switch_to_without_consuming(State::BogusComment);
-                    }
+            }
+            State::ScriptDataEscaped => {
+                if self.current_char() == '-' {
+                    self.switch_to_without_consuming(State::ScriptDataEscapedDash);
+                    self.emit_current_char_as_token();
+                } else if self.current_char() == '<' {
+                    self.token_start = self.pos;
+                    self.switch_to(State::ScriptDataEscapedLessThanSign);
+                } else if self.current_char() == '\0' {
+                    self.emit_replacement_character();
+                } else {
+                    self.emit_current_char_as_token();
                 }
-                State::TagName => {
-                    if self.current_char().is_ascii_whitespace() {
-                        self.switch_to(State::BeforeAttributeName);
-                    } else if self.current_char() == '/' {
-                        self.switch_to(State::SelfClosingStartTag);
-                    } else if self.current_char() == '>' {
-                        self.emit_token(&current_token);
-                        self.switch_to(State::Data);
-                    } else if self.current_char().is_ascii_uppercase() {
-                        current_token = match current_token {
-                            Token::StartTag(mut t) => {
-                                t.tag_name.push(self.current_char().to_ascii_lowercase());
-                                Token::StartTag(t)
-                            }
-                            Token::EndTag(mut t) => {
-                                t.tag_name.push(self.current_char().to_ascii_lowercase());
-                                Token::EndTag(t)
-                            }
-                            _ => panic!("Not a tag token!"),
-                        };
-                        self.pos += 1;
-                    } else {
-                        current_token = match current_token {
-                            Token::StartTag(mut t) => {
-                                t.tag_name.push(self.current_char().to_ascii_lowercase());
-                                Token::StartTag(t)
-                            }
-                            Token::EndTag(mut t) => {
-                                t.tag_name.push(self.current_char().to_ascii_lowercase());
-                                Token::EndTag(t)
-                            }
-                            _ => panic!("Not a tag token!"),
-                        };
-                        self.pos += 1;
-                    }
+            }
+            State::ScriptDataEscapedDash => {
+                if self.current_char() == '-' {
+                    self.switch_to_without_consuming(State::ScriptDataEscapedDashDash);
+                    self.emit_current_char_as_token();
+                } else if self.current_char() == '<' {
+                    self.token_start = self.pos;
+                    self.switch_to(State::ScriptDataEscapedLessThanSign);
+                } else if self.current_char() == '\0' {
+                    self.switch_to_without_consuming(State::ScriptDataEscaped);
+                    self.emit_replacement_character();
+                } else {
+                    self.switch_to_without_consuming(State::ScriptDataEscaped);
+                    self.emit_current_char_as_token();
                 }
-                State::RCDATALessThanSign => {
-                    if self.current_char() == '/' {
-                        temp_buffer = String::new();
-                        self.switch_to(State::RCDATAEndTagOpen);
-                    } else {
-                        self.emit_token(&Token::Character('<'));
-                        self.switch_to_without_consuming(State::RCDATA);
-                    }
+            }
+            State::ScriptDataEscapedDashDash => {
+                if self.current_char() == '-' {
+                    self.emit_current_char_as_token();
+                } else if self.current_char() == '<' {
+                    self.token_start = self.pos;
+                    self.switch_to(State::ScriptDataEscapedLessThanSign);
+                } else if self.current_char() == '>' {
+                    self.switch_to_without_consuming(State::ScriptData);
+                    self.emit_current_char_as_token();
+                } else if self.current_char() == '\0' {
+                    self.switch_to_without_consuming(State::ScriptDataEscaped);
+                    self.emit_replacement_character();
+                } else {
+                    self.switch_to_without_consuming(State::ScriptDataEscaped);
+                    self.emit_current_char_as_token();
                 }
-                State::RCDATAEndTagOpen => {
-                    if self.current_char().is_ascii_alphabetic() {
-                        current_token = Token::EndTag(Tag {
-                            ..Default::default()
-                        });
-                    } else {
-                        self.emit_token(&Token::Character('<'));
-                        self.switch_to_without_consuming(State::RCDATA);
-                    }
+            }
+            State::ScriptDataEscapedLessThanSign => {
+                if self.current_char() == '/' {
+                    self.temp_buffer = String::new();
+                    self.switch_to(State::ScriptDataEscapedEndTagOpen);
+                } else if self.current_char().is_ascii_alphabetic() {
+                    self.temp_buffer = String::new();
+                    self.emit_less_than_sign();
+                    self.switch_to_without_consuming(State::ScriptDataDoubleEscapeStart);
+                } else {
+                    self.emit_less_than_sign();
+                    self.switch_to_without_consuming(State::ScriptDataEscaped);
                 }
-                State::RCDATAEndTagName => {
-                    if self.current_char().is_ascii_whitespace() {
-                    } else if self.current_char() == '/' {
-                    } else if self.current_char() == '>' {
-                    } else if self.current_char().is_ascii_alphabetic() {
-                    } else {
-                    }
+            }
+            State::ScriptDataEscapedEndTagOpen => {
+                self.end_tag_open(State::ScriptDataEscapedEndTagName, State::ScriptDataEscaped);
+            }
+            State::ScriptDataEscapedEndTagName => {
+                self.end_tag_name(State::ScriptDataEscaped);
+            }
+            State::ScriptDataDoubleEscapeStart => {
+                self.double_escape_boundary(
+                    State::ScriptDataDoubleEscaped,
+                    State::ScriptDataEscaped,
+                );
+            }
+            State::ScriptDataDoubleEscaped => {
+                if self.current_char() == '-' {
+                    self.switch_to_without_consuming(State::ScriptDataDoubleEscapedDash);
+                    self.emit_current_char_as_token();
+                } else if self.current_char() == '<' {
+                    self.switch_to_without_consuming(State::ScriptDataDoubleEscapedLessThanSign);
+                    self.emit_current_char_as_token();
+                } else if self.current_char() == '\0' {
+                    self.emit_replacement_character();
+                } else {
+                    self.emit_current_char_as_token();
                 }
-                State::RAWTEXTLessThanSign => {
-                    if self.current_char() == '/' {
-                        temp_buffer = String::new();
-                        self.switch_to(State::RAWTEXTEndTagOpen);
-                    } else {
-                        self.emit_token(&Token::Character('<'));
-                        self.switch_to_without_consuming(State::RAWTEXT);
-                    }
+            }
+            State::ScriptDataDoubleEscapedDash => {
+                if self.current_char() == '-' {
+                    self.switch_to_without_consuming(State::ScriptDataDoubleEscapedDashDash);
+                    self.emit_current_char_as_token();
+                } else if self.current_char() == '<' {
+                    self.switch_to_without_consuming(State::ScriptDataDoubleEscapedLessThanSign);
+                    self.emit_current_char_as_token();
+                } else if self.current_char() == '\0' {
+                    self.switch_to_without_consuming(State::ScriptDataDoubleEscaped);
+                    self.emit_replacement_character();
+                } else {
+                    self.switch_to_without_consuming(State::ScriptDataDoubleEscaped);
+                    self.emit_current_char_as_token();
                 }
-                State::RAWTEXTEndTagOpen => {
-                    if self.current_char().is_ascii_alphabetic() {
-                        current_token = Token::EndTag(Tag {
-                            ..Default::default()
-                        });
-                        self.switch_to_without_consuming(State::RAWTEXT);
-                    } else {
-                        self.emit_token(&Token::Character('<'));
-                        self.emit_token(&Token::Character('/'));
-                        self.switch_to_without_consuming(State::RAWTEXT);
-                    }
+            }
+            State::ScriptDataDoubleEscapedDashDash => {
+                if self.current_char() == '-' {
+                    self.emit_current_char_as_token();
+                } else if self.current_char() == '<' {
+                    self.switch_to_without_consuming(State::ScriptDataDoubleEscapedLessThanSign);
+                    self.emit_current_char_as_token();
+                } else if self.current_char() == '>' {
+                    self.switch_to_without_consuming(State::ScriptData);
+                    self.emit_current_char_as_token();
+                } else if self.current_char() == '\0' {
+                    self.switch_to_without_consuming(State::ScriptDataDoubleEscaped);
+                    self.emit_replacement_character();
+                } else {
+                    self.switch_to_without_consuming(State::ScriptDataDoubleEscaped);
+                    self.emit_current_char_as_token();
                 }
-                State::RAWTEXTEndTagName => {
-                    if self.current_char().is_ascii_whitespace() {
-                    } else if self.current_char() == '/' {
-                    } else if self.current_char() == '>' {
-                    } else if self.current_char().is_ascii_alphabetic() {
-                    } else {
-                    }
+            }
+            State::ScriptDataDoubleEscapedLessThanSign => {
+                if self.current_char() == '/' {
+                    self.temp_buffer = String::new();
+                    self.switch_to_without_consuming(State::ScriptDataDoubleEscapeEnd);
+                    self.emit_current_char_as_token();
+                } else {
+                    self.switch_to_without_consuming(State::ScriptDataDoubleEscaped);
                 }
-                State::ScriptDataLessThanSign => {
-                    if self.current_char() == '/' {
-                        temp_buffer = String::new();
-                        self.switch_to(State::ScriptDataEndTagOpen);
-                    } else if self.current_char() == '!' {
-                        self.emit_token(&Token::Character('<'));
-                        self.emit_token(&Token::Character('!'));
-                        self.switch_to(State::ScriptData);
-                    } else {
-                        self.emit_token(&Token::Character('<'));
-                        self.switch_to_without_consuming(State::ScriptData);
+            }
+            State::ScriptDataDoubleEscapeEnd => {
+                self.double_escape_boundary(
+                    State::ScriptDataEscaped,
+                    State::ScriptDataDoubleEscaped,
+                );
+            }
+            State::BeforeAttributeName => {
+                if self.current_char().is_ascii_whitespace() {
+                    self.consume();
+                } else if self.current_char() == '/' || self.current_char() == '>' {
+                    self.switch_to_without_consuming(State::AfterAttributeName);
+                } else if self.current_char() == '=' {
+                    // Start a new attribute in the current tag token.
+                    // Set that attribute's name to the current input character,
+                    // and its value to the empty string.
+                    // Switch to the attribute name state.
+                    self.parse_error(ErrorCode::UnexpectedEqualsSignBeforeAttributeName);
+                    self.start_new_attribute();
+                    self.current_attribute().name.push('=');
+                    self.switch_to(State::AttributeName);
+                } else {
+                    self.start_new_attribute();
+                    self.switch_to_without_consuming(State::AttributeName);
+                }
+            }
+            State::AttributeName => {
+                if self.current_char().is_ascii_whitespace()
+                    || self.current_char() == '/'
+                    || self.current_char() == '>'
+                {
+                    self.finish_attribute_name();
+                    self.switch_to_without_consuming(State::AfterAttributeName);
+                } else if self.current_char() == '=' {
+                    self.finish_attribute_name();
+                    self.switch_to(State::BeforeAttributeValue);
+                } else {
+                    if matches!(self.current_char(), '"' | '\'' | '<') {
+                        self.parse_error(ErrorCode::UnexpectedCharacterInAttributeName);
                     }
+                    let c = self.current_char_for_token().to_ascii_lowercase();
+                    self.current_attribute().name.push(c);
+                    self.consume();
                 }
-                State::ScriptDataEndTagOpen => todo!(),
-                State::ScriptDataEndTagName => todo!(),
-                State::ScriptDataEscapeStart => todo!(),
-                State::ScriptDataEscapeStartDash => todo!(),
-                State::ScriptDataEscaped => todo!(),
-                State::ScriptDataEscapedDash => todo!(),
-                State::ScriptDataEscapedDashDash => todo!(),
-                State::ScriptDataEscapedLessThanSign => todo!(),
-                State::ScriptDataEscapedEndTagOpen => todo!(),
-                State::ScriptDataEscapedEndTagName => todo!(),
-                State::ScriptDataDoubleEscapeStart => todo!(),
-                State::ScriptDataDoubleEscaped => todo!(),
-                State::ScriptDataDoubleEscapedDash => todo!(),
-                State::ScriptDataDoubleEscapedDashDash => todo!(),
-                State::ScriptDataDoubleEscapedLessThanSign => todo!(),
-                State::ScriptDataDoubleEscapeEnd => todo!(),
-                State::BeforeAttributeName => {
-                    self.consume_if(self.current_char().is_ascii_whitespace());
-                    if self.current_char() == '/' && self.current_char() == '>' {
-                        self.switch_to_without_consuming(State::AfterAttributeName);
-                    } else if self.current_char() == '=' {
-                        // Should rewrite this into a function...
-                        // Start a new attribute in the current tag token.
-                        // Set that attribute's name to the current input character,
-                        // and its value to the empty string.
-                        // Switch to the attribute name state.
-                        // TODO: Keep track of index in attributes vec
-                        // and reset it when a new token starts or sth like that
-                        current_token = match current_token {
-                            Token::StartTag(mut t) => {
-                                if t.attributes.is_empty() {
-                                    attribute_id = 0;
-                                } else {
-                                    attribute_id += 1;
-                                }
-                                t.attributes.insert(
-                                    0,
-                                    Attribute {
-                                        name: self.current_char().to_string(),
-                                        ..Default::default()
-                                    },
-                                );
-                                Token::StartTag(t)
-                            }
-                            Token::EndTag(mut t) => {
-                                if t.attributes.is_empty() {
-                                    attribute_id = 0;
-                                } else {
-                                    attribute_id += 1;
-                                }
-                                t.attributes.insert(
-                                    0,
-                                    Attribute {
-                                        name: self.current_char().to_string(),
-                                        ..Default::default()
-                                    },
-                                );
-                                Token::EndTag(t)
-                            }
-                            _ => panic!("Not a tag token!"),
-                        };
-                        self.switch_to(State::AttributeName);
-                    } else {
-                        current_token = match current_token {
-                            Token::StartTag(mut t) => {
-                                if t.attributes.is_empty() {
-                                    attribute_id = 0;
-                                } else {
-                                    attribute_id += 1;
-                                }
-                                t.attributes.insert(
-                                    attribute_id,
-                                    Attribute {
-                                        ..Default::default()
-                                    },
-                                );
-                                Token::StartTag(t)
-                            }
-                            Token::EndTag(mut t) => {
-                                if t.attributes.is_empty() {
-                                    attribute_id = 0;
-                                } else {
-                                    attribute_id += 1;
-                                }
-                                t.attributes.insert(
-                                    attribute_id,
-                                    Attribute {
-                                        ..Default::default()
-                                    },
-                                );
-                                Token::EndTag(t)
-                            }
-                            _ => panic!("Not a tag token!"),
-                        };
-                        self.switch_to_without_consuming(State::AttributeName);
+            }
+            State::AfterAttributeName => {
+                if self.current_char().is_ascii_whitespace() {
+                    self.consume();
+                } else if self.current_char() == '/' {
+                    self.switch_to(State::SelfClosingStartTag);
+                } else if self.current_char() == '=' {
+                    self.switch_to(State::BeforeAttributeValue);
+                } else if self.current_char() == '>' {
+                    self.switch_to(State::Data);
+                    self.emit_current_tag();
+                } else {
+                    self.start_new_attribute();
+                    self.switch_to_without_consuming(State::AttributeName);
+                }
+            }
+            State::BeforeAttributeValue => {
+                if self.current_char().is_ascii_whitespace() {
+                    self.consume();
+                } else if self.current_char() == '"' {
+                    self.switch_to(State::AttributeValueDoubleQuoted);
+                    self.start_attribute_value();
+                } else if self.current_char() == '\'' {
+                    self.switch_to(State::AttributeValueSingleQuoted);
+                    self.start_attribute_value();
+                } else if self.current_char() == '>' {
+                    self.parse_error(ErrorCode::MissingAttributeValue);
+                    self.switch_to(State::Data);
+                    self.emit_current_tag();
+                } else {
+                    self.start_attribute_value();
+                    self.switch_to_without_consuming(State::AttributeValueUnquoted);
+                }
+            }
+            State::AttributeValueDoubleQuoted => {
+                if self.current_char() == '"' {
+                    self.finish_attribute_value();
+                    self.switch_to(State::AfterAttributeValueQuoted);
+                } else if self.current_char() == '&' {
+                    self.return_state = State::AttributeValueDoubleQuoted;
+                    self.switch_to(State::CharacterReference);
+                } else {
+                    let c = self.current_char_for_token();
+                    self.current_attribute().value.push(c);
+                    self.consume();
+                }
+            }
+            State::AttributeValueSingleQuoted => {
+                if self.current_char() == '\'' {
+                    self.finish_attribute_value();
+                    self.switch_to(State::AfterAttributeValueQuoted);
+                } else if self.current_char() == '&' {
+                    self.return_state = State::AttributeValueSingleQuoted;
+                    self.switch_to(State::CharacterReference);
+                } else {
+                    let c = self.current_char_for_token();
+                    self.current_attribute().value.push(c);
+                    self.consume();
+                }
+            }
+            State::AttributeValueUnquoted => {
+                if self.current_char().is_ascii_whitespace() {
+                    self.finish_attribute_value();
+                    self.switch_to(State::BeforeAttributeName);
+                } else if self.current_char() == '&' {
+                    self.return_state = State::AttributeValueUnquoted;
+                    self.switch_to(State::CharacterReference);
+                } else if self.current_char() == '>' {
+                    self.finish_attribute_value();
+                    self.switch_to(State::Data);
+                    self.emit_current_tag();
+                } else {
+                    if matches!(self.current_char(), '"' | '\'' | '<' | '=' | '`') {
+                        self.parse_error(ErrorCode::UnexpectedCharacterInUnquotedAttributeValue);
                     }
+                    let c = self.current_char_for_token();
+                    self.current_attribute().value.push(c);
+                    self.consume();
+                }
+            }
+            State::AfterAttributeValueQuoted => {
+                if self.current_char().is_ascii_whitespace() {
+                    self.switch_to(State::BeforeAttributeName);
+                } else if self.current_char() == '/' {
+                    self.switch_to(State::SelfClosingStartTag);
+                } else if self.current_char() == '>' {
+                    self.switch_to(State::Data);
+                    self.emit_current_tag();
+                } else {
+                    self.parse_error(ErrorCode::MissingWhitespaceBetweenAttributes);
+                    self.switch_to_without_consuming(State::BeforeAttributeName);
+                }
+            }
+            State::SelfClosingStartTag => {
+                if self.current_char() == '>' {
+                    self.current_tag.self_closing = true;
+                    self.switch_to(State::Data);
+                    self.emit_current_tag();
+                } else {
+                    self.parse_error(ErrorCode::UnexpectedSolidusInTag);
+                    self.switch_to_without_consuming(State::BeforeAttributeName);
+                }
+            }
+            State::BogusComment => {
+                if self.current_char() == '>' {
+                    self.switch_to(State::Data);
+                    self.emit_current_comment();
+                } else {
+                    let c = self.current_char_for_token();
+                    self.current_comment.push(c);
+                    self.consume();
                 }
-                State::AttributeName => {
-                    if self.current_char().is_ascii_whitespace()
-                        || self.current_char() == '/'
-                        || self.current_char() == '>'
+            }
+            State::MarkupDeclarationOpen => {
+                if self.next_chars("--") {
+                    self.advance_by(2);
+                    self.current_comment = String::new();
+                    self.switch_to(State::CommentStart);
+                } else if self.next_chars_ignore_ascii_case("DOCTYPE") {
+                    self.advance_by("DOCTYPE".len());
+                    self.switch_to(State::DOCTYPE);
+                } else if self.next_chars("[CDATA[") {
+                    self.advance_by("[CDATA[".len());
+                    if self
+                        .sink
+                        .adjusted_current_node_present_but_not_in_html_namespace()
                     {
-                        self.switch_to_without_consuming(State::AfterAttributeName);
-                    } else if self.current_char() == '=' {
-                        self.switch_to(State::BeforeAttributeValue);
+                        self.switch_to(State::CDATASection);
                     } else {
-                        // Combines ascii upper and else
-                        current_token = match current_token {
-                            Token::StartTag(mut t) => {
-                                t.attributes[attribute_id]
-                                    .name
-                                    .push(self.current_char().to_ascii_lowercase());
-                                Token::StartTag(t)
-                            }
-                            Token::EndTag(mut t) => {
-                                t.attributes[attribute_id]
-                                    .name
-                                    .push(self.current_char().to_ascii_lowercase());
-                                Token::EndTag(t)
-                            }
-                            _ => panic!("Not a tag token!"),
-                        };
-                        self.pos += 1;
+                        self.consume();
+                        self.parse_error(ErrorCode::CdataInHtmlContent);
+                        self.current_comment = String::from("[CDATA[");
+                        self.switch_to_without_consuming(State::BogusComment);
                     }
+                } else {
+                    self.parse_error(ErrorCode::IncorrectlyOpenedComment);
+                    self.current_comment = String::new();
+                    self.switch_to(State::BogusComment);
                 }
-                State::AfterAttributeName => {
-                    self.consume_if(self.current_char().is_ascii_whitespace());
-                    if self.current_char() == '/' {
-                        self.switch_to(State::SelfClosingStartTag);
-                    } else if self.current_char() == '=' {
-                        self.switch_to(State::BeforeAttributeValue);
-                    } else if self.current_char() == '>' {
-                        self.emit_token(&current_token);
-                        self.switch_to(State::Data);
-                    } else {
-                        current_token = match current_token {
-                            Token::StartTag(mut t) => {
-                                if t.attributes.is_empty() {
-                                    attribute_id = 0;
-                                } else {
-                                    attribute_id += 1;
-                                }
-                                t.attributes.insert(
-                                    attribute_id,
-                                    Attribute {
-                                        ..Default::default()
-                                    },
-                                );
-                                Token::StartTag(t)
-                            }
-                            Token::EndTag(mut t) => {
-                                if t.attributes.is_empty() {
-                                    attribute_id = 0;
-                                } else {
-                                    attribute_id += 1;
-                                }
-                                t.attributes.insert(
-                                    attribute_id,
-                                    Attribute {
-                                        ..Default::default()
-                                    },
-                                );
-                                Token::EndTag(t)
-                            }
-                            _ => panic!("Not a tag token!"),
-                        };
-                        self.switch_to_without_consuming(State::AttributeName);
-                    }
+            }
+            State::CommentStart => {
+                if self.current_char() == '-' {
+                    self.switch_to(State::CommentStartDash)
+                } else if self.current_char() == '>' {
+                    self.parse_error(ErrorCode::AbruptClosingOfEmptyComment);
+                    self.switch_to(State::Data);
+                    self.emit_current_comment();
+                } else {
+                    self.switch_to_without_consuming(State::Comment)
                 }
-                State::BeforeAttributeValue => {
-                    self.consume_if(self.current_char().is_ascii_whitespace());
-                    if self.current_char() == '"' {
-                        self.switch_to(State::AttributeValueDoubleQuoted);
-                    } else if self.current_char() == '\'' {
-                        self.switch_to(State::AttributeValueSingleQuoted);
-                    } else if self.current_char() == '>' {
-                        self.emit_token(&current_token);
-                        self.switch_to(State::Data);
-                    } else {
-                        self.switch_to_without_consuming(State::AttributeValueUnquoted);
-                    }
+            }
+            State::CommentStartDash => {
+                if self.current_char() == '-' {
+                    self.switch_to(State::CommentEnd);
+                } else if self.current_char() == '>' {
+                    self.parse_error(ErrorCode::AbruptClosingOfEmptyComment);
+                    self.switch_to(State::Data);
+                    self.emit_current_comment();
+                } else {
+                    self.current_comment.push('-');
+                    self.switch_to_without_consuming(State::Comment);
                 }
-                State::AttributeValueDoubleQuoted => {
-                    if self.current_char() == '"' {
-                        self.switch_to(State::AfterAttributeValueQuoted);
-                    } else if self.current_char() == '&' {
-                        self.return_state = State::AttributeValueDoubleQuoted;
-                        self.switch_to(State::CharacterReference);
-                    } else {
-                        current_token = match current_token {
-                            Token::StartTag(mut t) => {
-                                t.attributes[attribute_id].value.push(self.current_char());
-                                Token::StartTag(t)
-                            }
-                            Token::EndTag(mut t) => {
-                                t.attributes[attribute_id].value.push(self.current_char());
-                                Token::EndTag(t)
-                            }
-                            _ => panic!("Not a tag token!"),
-                        };
-                        self.pos += 1;
-                    }
+            }
+            State::Comment => {
+                if self.current_char() == '<' {
+                    self.current_comment.push('<');
+                    self.switch_to(State::CommentLessThanSign);
+                } else if self.current_char() == '-' {
+                    self.switch_to(State::CommentEndDash);
+                } else {
+                    let c = self.current_char_for_token();
+                    self.current_comment.push(c);
+                    self.consume();
                 }
-                State::AttributeValueSingleQuoted => {
-                    if self.current_char() == '\'' {
-                        self.switch_to(State::AfterAttributeValueQuoted);
-                    } else if self.current_char() == '&' {
-                        self.return_state = State::AttributeValueDoubleQuoted;
-                        self.switch_to(State::CharacterReference);
-                    } else {
-                        current_token = match current_token {
-                            Token::StartTag(mut t) => {
-                                t.attributes[attribute_id].value.push(self.current_char());
-                                Token::StartTag(t)
-                            }
-                            Token::EndTag(mut t) => {
-                                t.attributes[attribute_id].value.push(self.current_char());
-                                Token::EndTag(t)
-                            }
-                            _ => panic!("Not a tag token!"),
-                        };
-                        self.pos += 1;
-                    }
+            }
+            State::CommentLessThanSign => {
+                if self.current_char() == '!' {
+                    self.current_comment.push('!');
+                    self.switch_to(State::CommentLessThanSignBang);
+                } else if self.current_char() == '<' {
+                    self.current_comment.push('<');
+                    self.consume();
+                } else {
+                    self.switch_to_without_consuming(State::Comment);
                 }
-                State::AttributeValueUnquoted => {
-                    if self.current_char().is_ascii_whitespace() {
-                        self.switch_to(State::BeforeAttributeName);
-                    } else if self.current_char() == '&' {
-                        self.return_state = State::AttributeValueUnquoted;
-                        self.switch_to(State::CharacterReference);
-                    } else if self.current_char() == '>' {
-                        self.emit_token(&current_token);
-                        self.switch_to(State::Data);
-                    } else {
-                        current_token = match current_token {
-                            Token::StartTag(mut t) => {
-                                t.attributes[attribute_id].value.push(self.current_char());
-                                Token::StartTag(t)
-                            }
-                            Token::EndTag(mut t) => {
-                                t.attributes[attribute_id].value.push(self.current_char());
-                                Token::EndTag(t)
-                            }
-                            _ => panic!("Not a tag token!"),
-                        };
-                        self.pos += 1;
-                    }
+            }
+            State::CommentLessThanSignBang => {
+                if self.current_char() == '-' {
+                    self.switch_to(State::CommentLessThanSignBangDash);
+                } else {
+                    self.switch_to_without_consuming(State::Comment);
                 }
-                State::AfterAttributeValueQuoted => {
-                    if self.current_char().is_ascii_whitespace() {
-                        self.switch_to(State::BeforeAttributeName);
-                    } else if self.current_char() == '/' {
-                        self.switch_to(State::SelfClosingStartTag);
-                    } else if self.current_char() == '>' {
-                        self.emit_token(&current_token);
-                        self.switch_to(State::Data);
-                    } else {
-                        self.switch_to_without_consuming(State::BeforeAttributeName);
-                    }
+            }
+            State::CommentLessThanSignBangDash => {
+                if self.current_char() == '-' {
+                    self.switch_to(State::CommentLessThanSignBangDashDash);
+                } else {
+                    self.switch_to_without_consuming(State::CommentEndDash);
                 }
-                State::SelfClosingStartTag => {
-                    if self.current_char() == '>' {
-                        current_token = match current_token {
-                            Token::StartTag(mut t) => {
-                                t.self_closing = true;
-                                Token::StartTag(t)
-                            }
-                            Token::EndTag(mut t) => {
-                                t.self_closing = true;
-                                Token::EndTag(t)
-                            }
-                            _ => panic!("Not a tag token!"),
-                        };
-                        self.emit_token(&current_token);
-                        self.switch_to(State::Data);
-                    } else {
-                        self.switch_to_without_consuming(State::BeforeAttributeName);
-                    }
+            }
+            State::CommentLessThanSignBangDashDash => {
+                if self.current_char() != '>' {
+                    self.parse_error(ErrorCode::NestedComment);
                 }
-                State::BogusComment => {
-                    if self.current_char() == '>' {
-                        self.emit_token(&current_token);
-                        self.switch_to(State::Data);
-                    } else {
-                        current_token = match current_token {
-                            Token::Comment(mut c) => {
-                                c.push(self.current_char());
-                                Token::Comment(c)
-                            }
-                            _ => panic!("Not a comment token!"),
-                        };
-                        self.pos += 1;
-                    }
+                self.switch_to_without_consuming(State::CommentEnd);
+            }
+            State::CommentEndDash => {
+                if self.current_char() == '-' {
+                    self.switch_to(State::CommentEnd);
+                } else {
+                    self.current_comment.push('-');
+                    self.switch_to_without_consuming(State::Comment);
                 }
-                State::MarkupDeclarationOpen => {
-                    if self.next_chars("--") {
-                        self.advance_by(2);
-                        current_token = Token::Comment("".to_string());
-                        self.switch_to(State::CommentStart);
-                    } else if self.next_chars("DOCTYPE") {
-                        self.advance_by("DOCTYPE".len());
-                        self.switch_to(State::DOCTYPE);
-                    }
+            }
+            State::CommentEnd => {
+                if self.current_char() == '>' {
+                    self.switch_to(State::Data);
+                    self.emit_current_comment();
+                } else if self.current_char() == '!' {
+                    self.switch_to(State::CommentEndBang);
+                } else if self.current_char() == '-' {
+                    self.current_comment.push('-');
+                    self.consume();
+                } else {
+                    self.current_comment.push_str("--");
+                    self.switch_to_without_consuming(State::Comment);
                 }
-                State::CommentStart => {
-                    if self.current_char() == '-' {
-                        self.switch_to(State::CommentStartDash)
-                    } else if self.current_char() == '>' {
-                        self.emit_token(&current_token);
-                        self.switch_to(State::Data)
-                    } else {
-                        self.switch_to_without_consuming(State::Comment)
-                    }
+            }
+            State::CommentEndBang => {
+                if self.current_char() == '-' {
+                    self.current_comment.push_str("--!");
+                    self.switch_to(State::CommentEndDash);
+                } else if self.current_char() == '>' {
+                    self.parse_error(ErrorCode::IncorrectlyClosedComment);
+                    self.switch_to(State::Data);
+                    self.emit_current_comment();
+                } else {
+                    self.current_comment.push_str("--!");
+                    self.switch_to_without_consuming(State::Comment);
                 }
-                State::CommentStartDash => {
-                    if self.current_char() == '-' {
-                        self.switch_to(State::CommentEnd);
-                    } else if self.current_char() == '>' {
-                        self.emit_token(&current_token);
-                        self.switch_to(State::Data);
+            }
+            State::DOCTYPE => {
+                if self.current_char().is_ascii_whitespace() {
+                    self.switch_to(State::BeforeDOCTYPEName)
+                } else {
+                    if self.current_char() != '>' {
+                        self.parse_error(ErrorCode::MissingWhitespaceBeforeDoctypeName);
                     }
+                    self.switch_to_without_consuming(State::BeforeDOCTYPEName);
                 }
-                State::Comment => {
-                    if self.current_char() == '<' {
-                        current_token = match current_token {
-                            Token::Comment(mut c) => {
-                                c.push(self.current_char());
-                                Token::Comment(c)
-                            }
-                            _ => panic!("{:?}", current_token),
-                        };
-                        self.switch_to(State::CommentLessThanSign);
-                    } else if self.current_char() == '-' {
-                        self.switch_to(State::CommentEndDash);
-                    } else {
-                        current_token = match current_token {
-                            Token::Comment(mut c) => {
-                                c.push(self.current_char());
-                                Token::Comment(c)
-                            }
-                            _ => panic!("{:?}", current_token),
-                        };
-                        self.pos += 1;
-                    }
+            }
+            State::BeforeDOCTYPEName => {
+                if self.current_char().is_ascii_whitespace() {
+                    self.consume();
+                } else if self.current_char() == '>' {
+                    self.parse_error(ErrorCode::MissingDoctypeName);
+                    self.current_doctype = DOCTYPE {
+                        force_quirks: true,
+                        ..Default::default()
+                    };
+                    self.switch_to(State::Data);
+                    self.emit_current_doctype();
+                } else {
+                    self.current_doctype = DOCTYPE {
+                        name: self
+                            .current_char_for_token()
+                            .to_ascii_lowercase()
+                            .to_string(),
+                        ..Default::default()
+                    };
+                    self.switch_to(State::DOCTYPEName);
                 }
-                State::CommentLessThanSign => {
-                    if self.current_char() == '!' {
-                        current_token = match current_token {
-                            Token::Comment(mut c) => {
-                                c.push(self.current_char());
-                                Token::Comment(c)
-                            }
-                            _ => panic!("{:?}", current_token),
-                        };
-                        self.switch_to(State::CommentLessThanSignBang);
-                    } else if self.current_char() == '<' {
-                        current_token = match current_token {
-                            Token::Comment(mut c) => {
-                                c.push(self.current_char());
-                                Token::Comment(c)
-                            }
-                            _ => panic!("{:?}", current_token),
-                        };
-                    } else {
-                        self.switch_to_without_consuming(State::Comment);
-                    }
+            }
+            State::DOCTYPEName => {
+                if self.current_char().is_ascii_whitespace() {
+                    self.switch_to(State::AfterDOCTYPEName);
+                } else if self.current_char() == '>' {
+                    self.switch_to(State::Data);
+                    self.emit_current_doctype();
+                } else {
+                    let c = self.current_char_for_token().to_ascii_lowercase();
+                    self.current_doctype.name.push(c);
+                    self.consume();
                 }
-                State::CommentLessThanSignBang => {
-                    if self.current_char() == '-' {
-                        self.switch_to(State::CommentLessThanSignBangDash);
-                    } else {
-                        self.switch_to_without_consuming(State::Comment);
-                    }
+            }
+            State::AfterDOCTYPEName => {
+                if self.current_char().is_ascii_whitespace() {
+                    self.consume();
+                } else if self.current_char() == '>' {
+                    self.switch_to(State::Data);
+                    self.emit_current_doctype();
+                } else if self.current_chars_ignore_ascii_case("PUBLIC") {
+                    self.advance_by("PUBLIC".len());
+                    self.switch_to_without_consuming(State::AfterDOCTYPEPublicKeyword);
+                } else if self.current_chars_ignore_ascii_case("SYSTEM") {
+                    self.advance_by("SYSTEM".len());
+                    self.switch_to_without_consuming(State::AfterDOCTYPESystemKeyword);
+                } else {
+                    self.parse_error(ErrorCode::InvalidCharacterSequenceAfterDoctypeName);
+                    self.current_doctype.force_quirks = true;
+                    self.switch_to_without_consuming(State::BogusDOCTYPE);
                 }
-                State::CommentLessThanSignBangDash => {
-                    if self.current_char() == '-' {
-                        self.switch_to(State::CommentLessThanSignBangDashDash);
-                    } else {
-                        self.switch_to_without_consuming(State::CommentEndDash);
+            }
+            State::AfterDOCTYPEPublicKeyword => {
+                if self.current_char().is_ascii_whitespace() {
+                    self.switch_to(State::BeforeDOCTYPEPublicIdentifier);
+                } else {
+                    if self.current_char() == '"' || self.current_char() == '\'' {
+                        self.parse_error(ErrorCode::MissingWhitespaceAfterDoctypePublicKeyword);
                     }
+                    self.before_doctype_public_identifier();
                 }
-                State::CommentLessThanSignBangDashDash => {
-                    self.switch_to_without_consuming(State::CommentEnd);
+            }
+            State::BeforeDOCTYPEPublicIdentifier => {
+                if self.current_char().is_ascii_whitespace() {
+                    self.consume();
+                } else {
+                    self.before_doctype_public_identifier();
                 }
-                State::CommentEndDash => {
-                    if self.current_char() == '-' {
-                        self.switch_to(State::CommentEnd);
-                    } else {
-                        current_token = match current_token {
-                            Token::Comment(mut c) => {
-                                c.push('-');
-                                Token::Comment(c)
-                            }
-                            _ => panic!("{:?}", current_token),
-                        };
-                        self.switch_to_without_consuming(State::Comment);
-                    }
+            }
+            State::DOCTYPEPublicIdentifierDoubleQuoted => {
+                if self.current_char() == '"' {
+                    self.switch_to(State::AfterDOCTYPEPublicIdentifier);
+                } else if self.current_char() == '>' {
+                    self.parse_error(ErrorCode::AbruptDoctypePublicIdentifier);
+                    self.current_doctype.force_quirks = true;
+                    self.switch_to(State::Data);
+                    self.emit_current_doctype();
+                } else {
+                    let c = self.current_char_for_token();
+                    self.current_doctype
+                        .public_identifier
+                        .get_or_insert_with(String::new)
+                        .push(c);
+                    self.consume();
                 }
-                State::CommentEnd => {
-                    if self.current_char() == '>' {
-                        self.emit_token(&current_token);
-                        self.switch_to(State::Data);
-                    } else if self.current_char() == '!' {
-                        self.switch_to(State::CommentEndBang);
-                    } else if self.current_char() == '-' {
-                        current_token = match current_token {
-                            Token::Comment(mut c) => {
-                                c.push('-');
-                                Token::Comment(c)
-                            }
-                            _ => panic!("{:?}", current_token),
-                        };
-                    } else {
-                        current_token = match current_token {
-                            Token::Comment(mut c) => {
-                                c += "--";
-                                Token::Comment(c)
-                            }
-                            _ => panic!("{:?}", current_token),
-                        };
+            }
+            State::DOCTYPEPublicIdentifierSingleQuoted => {
+                if self.current_char() == '\'' {
+                    self.switch_to(State::AfterDOCTYPEPublicIdentifier);
+                } else if self.current_char() == '>' {
+                    self.parse_error(ErrorCode::AbruptDoctypePublicIdentifier);
+                    self.current_doctype.force_quirks = true;
+                    self.switch_to(State::Data);
+                    self.emit_current_doctype();
+                } else {
+                    let c = self.current_char_for_token();
+                    self.current_doctype
+                        .public_identifier
+                        .get_or_insert_with(String::new)
+                        .push(c);
+                    self.consume();
+                }
+            }
+            State::AfterDOCTYPEPublicIdentifier => {
+                if self.current_char().is_ascii_whitespace() {
+                    self.switch_to(State::BetweenDOCTYPEPublicAndSystemIdentifiers);
+                } else if self.current_char() == '>' {
+                    self.switch_to(State::Data);
+                    self.emit_current_doctype();
+                } else {
+                    if self.current_char() == '"' || self.current_char() == '\'' {
+                        self.parse_error(
+                            ErrorCode::MissingWhitespaceBetweenDoctypePublicAndSystemIdentifiers,
+                        );
                     }
+                    self.before_doctype_system_identifier();
                 }
-                State::CommentEndBang => {
-                    if self.current_char() == '-' {
-                        current_token = match current_token {
-                            Token::Comment(mut c) => {
-                                c += "!--";
-                                Token::Comment(c)
-                            }
-                            _ => panic!("{:?}", current_token),
-                        };
-                        self.switch_to(State::CommentEndDash);
-                    } else if self.current_char() == '>' {
-                        self.emit_token(&current_token);
-                        self.switch_to(State::Data);
-                    } else {
-                        current_token = match current_token {
-                            Token::Comment(mut c) => {
-                                // Should this be "!--"?
-                                c += "!--";
-                                Token::Comment(c)
-                            }
-                            _ => panic!("{:?}", current_token),
-                        };
-                        self.switch_to_without_consuming(State::Comment);
+            }
+            State::BetweenDOCTYPEPublicAndSystemIdentifiers => {
+                if self.current_char().is_ascii_whitespace() {
+                    self.consume();
+                } else if self.current_char() == '>' {
+                    self.switch_to(State::Data);
+                    self.emit_current_doctype();
+                } else {
+                    self.before_doctype_system_identifier();
+                }
+            }
+            State::AfterDOCTYPESystemKeyword => {
+                if self.current_char().is_ascii_whitespace() {
+                    self.switch_to(State::BeforeDOCTYPESystemIdentifier);
+                } else if self.current_char() == '>' {
+                    self.parse_error(ErrorCode::MissingDoctypeSystemIdentifier);
+                    self.current_doctype.force_quirks = true;
+                    self.switch_to(State::Data);
+                    self.emit_current_doctype();
+                } else {
+                    if self.current_char() == '"' || self.current_char() == '\'' {
+                        self.parse_error(ErrorCode::MissingWhitespaceAfterDoctypeSystemKeyword);
                     }
+                    self.before_doctype_system_identifier();
                 }
-                State::DOCTYPE => {
-                    if self.current_char().is_ascii_whitespace() || self.current_char() == '>' {
-                        self.switch_to(State::BeforeDOCTYPEName)
-                    } else if self.current_char() == '>' {
-                        self.switch_to(State::BeforeDOCTYPEName);
-                    } else {
-                        self.switch_to(State::BeforeDOCTYPEName);
+            }
+            State::BeforeDOCTYPESystemIdentifier => {
+                if self.current_char().is_ascii_whitespace() {
+                    self.consume();
+                } else if self.current_char() == '>' {
+                    self.parse_error(ErrorCode::MissingDoctypeSystemIdentifier);
+                    self.current_doctype.force_quirks = true;
+                    self.switch_to(State::Data);
+                    self.emit_current_doctype();
+                } else {
+                    self.before_doctype_system_identifier();
+                }
+            }
+            State::DOCTYPESystemIdentifierDoubleQuoted => {
+                if self.current_char() == '"' {
+                    self.switch_to(State::AfterDOCTYPESystemIdentifier);
+                } else if self.current_char() == '>' {
+                    self.parse_error(ErrorCode::AbruptDoctypeSystemIdentifier);
+                    self.current_doctype.force_quirks = true;
+                    self.switch_to(State::Data);
+                    self.emit_current_doctype();
+                } else {
+                    let c = self.current_char_for_token();
+                    self.current_doctype
+                        .system_identifier
+                        .get_or_insert_with(String::new)
+                        .push(c);
+                    self.consume();
+                }
+            }
+            State::DOCTYPESystemIdentifierSingleQuoted => {
+                if self.current_char() == '\'' {
+                    self.switch_to(State::AfterDOCTYPESystemIdentifier);
+                } else if self.current_char() == '>' {
+                    self.parse_error(ErrorCode::AbruptDoctypeSystemIdentifier);
+                    self.current_doctype.force_quirks = true;
+                    self.switch_to(State::Data);
+                    self.emit_current_doctype();
+                } else {
+                    let c = self.current_char_for_token();
+                    self.current_doctype
+                        .system_identifier
+                        .get_or_insert_with(String::new)
+                        .push(c);
+                    self.consume();
+                }
+            }
+            State::AfterDOCTYPESystemIdentifier => {
+                if self.current_char().is_ascii_whitespace() {
+                    self.consume();
+                } else if self.current_char() == '>' {
+                    self.switch_to(State::Data);
+                    self.emit_current_doctype();
+                } else {
+                    // Unlike everywhere else, trailing garbage doesn't set
+                    // the force-quirks flag here.
+                    self.parse_error(ErrorCode::UnexpectedCharacterAfterDoctypeSystemIdentifier);
+                    self.switch_to_without_consuming(State::BogusDOCTYPE);
+                }
+            }
+            State::BogusDOCTYPE => {
+                if self.current_char() == '>' {
+                    self.switch_to(State::Data);
+                    self.emit_current_doctype();
+                } else {
+                    if self.current_char() == '\0' {
+                        self.parse_error(ErrorCode::UnexpectedNullCharacter);
                     }
+                    self.consume();
+                }
+            }
+            State::CDATASection => {
+                if self.current_char() == ']' {
+                    self.switch_to(State::CDATASectionBracket);
+                } else {
+                    // U+0000 NULL is emitted as is, the tree builder deals
+                    // with it.
+                    self.emit_current_char_as_token();
+                }
+            }
+            State::CDATASectionBracket => {
+                if self.current_char() == ']' {
+                    self.switch_to(State::CDATASectionEnd);
+                } else {
+                    self.emit_chars("]", self.pos - 1);
+                    self.switch_to_without_consuming(State::CDATASection);
                 }
-                State::BeforeDOCTYPEName => {
-                    self.consume_if(self.current_char().is_whitespace());
-                    if self.current_char().is_ascii_uppercase() {
-                        current_token = Token::DOCTYPE(DOCTYPE {
-                            name: self.current_char().to_ascii_lowercase().to_string(),
-                            ..Default::default()
-                        });
-                        self.switch_to(State::DOCTYPEName);
+            }
+            State::CDATASectionEnd => {
+                if self.current_char() == ']' {
+                    self.emit_chars("]", self.pos - 2);
+                    self.consume();
+                } else if self.current_char() == '>' {
+                    self.switch_to(State::Data);
+                } else {
+                    self.emit_chars("]]", self.pos - 2);
+                    self.switch_to_without_consuming(State::CDATASection);
+                }
+            }
+            State::CharacterReference => {
+                self.character_reference_start = self.pos - 1;
+                self.temp_buffer = String::from("&");
+                if self.current_char().is_ascii_alphanumeric() {
+                    self.switch_to_without_consuming(State::NamedCharacterReference);
+                } else if self.current_char() == '#' {
+                    self.temp_buffer.push('#');
+                    self.switch_to(State::NumericCharacterReference);
+                } else {
+                    self.flush_code_points_consumed_as_character_reference();
+                    self.switch_to_without_consuming(self.return_state);
+                }
+            }
+            State::NamedCharacterReference => {
+                if let Some((len, value)) = self.match_named_character_reference() {
+                    let name = self.input[self.pos..self.pos + len].to_string();
+                    self.advance_by(len);
+                    self.temp_buffer.push_str(&name);
+                    let next_char = self.input[self.pos..].chars().next();
+                    if self.is_consumed_as_part_of_an_attribute()
+                        && !name.ends_with(';')
+                        && next_char.is_some_and(|c| c == '=' || c.is_ascii_alphanumeric())
+                    {
+                        // For historical reasons `href="?a=1&copy=2"` keeps
+                        // the `&copy` as written.
                     } else {
-                        current_token = Token::DOCTYPE(DOCTYPE {
-                            name: self.current_char().to_string(),
-                            ..Default::default()
-                        });
-                        self.switch_to(State::DOCTYPEName);
+                        if !name.ends_with(';') {
+                            self.parse_error(ErrorCode::MissingSemicolonAfterCharacterReference);
+                        }
+                        self.temp_buffer = value.to_string();
                     }
+                    self.flush_code_points_consumed_as_character_reference();
+                    self.switch_to_without_consuming(self.return_state);
+                } else {
+                    self.flush_code_points_consumed_as_character_reference();
+                    self.switch_to_without_consuming(State::AmbiguousAmpersand);
                 }
-                State::DOCTYPEName => {
-                    if self.current_char().is_whitespace() {
-                        self.switch_to(State::AfterDOCTYPEName);
-                    } else if self.current_char() == '>' {
-                        self.emit_token(&current_token);
-                        self.switch_to(State::Data);
-                    } else if self.current_char().is_ascii_uppercase() {
+            }
+            State::AmbiguousAmpersand => {
+                if self.current_char().is_ascii_alphanumeric() {
+                    if self.is_consumed_as_part_of_an_attribute() {
+                        let c = self.current_char();
+                        self.current_attribute().value.push(c);
+                        self.consume();
                     } else {
-                        current_token = match current_token {
-                            Token::DOCTYPE(mut d) => {
-                                d.name.push(self.current_char());
-                                Token::DOCTYPE(d)
-                            }
-                            _ => panic!("{:?}", current_token),
-                        };
-                        self.pos += 1;
+                        self.emit_current_char_as_token();
+                    }
+                } else {
+                    if self.current_char() == ';' {
+                        self.parse_error(ErrorCode::UnknownNamedCharacterReference);
                     }
+                    self.switch_to_without_consuming(self.return_state);
+                }
+            }
+            State::NumericCharacterReference => {
+                self.character_reference_code = 0;
+                if self.current_char() == 'x' || self.current_char() == 'X' {
+                    let c = self.current_char();
+                    self.temp_buffer.push(c);
+                    self.switch_to(State::HexadecimalCharacterReferenceStart);
+                } else {
+                    self.switch_to_without_consuming(State::DecimalCharacterReferenceStart);
+                }
+            }
+            State::HexadecimalCharacterReferenceStart => {
+                if self.current_char().is_ascii_hexdigit() {
+                    self.switch_to_without_consuming(State::HexadecimalCharacterReference);
+                } else {
+                    self.parse_error(ErrorCode::AbsenceOfDigitsInNumericCharacterReference);
+                    self.flush_code_points_consumed_as_character_reference();
+                    self.switch_to_without_consuming(self.return_state);
+                }
+            }
+            State::DecimalCharacterReferenceStart => {
+                if self.current_char().is_ascii_digit() {
+                    self.switch_to_without_consuming(State::DecimalCharacterReference);
+                } else {
+                    self.parse_error(ErrorCode::AbsenceOfDigitsInNumericCharacterReference);
+                    self.flush_code_points_consumed_as_character_reference();
+                    self.switch_to_without_consuming(self.return_state);
                 }
-                State::AfterDOCTYPEName => {
-                    self.consume_if(self.current_char().is_whitespace());
-                    if self.current_char() == '>' {
-                        self.switch_to(State::Data);
+            }
+            State::HexadecimalCharacterReference => {
+                if let Some(digit) = self.current_char().to_digit(16) {
+                    self.add_to_character_reference_code(16, digit);
+                    self.consume();
+                } else if self.current_char() == ';' {
+                    self.switch_to(State::NumericCharacterReferenceEnd);
+                } else {
+                    self.parse_error(ErrorCode::MissingSemicolonAfterCharacterReference);
+                    self.switch_to_without_consuming(State::NumericCharacterReferenceEnd);
+                }
+            }
+            State::DecimalCharacterReference => {
+                if let Some(digit) = self.current_char().to_digit(10) {
+                    self.add_to_character_reference_code(10, digit);
+                    self.consume();
+                } else if self.current_char() == ';' {
+                    self.switch_to(State::NumericCharacterReferenceEnd);
+                } else {
+                    self.parse_error(ErrorCode::MissingSemicolonAfterCharacterReference);
+                    self.switch_to_without_consuming(State::NumericCharacterReferenceEnd);
+                }
+            }
+            State::NumericCharacterReferenceEnd => {
+                self.numeric_character_reference_end();
+            }
+        }
+        true
+    }
+
+    /// The EOF branches of the states. Some of them reconsume the EOF in
+    /// another state, so this may run more than once before the `EndOfFile`
+    /// token is emitted.
+    fn step_at_eof(&mut self) {
+        match self.current_state {
+            State::Data | State::RCDATA | State::RAWTEXT | State::ScriptData | State::PLAINTEXT => {
+                self.emit_eof();
+            }
+            State::TagOpen => {
+                self.parse_error(ErrorCode::EofBeforeTagName);
+                self.emit_less_than_sign();
+                self.emit_eof();
+            }
+            State::EndTagOpen => {
+                self.parse_error(ErrorCode::EofBeforeTagName);
+                self.emit_chars("</", self.token_start);
+                self.emit_eof();
+            }
+            State::TagName
+            | State::AfterAttributeName
+            | State::AttributeValueDoubleQuoted
+            | State::AttributeValueSingleQuoted
+            | State::AttributeValueUnquoted
+            | State::AfterAttributeValueQuoted
+            | State::SelfClosingStartTag => {
+                // The unfinished tag is dropped.
+                self.parse_error(ErrorCode::EofInTag);
+                self.emit_eof();
+            }
+            State::RCDATALessThanSign => {
+                self.emit_less_than_sign();
+                self.switch_to_without_consuming(State::RCDATA);
+            }
+            State::RAWTEXTLessThanSign => {
+                self.emit_less_than_sign();
+                self.switch_to_without_consuming(State::RAWTEXT);
+            }
+            State::ScriptDataLessThanSign => {
+                self.emit_less_than_sign();
+                self.switch_to_without_consuming(State::ScriptData);
+            }
+            State::ScriptDataEscapedLessThanSign => {
+                self.emit_less_than_sign();
+                self.switch_to_without_consuming(State::ScriptDataEscaped);
+            }
+            State::RCDATAEndTagOpen | State::RCDATAEndTagName => {
+                self.end_tag_as_text(State::RCDATA);
+            }
+            State::RAWTEXTEndTagOpen | State::RAWTEXTEndTagName => {
+                self.end_tag_as_text(State::RAWTEXT);
+            }
+            State::ScriptDataEndTagOpen | State::ScriptDataEndTagName => {
+                self.end_tag_as_text(State::ScriptData);
+            }
+            State::ScriptDataEscapedEndTagOpen | State::ScriptDataEscapedEndTagName => {
+                self.end_tag_as_text(State::ScriptDataEscaped);
+            }
+            State::ScriptDataEscapeStart | State::ScriptDataEscapeStartDash => {
+                self.switch_to_without_consuming(State::ScriptData);
+            }
+            State::ScriptDataDoubleEscapeStart => {
+                self.switch_to_without_consuming(State::ScriptDataEscaped);
+            }
+            State::ScriptDataDoubleEscapedLessThanSign | State::ScriptDataDoubleEscapeEnd => {
+                self.switch_to_without_consuming(State::ScriptDataDoubleEscaped);
+            }
+            State::ScriptDataEscaped
+            | State::ScriptDataEscapedDash
+            | State::ScriptDataEscapedDashDash
+            | State::ScriptDataDoubleEscaped
+            | State::ScriptDataDoubleEscapedDash
+            | State::ScriptDataDoubleEscapedDashDash => {
+                self.parse_error(ErrorCode::EofInScriptHtmlCommentLikeText);
+                self.emit_eof();
+            }
+            State::BeforeAttributeName | State::AttributeName => {
+                self.switch_to_without_consuming(State::AfterAttributeName);
+            }
+            State::BeforeAttributeValue => {
+                self.switch_to_without_consuming(State::AttributeValueUnquoted);
+            }
+            State::BogusComment => {
+                self.emit_current_comment();
+                self.emit_eof();
+            }
+            State::MarkupDeclarationOpen => {
+                // Not reachable in practice, the `!` that led here is only
+                // consumed on leaving the state.
+                self.parse_error(ErrorCode::IncorrectlyOpenedComment);
+                self.current_comment = String::new();
+                self.switch_to_without_consuming(State::BogusComment);
+            }
+            State::CommentStart | State::CommentLessThanSign | State::CommentLessThanSignBang => {
+                self.switch_to_without_consuming(State::Comment);
+            }
+            State::CommentLessThanSignBangDash => {
+                self.switch_to_without_consuming(State::CommentEndDash);
+            }
+            State::CommentLessThanSignBangDashDash => {
+                self.switch_to_without_consuming(State::CommentEnd);
+            }
+            State::CommentStartDash
+            | State::Comment
+            | State::CommentEndDash
+            | State::CommentEnd
+            | State::CommentEndBang => {
+                self.parse_error(ErrorCode::EofInComment);
+                self.emit_current_comment();
+                self.emit_eof();
+            }
+            State::DOCTYPE | State::BeforeDOCTYPEName => {
+                self.parse_error(ErrorCode::EofInDoctype);
+                self.current_doctype = DOCTYPE {
+                    force_quirks: true,
+                    ..Default::default()
+                };
+                self.emit_current_doctype();
+                self.emit_eof();
+            }
+            State::DOCTYPEName
+            | State::AfterDOCTYPEName
+            | State::AfterDOCTYPEPublicKeyword
+            | State::BeforeDOCTYPEPublicIdentifier
+            | State::DOCTYPEPublicIdentifierDoubleQuoted
+            | State::DOCTYPEPublicIdentifierSingleQuoted
+            | State::AfterDOCTYPEPublicIdentifier
+            | State::BetweenDOCTYPEPublicAndSystemIdentifiers
+            | State::AfterDOCTYPESystemKeyword
+            | State::BeforeDOCTYPESystemIdentifier
+            | State::DOCTYPESystemIdentifierDoubleQuoted
+            | State::DOCTYPESystemIdentifierSingleQuoted
+            | State::AfterDOCTYPESystemIdentifier => {
+                self.parse_error(ErrorCode::EofInDoctype);
+                self.current_doctype.force_quirks = true;
+                self.emit_current_doctype();
+                self.emit_eof();
+            }
+            State::BogusDOCTYPE => {
+                self.emit_current_doctype();
+                self.emit_eof();
+            }
+            State::CDATASection => {
+                self.parse_error(ErrorCode::EofInCdata);
+                self.emit_eof();
+            }
+            State::CDATASectionBracket => {
+                self.emit_chars("]", self.pos - 1);
+                self.switch_to_without_consuming(State::CDATASection);
+            }
+            State::CDATASectionEnd => {
+                self.emit_chars("]]", self.pos - 2);
+                self.switch_to_without_consuming(State::CDATASection);
+            }
+            State::CharacterReference => {
+                self.character_reference_start = self.pos - 1;
+                self.temp_buffer = String::from("&");
+                self.flush_code_points_consumed_as_character_reference();
+                self.switch_to_without_consuming(self.return_state);
+            }
+            State::NamedCharacterReference => {
+                self.flush_code_points_consumed_as_character_reference();
+                self.switch_to_without_consuming(State::AmbiguousAmpersand);
+            }
+            State::AmbiguousAmpersand => {
+                self.switch_to_without_consuming(self.return_state);
+            }
+            State::NumericCharacterReference => {
+                self.character_reference_code = 0;
+                self.switch_to_without_consuming(State::DecimalCharacterReferenceStart);
+            }
+            State::HexadecimalCharacterReferenceStart | State::DecimalCharacterReferenceStart => {
+                self.parse_error(ErrorCode::AbsenceOfDigitsInNumericCharacterReference);
+                self.flush_code_points_consumed_as_character_reference();
+                self.switch_to_without_consuming(self.return_state);
+            }
+            State::HexadecimalCharacterReference | State::DecimalCharacterReference => {
+                self.parse_error(ErrorCode::MissingSemicolonAfterCharacterReference);
+                self.switch_to_without_consuming(State::NumericCharacterReferenceEnd);
+            }
+            State::NumericCharacterReferenceEnd => {
+                self.numeric_character_reference_end();
+            }
+        }
+    }
+
+    fn emit_eof(&mut self) {
+        self.emitted_eof = true;
+        let span = self.span(self.pos, self.pos);
+        self.emit_token(Token::EndOfFile(span));
+    }
+
+    fn numeric_character_reference_end(&mut self) {
+        let code = self.character_reference_code;
+        let c = if code == 0 {
+            self.parse_error(ErrorCode::NullCharacterReference);
+            '\u{FFFD}'
+        } else if code > 0x10FFFF {
+            self.parse_error(ErrorCode::CharacterReferenceOutsideUnicodeRange);
+            '\u{FFFD}'
+        } else if (0xD800..=0xDFFF).contains(&code) {
+            self.parse_error(ErrorCode::SurrogateCharacterReference);
+            '\u{FFFD}'
+        } else {
+            let c = char::from_u32(code).unwrap();
+            if is_noncharacter(c) {
+                self.parse_error(ErrorCode::NoncharacterCharacterReference);
+            } else if code == 0x0D || (is_control(c) && !c.is_ascii_whitespace()) {
+                self.parse_error(ErrorCode::ControlCharacterReference);
+            }
+            entities::numeric_character_reference_replacement(code).unwrap_or(c)
+        };
+        self.temp_buffer = c.to_string();
+        self.flush_code_points_consumed_as_character_reference();
+        self.switch_to_without_consuming(self.return_state);
+    }
+}
+
+/// Pulls tokens one at a time, running the state machine only as far as
+/// needed to produce the next one.
+impl Iterator for Tokenizer<TokenQueue> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        loop {
+            if let Some(token) = self.sink.tokens.pop_front() {
+                return Some(token);
+            }
+            if !self.step() {
+                return None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokenize_in(input: &str, state: State) -> TokenCollector {
+        let mut tokenizer = Tokenizer::new(input.to_string(), TokenCollector::default());
+        tokenizer.set_state(state);
+        tokenizer.run();
+        tokenizer.into_sink()
+    }
+
+    fn tokenize(input: &str) -> TokenCollector {
+        tokenize_in(input, State::Data)
+    }
+
+    /// Tokens written roughly as markup, with runs of characters merged into
+    /// one quoted string.
+    fn describe(tokens: &[Token]) -> Vec<String> {
+        let mut described = Vec::new();
+        let mut characters = String::new();
+        for token in tokens {
+            if let Token::Character(c, _) = token {
+                characters.push(*c);
+                continue;
+            }
+            if !characters.is_empty() {
+                described.push(format!("{:?}", characters));
+                characters.clear();
+            }
+            described.push(match token {
+                Token::DOCTYPE(doctype) => format!(
+                    "<!DOCTYPE {} {:?} {:?}{}>",
+                    doctype.name,
+                    doctype.public_identifier,
+                    doctype.system_identifier,
+                    if doctype.force_quirks { " quirks" } else { "" }
+                ),
+                Token::StartTag(tag) => {
+                    let mut markup = format!("<{}", tag.tag_name);
+                    for attribute in &tag.attributes {
+                        markup.push_str(&format!(" {}={:?}", attribute.name, attribute.value));
                     }
+                    if tag.self_closing {
+                        markup.push_str(" /");
+                    }
+                    markup + ">"
                 }
-                State::AfterDOCTYPEPublicKeyword => todo!(),
-                State::BeforeDOCTYPEPublicIdentifier => todo!(),
-                State::DOCTYPEPublicIdentifierDoubleQuoted => todo!(),
-                State::DOCTYPEPublicIdentifierSingleQuoted => todo!(),
-                State::AfterDOCTYPEPublicIdentifier => todo!(),
-                State::BetweenDOCTYPEPublicAndSystemIdentifiers => todo!(),
-                State::AfterDOCTYPESystemKeyword => todo!(),
-                State::BeforeDOCTYPESystemIdentifier => todo!(),
-                State::DOCTYPESystemIdentifierDoubleQuoted => todo!(),
-                State::DOCTYPESystemIdentifierSingleQuoted => todo!(),
-                State::AfterDOCTYPESystemIdentifier => todo!(),
-                State::BogusDOCTYPE => todo!(),
-                State::CDATASection => todo!(),
-                State::CDATASectionBracket => todo!(),
-                State::CDATASectionEnd => todo!(),
-                State::CharacterReference => todo!(),
-                State::NamedCharacterReference => todo!(),
-                State::AmbiguousAmpersand => todo!(),
-                State::NumericCharacterReference => todo!(),
-                State::HexadecimalCharacterReferenceStart => todo!(),
-                State::DecimalCharacterReferenceStart => todo!(),
-                State::HexadecimalCharacterReference => todo!(),
-                State::DecimalCharacterReference => todo!(),
-                State::NumericCharacterReferenceEnd => todo!(),
-            }
-        }
-
-        self.emit_token(&Token::EndOfFile);
+                Token::EndTag(tag) => format!("</{}>", tag.tag_name),
+                Token::Comment(data, _) => format!("<!--{}-->", data),
+                Token::EndOfFile(_) => "EOF".to_string(),
+                Token::Character(..) => unreachable!(),
+            });
+        }
+        described
+    }
+
+    fn codes(errors: &[ParseError]) -> Vec<ErrorCode> {
+        errors.iter().map(|error| error.code).collect()
+    }
+
+    #[test]
+    fn tokenizes_tags_and_attributes() {
+        let sink = tokenize("<DIV Class='a' id=b hidden data-x=\"1\"><br/></Div>");
+        assert_eq!(
+            describe(&sink.tokens),
+            [
+                r#"<div class="a" id="b" hidden="" data-x="1">"#,
+                "<br />",
+                "</div>",
+                "EOF"
+            ]
+        );
+        assert!(sink.errors.is_empty());
+    }
+
+    #[test]
+    fn drops_duplicate_attributes() {
+        let sink = tokenize("<p a=1 A=2 b>");
+        assert_eq!(describe(&sink.tokens), [r#"<p a="1" b="">"#, "EOF"]);
+        assert_eq!(codes(&sink.errors), [ErrorCode::DuplicateAttribute]);
+    }
+
+    #[test]
+    fn tokenizes_comments() {
+        let sink = tokenize("<!-- a -- b --><!----><?php x?><!-->");
+        assert_eq!(
+            describe(&sink.tokens),
+            [
+                "<!-- a -- b -->",
+                "<!---->",
+                "<!--?php x?-->",
+                "<!---->",
+                "EOF"
+            ]
+        );
+        assert_eq!(
+            codes(&sink.errors),
+            [
+                ErrorCode::UnexpectedQuestionMarkInsteadOfTagName,
+                ErrorCode::AbruptClosingOfEmptyComment
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenizes_rcdata_and_rawtext_content() {
+        let sink = tokenize_in("a&amp;<b></b>", State::RCDATA);
+        assert_eq!(describe(&sink.tokens), ["\"a&<b></b>\"", "EOF"]);
+        let sink = tokenize_in("a&amp;<b>", State::RAWTEXT);
+        assert_eq!(describe(&sink.tokens), ["\"a&amp;<b>\"", "EOF"]);
+        let sink = tokenize_in("</p><p>&amp;", State::PLAINTEXT);
+        assert_eq!(describe(&sink.tokens), ["\"</p><p>&amp;\"", "EOF"]);
+    }
+
+    #[test]
+    fn the_iterator_switches_to_the_content_state_of_elements() {
+        let tokens: Vec<Token> = Tokenizer::new(
+            "<title>a<b>&amp;</title><style>p<q></style>".to_string(),
+            TokenQueue::default(),
+        )
+        .collect();
+        assert_eq!(
+            describe(&tokens),
+            [
+                "<title>",
+                "\"a<b>&\"",
+                "</title>",
+                "<style>",
+                "\"p<q>\"",
+                "</style>",
+                "EOF"
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenizes_escaped_script_data() {
+        let input = "<script>a<!--<script>x</script>y--></script>";
+        let tokens: Vec<Token> = Tokenizer::new(input.to_string(), TokenQueue::default()).collect();
+        assert_eq!(
+            describe(&tokens),
+            [
+                "<script>",
+                "\"a<!--<script>x</script>y-->\"",
+                "</script>",
+                "EOF"
+            ]
+        );
+
+        let sink = tokenize_in("a<!--b</script>c", State::ScriptData);
+        assert_eq!(describe(&sink.tokens), ["\"a<!--b</script>c\"", "EOF"]);
+        let sink = tokenize_in("<!--x", State::ScriptData);
+        assert_eq!(describe(&sink.tokens), ["\"<!--x\"", "EOF"]);
+        assert_eq!(
+            codes(&sink.errors),
+            [ErrorCode::EofInScriptHtmlCommentLikeText]
+        );
+    }
+
+    #[test]
+    fn tokenizes_doctypes() {
+        let sink = tokenize(
+            "<!DOCTYPE html><!doctype HTML PUBLIC \"-//W3C//DTD HTML 4.01//EN\" \
+             'http://www.w3.org/TR/html4/strict.dtd'><!DOCTYPE html SYSTEM ''><!DOCTYPE>",
+        );
+        assert_eq!(
+            describe(&sink.tokens),
+            [
+                "<!DOCTYPE html None None>",
+                r#"<!DOCTYPE html Some("-//W3C//DTD HTML 4.01//EN") Some("http://www.w3.org/TR/html4/strict.dtd")>"#,
+                r#"<!DOCTYPE html None Some("")>"#,
+                "<!DOCTYPE  None None quirks>",
+                "EOF"
+            ]
+        );
+        assert_eq!(codes(&sink.errors), [ErrorCode::MissingDoctypeName]);
+    }
+
+    #[test]
+    fn a_bogus_doctype_forces_quirks() {
+        let sink = tokenize("<!DOCTYPE html PUBLIC x>");
+        assert_eq!(
+            describe(&sink.tokens),
+            ["<!DOCTYPE html None None quirks>", "EOF"]
+        );
+        assert_eq!(
+            codes(&sink.errors),
+            [ErrorCode::MissingQuoteBeforeDoctypePublicIdentifier]
+        );
+    }
+
+    #[test]
+    fn decodes_character_references() {
+        let sink = tokenize("&amp;&lt &#x41;&#66;&notin;&notit;&#0;&#x110000;&#x80;&bogus;&");
+        assert_eq!(
+            describe(&sink.tokens),
+            ["\"&< AB∉¬it;\u{FFFD}\u{FFFD}€&bogus;&\"", "EOF"]
+        );
+        assert_eq!(
+            codes(&sink.errors),
+            [
+                ErrorCode::MissingSemicolonAfterCharacterReference,
+                ErrorCode::MissingSemicolonAfterCharacterReference,
+                ErrorCode::NullCharacterReference,
+                ErrorCode::CharacterReferenceOutsideUnicodeRange,
+                ErrorCode::ControlCharacterReference,
+                ErrorCode::UnknownNamedCharacterReference,
+            ]
+        );
+    }
+
+    #[test]
+    fn leaves_historical_references_followed_by_equals_or_alphanumerics_in_attributes() {
+        let sink = tokenize("<a href='?x=1&copy=2&copy3&copy;&amp=4'>&copy=");
+        assert_eq!(
+            describe(&sink.tokens),
+            [r#"<a href="?x=1&copy=2&copy3©&amp=4">"#, "\"©=\"", "EOF"]
+        );
+        assert_eq!(
+            codes(&sink.errors),
+            [ErrorCode::MissingSemicolonAfterCharacterReference]
+        );
+    }
+
+    #[test]
+    fn normalizes_newlines() {
+        let sink = tokenize("a\r\nb\rc\n");
+        assert_eq!(describe(&sink.tokens), ["\"a\\nb\\nc\\n\"", "EOF"]);
+    }
+
+    #[test]
+    fn reports_null_and_control_characters() {
+        let sink = tokenize("a\0\u{1}");
+        assert_eq!(describe(&sink.tokens), ["\"a\\0\\u{1}\"", "EOF"]);
+        assert_eq!(
+            codes(&sink.errors),
+            [
+                ErrorCode::ControlCharacterInInputStream,
+                ErrorCode::UnexpectedNullCharacter
+            ]
+        );
+    }
+
+    #[test]
+    fn errors_have_positions() {
+        let sink = tokenize("ab\n c</p x>");
+        assert_eq!(sink.errors.len(), 1);
+        assert_eq!(sink.errors[0].code, ErrorCode::EndTagWithAttributes);
+        assert_eq!(sink.errors[0].to_string(), "end-tag-with-attributes at 2:9");
+    }
+
+    #[test]
+    fn reports_malformed_tags_with_spec_error_codes() {
+        let cases = [
+            ("<a b='c'd>", ErrorCode::MissingWhitespaceBetweenAttributes),
+            ("<a =b>", ErrorCode::UnexpectedEqualsSignBeforeAttributeName),
+            ("<a b=>", ErrorCode::MissingAttributeValue),
+            ("<a b\"=c>", ErrorCode::UnexpectedCharacterInAttributeName),
+            (
+                "<a b=c'>",
+                ErrorCode::UnexpectedCharacterInUnquotedAttributeValue,
+            ),
+            ("<a / b>", ErrorCode::UnexpectedSolidusInTag),
+            ("</>", ErrorCode::MissingEndTagName),
+            ("</a/>", ErrorCode::EndTagWithTrailingSolidus),
+            ("<1>", ErrorCode::InvalidFirstCharacterOfTagName),
+            ("</ a>", ErrorCode::InvalidFirstCharacterOfTagName),
+            ("<!a>", ErrorCode::IncorrectlyOpenedComment),
+            ("<!--a--!>", ErrorCode::IncorrectlyClosedComment),
+            ("<!--<!--a-->", ErrorCode::NestedComment),
+        ];
+        for (input, code) in cases {
+            assert_eq!(codes(&tokenize(input).errors), [code], "{}", input);
+        }
+        assert_eq!(ErrorCode::EofInTag.to_string(), "eof-in-tag");
+    }
+
+    fn position(offset: usize, line: usize, column: usize) -> Position {
+        Position {
+            offset,
+            line,
+            column,
+        }
+    }
+
+    #[test]
+    fn tokens_span_the_original_input() {
+        let sink = tokenize("<p class=\"x\">\r\né&amp;</p>");
+        let spans: Vec<Span> = sink.tokens.iter().map(Token::span).collect();
+        // `<p class="x">`, `\r\n`, `é`, `&amp;`, `</p>` and the end of file.
+        assert_eq!(spans[0].start, position(0, 1, 1));
+        assert_eq!(spans[0].end, position(13, 1, 14));
+        assert_eq!(spans[1].start, position(13, 1, 14));
+        assert_eq!(spans[1].end, position(15, 2, 1));
+        assert_eq!(spans[2].start, position(15, 2, 1));
+        assert_eq!(spans[2].end, position(17, 2, 2));
+        assert_eq!(spans[3].start, position(17, 2, 2));
+        assert_eq!(spans[3].end, position(22, 2, 7));
+        assert_eq!(spans[4].start, position(22, 2, 7));
+        assert_eq!(spans[5].start, position(26, 2, 11));
+
+        let tag = match &sink.tokens[0] {
+            Token::StartTag(tag) => tag,
+            token => panic!("{:?}", token),
+        };
+        let attribute = &tag.attributes[0];
+        assert_eq!(attribute.name_span.start, position(3, 1, 4));
+        assert_eq!(attribute.name_span.end, position(8, 1, 9));
+        assert_eq!(attribute.value_span.start, position(10, 1, 11));
+        assert_eq!(attribute.value_span.end, position(11, 1, 12));
+    }
+
+    #[test]
+    fn cdata_is_a_bogus_comment_in_html_content() {
+        let sink = tokenize("<![CDATA[x]]>");
+        assert_eq!(describe(&sink.tokens), ["<!--[CDATA[x]]-->", "EOF"]);
+        assert_eq!(codes(&sink.errors), [ErrorCode::CdataInHtmlContent]);
+    }
+
+    /// Collects tokens as if the current node were an SVG element.
+    #[derive(Default)]
+    struct ForeignCollector(TokenCollector);
+
+    impl TokenSink for ForeignCollector {
+        fn process_token(&mut self, token: Token) -> TokenSinkResult {
+            self.0.process_token(token)
+        }
+
+        fn parse_error(&mut self, error: ParseError) {
+            self.0.parse_error(error)
+        }
+
+        fn adjusted_current_node_present_but_not_in_html_namespace(&self) -> bool {
+            true
+        }
+    }
+
+    fn tokenize_foreign(input: &str) -> TokenCollector {
+        let mut tokenizer = Tokenizer::new(input.to_string(), ForeignCollector::default());
+        tokenizer.run();
+        tokenizer.into_sink().0
+    }
+
+    #[test]
+    fn tokenizes_cdata_sections_in_foreign_content() {
+        let sink = tokenize_foreign("<![CDATA[a<b>&amp;]]]x]]>c");
+        assert_eq!(describe(&sink.tokens), ["\"a<b>&amp;]]]xc\"", "EOF"]);
+        assert!(sink.errors.is_empty());
+
+        let sink = tokenize_foreign("<![CDATA[a]");
+        assert_eq!(describe(&sink.tokens), ["\"a]\"", "EOF"]);
+        assert_eq!(codes(&sink.errors), [ErrorCode::EofInCdata]);
+    }
+
+    #[test]
+    fn reports_the_end_of_the_input_in_every_construct() {
+        let cases: &[(&str, &[&str], ErrorCode)] = &[
+            ("<div a='", &["EOF"], ErrorCode::EofInTag),
+            ("<", &["\"<\"", "EOF"], ErrorCode::EofBeforeTagName),
+            ("</", &["\"</\"", "EOF"], ErrorCode::EofBeforeTagName),
+            ("<!--x", &["<!--x-->", "EOF"], ErrorCode::EofInComment),
+            (
+                "<!DOCTYPE html",
+                &["<!DOCTYPE html None None quirks>", "EOF"],
+                ErrorCode::EofInDoctype,
+            ),
+        ];
+        for (input, tokens, code) in cases {
+            let sink = tokenize(input);
+            assert_eq!(describe(&sink.tokens), *tokens, "{}", input);
+            assert_eq!(codes(&sink.errors), [*code], "{}", input);
+        }
     }
 }
@@ -0,0 +1,132 @@
+//! Runs the tree construction fixtures in `tests/fixtures/tree_construction`,
+//! which use the html5lib-tests format: each test has the input under
+//! `#data`, the parse errors under `#errors` as `(line,column): code`, an
+//! optional `#document-fragment` context element such as `td` or `svg path`,
+//! an optional `#script-on` for parsing with scripting enabled, and the
+//! expected tree under `#document` as the [`Document`]'s `Display` output.
+
+use my_html_parser::node::{Document, Namespace};
+use my_html_parser::parser::Parser;
+use std::fs;
+use std::path::Path;
+
+struct Test {
+    data: String,
+    errors: Vec<String>,
+    fragment_context: Option<String>,
+    scripting: bool,
+    document: String,
+}
+
+fn parse_tests(fixture: &str) -> Vec<Test> {
+    let mut tests = Vec::new();
+    for chunk in fixture.split("#data\n").skip(1) {
+        let mut sections: Vec<(&str, Vec<&str>)> = Vec::new();
+        let mut lines = chunk.lines();
+        sections.push(("#data", Vec::new()));
+        for line in &mut lines {
+            if is_section(line) {
+                sections.push((line, Vec::new()));
+            } else {
+                sections.last_mut().unwrap().1.push(line);
+            }
+        }
+        let section = |name: &str| {
+            sections
+                .iter()
+                .find(|(section, _)| *section == name)
+                .map(|(_, lines)| lines.clone())
+        };
+        let mut document = section("#document").expect("every test has a #document");
+        // Tests are separated by a blank line.
+        if document.last() == Some(&"") {
+            document.pop();
+        }
+        tests.push(Test {
+            data: section("#data").unwrap().join("\n"),
+            errors: section("#errors")
+                .unwrap_or_default()
+                .into_iter()
+                .map(str::to_string)
+                .collect(),
+            fragment_context: section("#document-fragment").map(|lines| lines.join("")),
+            scripting: section("#script-on").is_some(),
+            document: document.iter().map(|line| format!("{}\n", line)).collect(),
+        });
+    }
+    tests
+}
+
+fn is_section(line: &str) -> bool {
+    matches!(
+        line,
+        "#errors" | "#document-fragment" | "#script-on" | "#script-off" | "#document"
+    )
+}
+
+fn run(test: &Test) -> Parser {
+    let mut parser = Parser::new();
+    parser.set_scripting(test.scripting);
+    match &test.fragment_context {
+        Some(context) => {
+            let (namespace, local_name) = match context.split_once(' ') {
+                Some(("svg", local_name)) => (Namespace::SVG, local_name),
+                Some(("math", local_name)) => (Namespace::MathML, local_name),
+                _ => (Namespace::HTML, context.as_str()),
+            };
+            let mut document = Document::new();
+            let context = document.create_element(local_name.to_string(), namespace, Vec::new());
+            parser.parse_fragment(&document, context, test.data.clone())
+        }
+        None => parser.parse_document(test.data.clone()),
+    }
+}
+
+#[test]
+fn tree_construction() {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tree_construction");
+    let mut paths: Vec<_> = fs::read_dir(directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "dat"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty());
+
+    let mut failures = Vec::new();
+    let mut count = 0;
+    for path in paths {
+        let fixture = fs::read_to_string(&path).unwrap();
+        for test in parse_tests(&fixture) {
+            count += 1;
+            let parser = run(&test);
+            let document = parser.document().to_string();
+            let errors: Vec<String> = parser
+                .errors()
+                .iter()
+                .map(|error| {
+                    format!(
+                        "({},{}): {}",
+                        error.position.line, error.position.column, error.code
+                    )
+                })
+                .collect();
+            if document != test.document || errors != test.errors {
+                failures.push(format!(
+                    "{}\n#data\n{}\n#errors\n{}\n#document\n{}",
+                    path.file_name().unwrap().to_string_lossy(),
+                    test.data,
+                    errors.join("\n"),
+                    document
+                ));
+            }
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "{} of {} tests failed:\n\n{}",
+        failures.len(),
+        count,
+        failures.join("\n")
+    );
+}